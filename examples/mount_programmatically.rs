@@ -0,0 +1,16 @@
+//! Mounts a bucket using the library API instead of the `aegisfs` CLI.
+//!
+//! Run with: `cargo run --example mount_programmatically -- aegisfs.toml /mnt/aegisfs`
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let mut args = std::env::args().skip(1);
+    let config_path = args.next().expect("usage: mount_programmatically <config> <mountpoint>");
+    let mountpoint = args.next().expect("usage: mount_programmatically <config> <mountpoint>");
+
+    let config = aegisfs::Config::load(&config_path)?;
+    let fs = aegisfs::builder::build(&config).await?;
+
+    tokio::task::spawn_blocking(move || fuser::mount2(fs, &mountpoint, &[])).await??;
+    Ok(())
+}
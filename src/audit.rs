@@ -0,0 +1,116 @@
+//! Opt-in compliance access trail: who (uid/gid/pid) did what to which
+//! path, and whether it succeeded, for every FUSE op [`crate::metrics`]
+//! already counts.
+//!
+//! [`AuditLog`] is deliberately the thinnest thing that can satisfy
+//! "who accessed which files when": one JSON object per line, appended
+//! to a file, with no buffering or background thread of its own —
+//! unlike [`crate::metrics::Metrics`], which is cheap enough to always
+//! collect, a line of I/O per op is not something every mount should
+//! pay for, so this only exists at all when `config::AuditConfig::enabled`
+//! is set. Writing anywhere other than a local file (e.g. real syslog)
+//! would need a new dependency this crate doesn't carry; callers who
+//! need that can ship the JSON lines onward with `logger`/`syslog-ng`
+//! watching the file, which is also why each line is self-contained
+//! JSON rather than some bespoke format.
+
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+
+/// One audit event, serialized as a single JSON line. Kept separate
+/// from any of `metadata::FileMetadata`'s types since it describes an
+/// access, not a file.
+#[derive(Debug, Clone, Serialize)]
+struct AuditEvent<'a> {
+    /// Seconds since the Unix epoch. Not a human-readable timestamp,
+    /// to avoid pulling in a datetime-formatting dependency for a log
+    /// meant to be machine-parsed.
+    timestamp: u64,
+    uid: u32,
+    gid: u32,
+    pid: u32,
+    op: &'a str,
+    path: &'a str,
+    success: bool,
+}
+
+/// An append-only destination for audit events, opened once at mount
+/// time and written to from every instrumented `fuser::Filesystem`
+/// callback. The file is reopened in append mode on every mount rather
+/// than truncated, so restarting a mount doesn't lose the prior trail.
+pub struct AuditLog {
+    file: Mutex<std::fs::File>,
+}
+
+impl AuditLog {
+    /// Opens (creating if necessary) `path` for appending.
+    pub fn open(path: &str) -> std::io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(AuditLog { file: Mutex::new(file) })
+    }
+
+    /// Appends one event. Best-effort: a write failure here (e.g. a
+    /// full disk) is logged to stderr rather than surfaced to the FUSE
+    /// caller, since a compliance trail going briefly unwritable isn't
+    /// a reason to fail the filesystem operation it's recording.
+    pub fn record(&self, uid: u32, gid: u32, pid: u32, op: &str, path: &str, success: bool) {
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+        let event = AuditEvent { timestamp, uid, gid, pid, op, path, success };
+        let line = match serde_json::to_string(&event) {
+            Ok(line) => line,
+            Err(e) => {
+                eprintln!("audit: failed to serialize event: {}", e);
+                return;
+            }
+        };
+        let mut file = self.file.lock().unwrap();
+        if let Err(e) = writeln!(file, "{}", line) {
+            eprintln!("audit: failed to write event: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn record_appends_one_json_line_per_event() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("audit.log");
+        let log = AuditLog::open(path.to_str().unwrap()).unwrap();
+
+        log.record(1000, 1000, 42, "read", "docs/readme.txt", true);
+        log.record(0, 0, 7, "unlink", "secret.txt", false);
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<&str> = contents.lines().collect();
+        assert_eq!(lines.len(), 2);
+
+        let first: serde_json::Value = serde_json::from_str(lines[0]).unwrap();
+        assert_eq!(first["uid"], 1000);
+        assert_eq!(first["op"], "read");
+        assert_eq!(first["path"], "docs/readme.txt");
+        assert_eq!(first["success"], true);
+
+        let second: serde_json::Value = serde_json::from_str(lines[1]).unwrap();
+        assert_eq!(second["pid"], 7);
+        assert_eq!(second["success"], false);
+    }
+
+    #[test]
+    fn reopening_the_same_path_appends_instead_of_truncating() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("audit.log");
+
+        AuditLog::open(path.to_str().unwrap()).unwrap().record(0, 0, 1, "open", "a.txt", true);
+        AuditLog::open(path.to_str().unwrap()).unwrap().record(0, 0, 1, "open", "b.txt", true);
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(contents.lines().count(), 2);
+    }
+}
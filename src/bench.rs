@@ -0,0 +1,160 @@
+//! Offline throughput/latency benchmark against the configured backend
+//! and encryptor, bypassing FUSE entirely.
+//!
+//! The overhead FUSE adds (kernel round-trips, attribute caching) is
+//! fixed and not something a block-size/cache/concurrency tweak can
+//! change, so it would only add noise to a benchmark meant to compare
+//! *those* settings. [`run`] drives [`crate::blockstore`] directly, the
+//! same primitives `AegisFS::read`/`write` call into once the FUSE
+//! plumbing has already resolved a path to a storage key.
+
+use std::time::{Duration, Instant};
+
+use rand::seq::SliceRandom;
+use serde::Serialize;
+
+use crate::blockstore;
+use crate::encryption::Encryptor;
+use crate::error::Result;
+use crate::storage::StorageBackend;
+
+/// Throughput and tail latency for one phase of [`run`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct PhaseStats {
+    pub mb_per_sec: f64,
+    pub p50_micros: u64,
+    pub p99_micros: u64,
+}
+
+impl PhaseStats {
+    /// `total_bytes` moved across `durations` (one entry per op),
+    /// measured over their combined wall-clock time, plus the p50/p99
+    /// of the individual op latencies.
+    fn from_samples(total_bytes: u64, mut durations: Vec<Duration>) -> Self {
+        if durations.is_empty() {
+            return Self::default();
+        }
+        durations.sort_unstable();
+        let total_secs: f64 = durations.iter().map(Duration::as_secs_f64).sum();
+        let mb_per_sec = if total_secs > 0.0 { (total_bytes as f64 / (1024.0 * 1024.0)) / total_secs } else { 0.0 };
+
+        PhaseStats {
+            mb_per_sec,
+            p50_micros: percentile(&durations, 0.50).as_micros() as u64,
+            p99_micros: percentile(&durations, 0.99).as_micros() as u64,
+        }
+    }
+}
+
+/// `durations[p * (len - 1)]`, rounded to the nearest index. Good
+/// enough for the sample sizes a benchmark run produces; not a
+/// substitute for a real histogram under heavy statistical load.
+fn percentile(sorted: &[Duration], p: f64) -> Duration {
+    let index = ((sorted.len() - 1) as f64 * p).round() as usize;
+    sorted[index]
+}
+
+/// Result of [`run`]: one [`PhaseStats`] per phase, in the order they
+/// ran.
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct BenchReport {
+    pub write: PhaseStats,
+    pub read_sequential: PhaseStats,
+    pub read_random: PhaseStats,
+    pub delete: PhaseStats,
+}
+
+/// Writes `iterations` synthetic files of `file_size` bytes each under
+/// `prefix`, reads them back sequentially, reads them back again in a
+/// shuffled order, then deletes all of them, timing every individual
+/// op. Leaves nothing behind in `prefix` on success — the delete phase
+/// cleans up everything the earlier phases created.
+pub async fn run<B: StorageBackend>(
+    backend: &B,
+    encryptor: &Encryptor,
+    prefix: &str,
+    file_size: u64,
+    iterations: usize,
+) -> Result<BenchReport> {
+    let keys: Vec<String> = (0..iterations).map(|i| format!("{}bench-{:06}", prefix, i)).collect();
+    let payload = vec![0xABu8; file_size as usize];
+    let total_bytes = file_size * iterations as u64;
+
+    let mut write_durations = Vec::with_capacity(iterations);
+    for key in &keys {
+        let started = Instant::now();
+        // Compression is deliberately off here regardless of the
+        // caller's real config: it would measure zstd's CPU cost (and,
+        // on this constant-byte payload, an unrealistic ratio) instead
+        // of the storage+encryption throughput this benchmark isolates.
+        blockstore::write_range(backend, encryptor, key, 0, &payload, false, 0).await?;
+        write_durations.push(started.elapsed());
+    }
+
+    let mut read_sequential_durations = Vec::with_capacity(iterations);
+    for key in &keys {
+        let started = Instant::now();
+        blockstore::read_range(backend, encryptor, key, 0, file_size).await?;
+        read_sequential_durations.push(started.elapsed());
+    }
+
+    let mut shuffled_keys = keys.clone();
+    shuffled_keys.shuffle(&mut rand::thread_rng());
+    let mut read_random_durations = Vec::with_capacity(iterations);
+    for key in &shuffled_keys {
+        let started = Instant::now();
+        blockstore::read_range(backend, encryptor, key, 0, file_size).await?;
+        read_random_durations.push(started.elapsed());
+    }
+
+    let mut delete_durations = Vec::with_capacity(iterations);
+    for key in &keys {
+        let started = Instant::now();
+        blockstore::delete_all(backend, key).await?;
+        delete_durations.push(started.elapsed());
+    }
+
+    Ok(BenchReport {
+        write: PhaseStats::from_samples(total_bytes, write_durations),
+        read_sequential: PhaseStats::from_samples(total_bytes, read_sequential_durations),
+        read_random: PhaseStats::from_samples(total_bytes, read_random_durations),
+        delete: PhaseStats::from_samples(0, delete_durations),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryStorage;
+
+    #[tokio::test]
+    async fn runs_every_phase_and_leaves_nothing_behind() {
+        let backend = MemoryStorage::new();
+        let enc = Encryptor::new(&[9u8; 32]);
+
+        let report = run(&backend, &enc, "", 4096, 5).await.unwrap();
+
+        assert!(report.write.mb_per_sec > 0.0);
+        assert!(report.read_sequential.mb_per_sec > 0.0);
+        assert!(report.read_random.mb_per_sec > 0.0);
+        assert_eq!(backend.list("").await.unwrap().len(), 0);
+    }
+
+    #[tokio::test]
+    async fn respects_a_nonempty_prefix() {
+        let backend = MemoryStorage::new();
+        let enc = Encryptor::new(&[10u8; 32]);
+
+        run(&backend, &enc, "bench/", 1024, 3).await.unwrap();
+
+        assert_eq!(backend.list("bench/").await.unwrap().len(), 0);
+        assert_eq!(backend.list("").await.unwrap().len(), 0);
+    }
+
+    #[test]
+    fn percentile_picks_the_nearest_ranked_sample() {
+        let samples: Vec<Duration> = (1..=10).map(Duration::from_millis).collect();
+        assert_eq!(percentile(&samples, 0.0), Duration::from_millis(1));
+        assert_eq!(percentile(&samples, 1.0), Duration::from_millis(10));
+    }
+}
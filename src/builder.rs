@@ -0,0 +1,283 @@
+use std::sync::Arc;
+
+use crate::audit::AuditLog;
+use crate::config::Config;
+use crate::encryption::Encryptor;
+use crate::error::{AegisError, Result};
+use crate::fs::AegisFS;
+use crate::journal::Journal;
+use crate::metrics::Metrics;
+use crate::pathcrypt::PathCrypt;
+use crate::storage::{CachingStorage, GcsStorage, LocalStorage, S3Storage, StorageBackend};
+
+/// Builds a mountable [`AegisFS`] from a [`Config`], so embedders don't
+/// have to hand-wire the encryption/storage/FS layers together the way
+/// `main.rs` does.
+///
+/// ```no_run
+/// # async fn example() -> aegisfs::Result<()> {
+/// let config = aegisfs::Config::load("aegisfs.toml")?;
+/// let fs = aegisfs::builder::build(&config).await?;
+/// fuser::mount2(fs, "/mnt/aegisfs", &[])?;
+/// # Ok(())
+/// # }
+/// ```
+pub async fn build(config: &Config) -> Result<AegisFS> {
+    // Shared with the storage backend below (where it supports metrics
+    // at all) so `AegisFS::metrics` reports op counts, backend
+    // requests, and cache hits/misses through the same instance.
+    let metrics = Arc::new(Metrics::default());
+
+    let (storage, prefix): (Arc<dyn StorageBackend>, String) = match (&config.local, &config.gcs) {
+        (Some(local), _) => (Arc::new(LocalStorage::new(local.root.clone())), config.s3.prefix.clone().unwrap_or_default()),
+        (None, Some(gcs)) => (Arc::new(GcsStorage::new(gcs).await?), gcs.prefix.clone().unwrap_or_default()),
+        (None, None) => (
+            Arc::new(S3Storage::new(&config.s3).await?.with_metrics(Arc::clone(&metrics))),
+            config.s3.prefix.clone().unwrap_or_default(),
+        ),
+    };
+    let storage: Arc<dyn StorageBackend> = if config.cache.enabled {
+        Arc::new(CachingStorage::new(storage, config.cache.max_entries).with_metrics(Arc::clone(&metrics)))
+    } else {
+        storage
+    };
+
+    let key = crate::kms::resolve_master_key(&config.encryption, config.kms.as_ref()).await?;
+    let encryptor = Encryptor::with_algorithm(&key, config.encryption.algorithm);
+    let sentinel_key = AegisFS::storage_key_with_prefix(&prefix, None, &config.encryption.key_sentinel);
+    crate::keystore::check_or_initialize(storage.as_ref(), &sentinel_key, &key, config.encryption.algorithm).await?;
+    let path_crypt = config.encryption.encrypt_filenames.then(|| PathCrypt::new(encryptor.key()));
+    let audit = config
+        .audit
+        .enabled
+        .then(|| config.audit.log_path.as_deref())
+        .flatten()
+        .map(AuditLog::open)
+        .transpose()
+        .map_err(|e| AegisError::Config(format!("opening audit.log_path: {}", e)))?
+        .map(Arc::new);
+    let journal = config
+        .journal
+        .enabled
+        .then(|| config.journal.dir.as_deref())
+        .flatten()
+        .map(Journal::open)
+        .transpose()
+        .map_err(|e| AegisError::Config(format!("opening journal.dir: {}", e)))?
+        .map(Arc::new);
+    Ok(AegisFS::with_journal(
+        storage,
+        encryptor,
+        config.directory_mode.into(),
+        prefix,
+        config.consistency_mode,
+        config.cache.max_open_handles,
+        path_crypt,
+        metrics,
+        config.read_only,
+        std::time::Duration::from_secs(config.cache.attr_ttl_secs),
+        std::time::Duration::from_secs(config.cache.entry_ttl_secs),
+        config.compression.clone(),
+        config.dedup.clone(),
+        config.block_size,
+        audit,
+        journal,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{CacheConfig, DirectoryMode, EncryptionConfig, S3Config};
+    use std::io::Write;
+
+    #[tokio::test]
+    async fn builds_aegisfs_from_config_without_the_cli() {
+        let mut key_file = tempfile::NamedTempFile::new().unwrap();
+        writeln!(key_file, "a test passphrase").unwrap();
+
+        let config = Config {
+            s3: S3Config {
+                bucket: "test-bucket".into(),
+                region: "us-east-1".into(),
+                endpoint: None,
+                access_key: None,
+                secret_key: None,
+                read_endpoint: None,
+                restore_archived_objects: false,
+                profile: None,
+                role_arn: None,
+                ca_cert_path: None,
+                prefix: None,
+                danger_accept_invalid_certs: false,
+                max_upload_bytes_per_sec: None,
+                max_download_bytes_per_sec: None,
+                multipart_threshold_bytes: 16 * 1024 * 1024,
+                multipart_part_size_bytes: 8 * 1024 * 1024,
+                max_concurrent_requests: None,
+                max_idle_connections_per_host: None,
+                server_side_encryption: None,
+                sse_kms_key_id: None,
+                connect_timeout_secs: 10,
+                read_timeout_secs: 30,
+            },
+            local: None,
+            gcs: None,
+            kms: None,
+            encryption: EncryptionConfig {
+                key_path: key_file.path().to_str().unwrap().to_string(),
+                algorithm: Default::default(),
+                salt: "a-stable-test-salt".into(),
+                encrypt_filenames: false,
+                key_sentinel: ".aegisfs-key-sentinel".into(),
+            },
+            cache: CacheConfig::default(),
+            metrics: Default::default(),
+            compression: Default::default(),
+            dedup: Default::default(),
+            audit: Default::default(),
+            journal: Default::default(),
+            directory_mode: DirectoryMode::Marker,
+            consistency_mode: Default::default(),
+            block_size: crate::blockstore::BLOCK_SIZE,
+            read_only: false,
+            profiles: Default::default(),
+        };
+
+        assert!(build(&config).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn builds_aegisfs_with_a_local_backend_and_no_network_access() {
+        let mut key_file = tempfile::NamedTempFile::new().unwrap();
+        writeln!(key_file, "a test passphrase").unwrap();
+        let storage_dir = tempfile::tempdir().unwrap();
+
+        let config = Config {
+            s3: S3Config {
+                bucket: "unused".into(),
+                region: "unused".into(),
+                endpoint: None,
+                access_key: None,
+                secret_key: None,
+                read_endpoint: None,
+                restore_archived_objects: false,
+                profile: None,
+                role_arn: None,
+                ca_cert_path: None,
+                prefix: None,
+                danger_accept_invalid_certs: false,
+                max_upload_bytes_per_sec: None,
+                max_download_bytes_per_sec: None,
+                multipart_threshold_bytes: 16 * 1024 * 1024,
+                multipart_part_size_bytes: 8 * 1024 * 1024,
+                max_concurrent_requests: None,
+                max_idle_connections_per_host: None,
+                server_side_encryption: None,
+                sse_kms_key_id: None,
+                connect_timeout_secs: 10,
+                read_timeout_secs: 30,
+            },
+            local: Some(crate::config::LocalConfig { root: storage_dir.path().to_str().unwrap().to_string() }),
+            gcs: None,
+            kms: None,
+            encryption: EncryptionConfig {
+                key_path: key_file.path().to_str().unwrap().to_string(),
+                algorithm: Default::default(),
+                salt: "a-stable-test-salt".into(),
+                encrypt_filenames: false,
+                key_sentinel: ".aegisfs-key-sentinel".into(),
+            },
+            cache: CacheConfig::default(),
+            metrics: Default::default(),
+            compression: Default::default(),
+            dedup: Default::default(),
+            audit: Default::default(),
+            journal: Default::default(),
+            directory_mode: DirectoryMode::Marker,
+            consistency_mode: Default::default(),
+            block_size: crate::blockstore::BLOCK_SIZE,
+            read_only: false,
+            profiles: Default::default(),
+        };
+
+        assert!(build(&config).await.is_ok());
+    }
+
+    fn local_config(key_path: String, storage_root: String) -> Config {
+        Config {
+            s3: S3Config {
+                bucket: "unused".into(),
+                region: "unused".into(),
+                endpoint: None,
+                access_key: None,
+                secret_key: None,
+                read_endpoint: None,
+                restore_archived_objects: false,
+                profile: None,
+                role_arn: None,
+                ca_cert_path: None,
+                prefix: None,
+                danger_accept_invalid_certs: false,
+                max_upload_bytes_per_sec: None,
+                max_download_bytes_per_sec: None,
+                multipart_threshold_bytes: 16 * 1024 * 1024,
+                multipart_part_size_bytes: 8 * 1024 * 1024,
+                max_concurrent_requests: None,
+                max_idle_connections_per_host: None,
+                server_side_encryption: None,
+                sse_kms_key_id: None,
+                connect_timeout_secs: 10,
+                read_timeout_secs: 30,
+            },
+            local: Some(crate::config::LocalConfig { root: storage_root }),
+            gcs: None,
+            kms: None,
+            encryption: EncryptionConfig {
+                key_path,
+                algorithm: Default::default(),
+                salt: "a-stable-test-salt".into(),
+                encrypt_filenames: false,
+                key_sentinel: ".aegisfs-key-sentinel".into(),
+            },
+            cache: CacheConfig::default(),
+            metrics: Default::default(),
+            compression: Default::default(),
+            dedup: Default::default(),
+            audit: Default::default(),
+            journal: Default::default(),
+            directory_mode: DirectoryMode::Marker,
+            consistency_mode: Default::default(),
+            block_size: crate::blockstore::BLOCK_SIZE,
+            read_only: false,
+            profiles: Default::default(),
+        }
+    }
+
+    #[tokio::test]
+    async fn a_second_mount_with_the_same_key_succeeds_off_the_sentinel_already_written() {
+        let mut key_file = tempfile::NamedTempFile::new().unwrap();
+        writeln!(key_file, "a test passphrase").unwrap();
+        let storage_dir = tempfile::tempdir().unwrap();
+        let config = local_config(key_file.path().to_str().unwrap().to_string(), storage_dir.path().to_str().unwrap().to_string());
+
+        assert!(build(&config).await.is_ok());
+        assert!(build(&config).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn a_mount_with_the_wrong_key_is_rejected_instead_of_surfacing_later_as_a_read_failure() {
+        let mut key_file = tempfile::NamedTempFile::new().unwrap();
+        writeln!(key_file, "the original passphrase").unwrap();
+        let storage_dir = tempfile::tempdir().unwrap();
+        let config = local_config(key_file.path().to_str().unwrap().to_string(), storage_dir.path().to_str().unwrap().to_string());
+        build(&config).await.unwrap();
+
+        let mut wrong_key_file = tempfile::NamedTempFile::new().unwrap();
+        writeln!(wrong_key_file, "a completely different passphrase").unwrap();
+        let mismatched = local_config(wrong_key_file.path().to_str().unwrap().to_string(), storage_dir.path().to_str().unwrap().to_string());
+
+        let err = build(&mismatched).await.unwrap_err();
+        assert!(matches!(err, AegisError::KeyMismatch(_)));
+    }
+}
@@ -0,0 +1,104 @@
+//! Optional zstd compression of pre-encryption block plaintext, to
+//! shrink storage and transfer costs for compressible content.
+//!
+//! Applied after [`crate::sparse`]'s zero-run framing and before
+//! encryption, and reversed in the opposite order after decryption, so
+//! ciphertext length tracks compressed size rather than logical size.
+//! Each block that opts in self-describes with a leading tag byte
+//! recording whether it actually ended up compressed, so [`decode`]
+//! never needs to be told ahead of time and a bucket can freely mix
+//! compressed and incompressible blocks. Blocks from a file that never
+//! opted in have no tag byte at all — see
+//! `blockstore::Header::blocks_compressed` — so turning this on can
+//! never change how an existing file's blocks are framed.
+//!
+//! Opt-in rather than on by default: compressing plaintext before
+//! encrypting it can leak information about the plaintext through the
+//! resulting ciphertext's length (a CRIME/BREACH-style side channel),
+//! which matters for some threat models and not others.
+
+use crate::codec::Codec;
+use crate::error::{AegisError, Result};
+
+const TAG_RAW: u8 = 0;
+const TAG_ZSTD: u8 = 1;
+
+/// Compresses `data` at `level` and tags it [`TAG_ZSTD`], unless the
+/// result isn't actually smaller than `data`, in which case `data` is
+/// stored as-is under [`TAG_RAW`] instead of paying for compression
+/// that didn't help.
+pub fn encode(data: &[u8], level: i32) -> Vec<u8> {
+    if let Ok(compressed) = zstd::stream::encode_all(data, level) {
+        if compressed.len() < data.len() {
+            let mut out = Vec::with_capacity(compressed.len() + 1);
+            out.push(TAG_ZSTD);
+            out.extend_from_slice(&compressed);
+            return out;
+        }
+    }
+    let mut out = Vec::with_capacity(data.len() + 1);
+    out.push(TAG_RAW);
+    out.extend_from_slice(data);
+    out
+}
+
+/// Reverses [`encode`], reading the leading tag byte to decide whether
+/// the rest needs decompressing.
+pub fn decode(data: &[u8]) -> Result<Vec<u8>> {
+    match data.split_first() {
+        Some((&TAG_RAW, rest)) => Ok(rest.to_vec()),
+        Some((&TAG_ZSTD, rest)) => zstd::stream::decode_all(rest)
+            .map_err(|e| AegisError::Encryption(format!("decompressing block: {}", e))),
+        Some((tag, _)) => Err(AegisError::Encryption(format!("unknown compression tag {}", tag))),
+        None => Ok(Vec::new()),
+    }
+}
+
+/// [`Codec`] wrapper around [`encode`]/[`decode`], so [`crate::blockstore`]
+/// can compose zstd compression into a [`crate::codec::Pipeline`]
+/// alongside [`crate::encryption::Encryptor`] instead of calling the
+/// free functions directly.
+pub struct ZstdCodec {
+    pub level: i32,
+}
+
+impl Codec for ZstdCodec {
+    fn encode(&self, _path: &str, plaintext: &[u8]) -> Result<Vec<u8>> {
+        Ok(encode(plaintext, self.level))
+    }
+
+    fn decode(&self, _path: &str, stored: &[u8]) -> Result<Vec<u8>> {
+        decode(stored)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_compressible_data_as_zstd() {
+        let data = b"aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa".to_vec();
+        let encoded = encode(&data, 3);
+        assert_eq!(encoded[0], TAG_ZSTD);
+        assert!(encoded.len() < data.len());
+        assert_eq!(decode(&encoded).unwrap(), data);
+    }
+
+    #[test]
+    fn falls_back_to_raw_for_data_compression_cannot_shrink() {
+        use rand::RngCore;
+        // High-entropy random bytes: zstd can't beat storing them as-is
+        // once its own framing overhead is counted.
+        let mut data = vec![0u8; 4096];
+        rand::rngs::OsRng.fill_bytes(&mut data);
+        let encoded = encode(&data, 3);
+        assert_eq!(encoded[0], TAG_RAW);
+        assert_eq!(decode(&encoded).unwrap(), data);
+    }
+
+    #[test]
+    fn empty_input_round_trips() {
+        assert_eq!(decode(&encode(&[], 3)).unwrap(), Vec::<u8>::new());
+    }
+}
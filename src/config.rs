@@ -0,0 +1,1397 @@
+use serde::{Deserialize, Serialize};
+
+use crate::encryption::EncryptionAlgorithm;
+use crate::error::{AegisError, Result};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct S3Config {
+    pub bucket: String,
+    pub region: String,
+    pub endpoint: Option<String>,
+    pub access_key: Option<String>,
+    pub secret_key: Option<String>,
+    /// Alternate endpoint `S3Storage::get`/`list`/`list_with_delimiter`/
+    /// `head` talk to instead of the primary — a geographically closer
+    /// read replica or a CDN-backed endpoint, say — while `put`/`delete`
+    /// always go to the primary. Falls back to the primary on a read
+    /// failure, so a stale or unreachable replica degrades to normal
+    /// latency rather than failing the request. `None` (the default)
+    /// reads from the primary too.
+    #[serde(default)]
+    pub read_endpoint: Option<String>,
+    /// Named profile (`~/.aws/config`/`~/.aws/credentials`) to resolve
+    /// credentials from instead of the default chain — an SSO-login
+    /// profile, for example.
+    #[serde(default)]
+    pub profile: Option<String>,
+    /// ARN of an IAM role to assume via STS before talking to the
+    /// bucket, layered on top of whatever `profile` or the default
+    /// chain resolves as the base credentials. The assumed session is
+    /// refreshed automatically as it nears expiry, rather than cached
+    /// once for the life of the mount.
+    #[serde(default)]
+    pub role_arn: Option<String>,
+    /// Path to a PEM-encoded CA bundle to trust in addition to the
+    /// system store, for MinIO/self-signed endpoints.
+    #[serde(default)]
+    pub ca_cert_path: Option<String>,
+    /// Object-key prefix to root this mount at, e.g. `"tenants/acme"`
+    /// to expose only that slice of a shared bucket. Overridable at
+    /// mount time with `--prefix`.
+    #[serde(default)]
+    pub prefix: Option<String>,
+    /// Skip TLS certificate verification entirely. Dangerous: only for
+    /// local development against an endpoint you can't otherwise get a
+    /// trusted cert for. Never enable this against a real bucket.
+    #[serde(default)]
+    pub danger_accept_invalid_certs: bool,
+    /// Caps outbound (`put`/`append`) bandwidth to the backend, in
+    /// bytes/sec. `None` (the default) is unlimited.
+    #[serde(default)]
+    pub max_upload_bytes_per_sec: Option<u64>,
+    /// Caps inbound (`get`) bandwidth from the backend, in bytes/sec.
+    /// `None` (the default) is unlimited.
+    #[serde(default)]
+    pub max_download_bytes_per_sec: Option<u64>,
+    /// Object size, in bytes, at or above which `S3Storage::put` uses a
+    /// multipart upload instead of a single `PutObject`. `PutObject`
+    /// caps out at 5 GiB, so anything larger must go through multipart
+    /// regardless of this setting.
+    #[serde(default = "default_multipart_threshold_bytes")]
+    pub multipart_threshold_bytes: u64,
+    /// Size of each part in a multipart upload, in bytes. S3 requires
+    /// every part but the last to be at least 5 MiB.
+    #[serde(default = "default_multipart_part_size_bytes")]
+    pub multipart_part_size_bytes: u64,
+    /// Caps how many requests `S3Storage` has in flight against the
+    /// backend at once, across every op (not just multipart parts,
+    /// which are bounded separately). `None` (the default) leaves it
+    /// unbounded. Useful for staying under a self-imposed rate limit or
+    /// avoiding connection-pool exhaustion under heavy concurrent load.
+    #[serde(default)]
+    pub max_concurrent_requests: Option<usize>,
+    /// Idle HTTP/1.1 connections to keep open per host, for reuse by
+    /// the next request instead of reconnecting. `None` (the default)
+    /// uses the SDK's own default pool size.
+    #[serde(default)]
+    pub max_idle_connections_per_host: Option<usize>,
+    /// `ServerSideEncryption` header to set on every upload — `"AES256"`
+    /// for SSE-S3, or `"aws:kms"` alongside `sse_kms_key_id` for
+    /// SSE-KMS. `None` (the default) sets neither, leaving encryption
+    /// at rest up to the bucket's own default. Additive on top of
+    /// AegisFS's client-side encryption, for audit requirements that
+    /// call for both.
+    #[serde(default)]
+    pub server_side_encryption: Option<String>,
+    /// KMS key id/ARN to pass as `SSEKMSKeyId` when
+    /// `server_side_encryption` is `"aws:kms"`. Ignored otherwise.
+    #[serde(default)]
+    pub sse_kms_key_id: Option<String>,
+    /// When a `get` hits an object S3 reports as archived (Glacier or
+    /// Deep Archive), issue a `RestoreObject` request for it instead of
+    /// just surfacing the error. The read still fails either way — a
+    /// restore takes hours, far longer than any single FUSE call can
+    /// wait — but with this on, that failure also sets the restore in
+    /// motion instead of requiring an operator to notice and trigger it
+    /// out of band. `false` (the default) just reports the error.
+    #[serde(default)]
+    pub restore_archived_objects: bool,
+    /// Seconds to wait for a TCP connection to the endpoint before
+    /// giving up. Without this, a stalled network can leave a FUSE op
+    /// (and the process touching the mount) hanging forever instead of
+    /// failing fast.
+    #[serde(default = "default_connect_timeout_secs")]
+    pub connect_timeout_secs: u64,
+    /// Seconds to wait for bytes from the backend, once connected,
+    /// before giving up on the request. Same "fail fast rather than
+    /// hang forever" motivation as `connect_timeout_secs`.
+    #[serde(default = "default_read_timeout_secs")]
+    pub read_timeout_secs: u64,
+}
+
+fn default_multipart_threshold_bytes() -> u64 {
+    16 * 1024 * 1024
+}
+
+fn default_block_size() -> u64 {
+    crate::blockstore::BLOCK_SIZE
+}
+
+fn default_multipart_part_size_bytes() -> u64 {
+    8 * 1024 * 1024
+}
+
+fn default_connect_timeout_secs() -> u64 {
+    10
+}
+
+fn default_read_timeout_secs() -> u64 {
+    30
+}
+
+/// Configuration for the offline/test-friendly `LocalStorage` backend.
+/// Mutually exclusive with `s3`/`gcs`: when present, it's used instead.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LocalConfig {
+    /// Directory blobs are read from and written to, created on first use.
+    pub root: String,
+}
+
+/// Configuration for `storage::GcsStorage`. Mutually exclusive with
+/// `local`: when present (and `local` isn't), it's used instead of
+/// `s3`. See `storage::GcsStorage` for why this is an interop HMAC
+/// key pair rather than a service-account JSON key.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GcsConfig {
+    pub bucket: String,
+    /// HMAC access key id, from a GCS interoperability credential.
+    pub access_key: String,
+    /// HMAC secret, from the same interoperability credential.
+    pub secret_key: String,
+    /// Object-key prefix to root this mount at, same semantics as
+    /// `S3Config::prefix`.
+    #[serde(default)]
+    pub prefix: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct KmsConfig {
+    /// ARN of the KMS key the master key's ciphertext blob was
+    /// encrypted under. `Decrypt` doesn't strictly require this (the
+    /// blob carries its own key id), but passing it lets KMS reject a
+    /// blob encrypted under the wrong key before it's even decrypted.
+    pub key_arn: String,
+    pub region: String,
+}
+
+/// Environment variable that, when set, supplies the encryption
+/// passphrase directly and takes priority over `key_path` entirely —
+/// so a secrets manager or `systemd-creds` can inject it without it
+/// ever touching the filesystem.
+pub const KEY_ENV_VAR: &str = "AEGIS_KEY";
+
+/// `key_path` value meaning "read the passphrase from stdin" instead of
+/// a file, e.g. `systemd-creds cat aegis-key | aegisfs mount --key-path -`.
+const KEY_PATH_STDIN: &str = "-";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EncryptionConfig {
+    pub key_path: String,
+    #[serde(default)]
+    pub algorithm: EncryptionAlgorithm,
+    /// Salt for the Argon2id passphrase KDF. Must stay constant for a
+    /// given bucket: changing it re-derives a different key and makes
+    /// every existing object undecryptable. Generate one with a CSPRNG
+    /// once per deployment and keep it alongside `key_path`.
+    pub salt: String,
+    /// Encrypt each path component (file and directory names), not
+    /// just file contents. Changes the on-disk object-key layout, so
+    /// this must be decided once per bucket: toggling it later makes
+    /// every existing object unreachable under its old key. See
+    /// `crate::pathcrypt::PathCrypt`.
+    #[serde(default)]
+    pub encrypt_filenames: bool,
+    /// Object key a fresh mount writes (and every later mount reads
+    /// back) recording the key's fingerprint, chosen algorithm, and
+    /// format version; see `crate::keystore`. Never matched by a real
+    /// path, since it has none of the sidecar suffixes `readdir`
+    /// recognizes, and customizable in case it collides with something
+    /// pre-existing in an unusual bucket layout.
+    #[serde(default = "default_key_sentinel")]
+    pub key_sentinel: String,
+}
+
+impl EncryptionConfig {
+    /// Resolves the actual passphrase this config's key should be
+    /// derived from: [`KEY_ENV_VAR`] if set, else stdin if `key_path`
+    /// is `"-"`, else the contents of the file at `key_path` — in that
+    /// priority order, so an env var injected by a secrets manager
+    /// always wins over whatever `key_path` happens to say. Every path
+    /// is trimmed and checked for emptiness the same way, so a blank
+    /// secret is rejected identically no matter where it came from.
+    pub fn load_passphrase(&self) -> Result<String> {
+        let raw = if let Ok(value) = std::env::var(KEY_ENV_VAR) {
+            value
+        } else if self.key_path == KEY_PATH_STDIN {
+            let mut buf = String::new();
+            std::io::Read::read_to_string(&mut std::io::stdin(), &mut buf).map_err(AegisError::Io)?;
+            buf
+        } else {
+            warn_if_key_file_is_readable_by_others(&self.key_path);
+            std::fs::read_to_string(&self.key_path).map_err(|e| {
+                if e.kind() == std::io::ErrorKind::NotFound {
+                    AegisError::MissingKey(self.key_path.clone())
+                } else {
+                    AegisError::Io(e)
+                }
+            })?
+        };
+
+        let passphrase = raw.trim().to_string();
+        if passphrase.is_empty() {
+            return Err(AegisError::MissingKey(self.key_path.clone()));
+        }
+        Ok(passphrase)
+    }
+}
+
+fn default_key_sentinel() -> String {
+    ".aegisfs-key-sentinel".to_string()
+}
+
+/// Logs a warning if `path`'s permission bits let anyone outside its
+/// owner read it — this is the single most sensitive file AegisFS
+/// touches, so a loose umask at creation time (AegisFS itself only
+/// writes it via `generate-key`, which creates it `0600`) deserves a loud
+/// heads-up rather than a silent leak. Best-effort: a `stat` failure
+/// here (e.g. the file is about to 404 anyway) is left for the actual
+/// read to report.
+#[cfg(unix)]
+fn warn_if_key_file_is_readable_by_others(path: &str) {
+    use std::os::unix::fs::PermissionsExt;
+
+    if let Ok(metadata) = std::fs::metadata(path) {
+        let mode = metadata.permissions().mode();
+        if mode & 0o077 != 0 {
+            tracing::warn!(
+                key_path = path,
+                mode = format!("{:o}", mode & 0o777),
+                "key file is readable or writable by group/other; run `chmod 0600 {}`",
+                path
+            );
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn warn_if_key_file_is_readable_by_others(_path: &str) {}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CacheConfig {
+    /// Wraps the configured storage backend in an in-memory LRU cache
+    /// of object bytes. See `storage::CachingStorage`.
+    pub enabled: bool,
+    /// Maximum number of objects the cache keeps resident at once,
+    /// once `enabled`.
+    pub max_entries: usize,
+    /// Maximum number of open-file buffers kept resident at once
+    /// before the least-recently-used one is evicted. See
+    /// `handles::HandleTable`.
+    #[serde(default = "default_max_open_handles")]
+    pub max_open_handles: usize,
+    /// How long the kernel trusts a `getattr` reply before revalidating,
+    /// in seconds. See `fs::AegisFS::with_cache_ttls` for the
+    /// consistency-vs-performance tradeoff.
+    #[serde(default = "default_attr_ttl_secs")]
+    pub attr_ttl_secs: u64,
+    /// How long the kernel trusts a `lookup`/`mkdir`/`create` dentry
+    /// before revalidating, in seconds. Bundled into the same
+    /// `fuser::ReplyEntry::entry` call as the attr validity, so on a
+    /// freshly-resolved entry it can't usefully outlive
+    /// [`Self::attr_ttl_secs`] — see `fs::AegisFS::with_cache_ttls`.
+    #[serde(default = "default_entry_ttl_secs")]
+    pub entry_ttl_secs: u64,
+    /// How long, in seconds, a write-back buffer may sit dirty before
+    /// the background flusher uploads it unasked, bounding how much
+    /// unflushed data a long-lived open file (a log writer, a database)
+    /// can lose to a crash. See `fs::AegisFS::spawn_writeback_flusher`.
+    #[serde(default = "default_writeback_flush_interval_secs")]
+    pub writeback_flush_interval_secs: u64,
+    /// Total buffered bytes across every open handle that, once
+    /// exceeded, makes the background flusher upload dirty buffers
+    /// (largest first) until back under the limit, regardless of how
+    /// recently they were written to. Bounds worst-case memory growth
+    /// from several files being written concurrently, independent of
+    /// [`Self::writeback_flush_interval_secs`].
+    #[serde(default = "default_writeback_dirty_bytes_limit")]
+    pub writeback_dirty_bytes_limit: u64,
+}
+
+fn default_max_open_handles() -> usize {
+    256
+}
+
+fn default_attr_ttl_secs() -> u64 {
+    1
+}
+
+fn default_entry_ttl_secs() -> u64 {
+    1
+}
+
+fn default_writeback_flush_interval_secs() -> u64 {
+    30
+}
+
+fn default_writeback_dirty_bytes_limit() -> u64 {
+    64 * 1024 * 1024
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        CacheConfig {
+            enabled: false,
+            max_entries: 1024,
+            max_open_handles: default_max_open_handles(),
+            attr_ttl_secs: default_attr_ttl_secs(),
+            entry_ttl_secs: default_entry_ttl_secs(),
+            writeback_flush_interval_secs: default_writeback_flush_interval_secs(),
+            writeback_dirty_bytes_limit: default_writeback_dirty_bytes_limit(),
+        }
+    }
+}
+
+/// Transparent zstd compression of block plaintext before encryption.
+/// See `crate::compress` and `blockstore::Header::blocks_compressed`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CompressionConfig {
+    /// Compress newly-written blocks. Decided once per file and
+    /// persisted in its header, so flipping this only affects files
+    /// that haven't been written to yet; existing files keep whatever
+    /// was decided for them the first time they were written. Off by
+    /// default: compressing plaintext before encrypting it can leak
+    /// information about the plaintext through the resulting
+    /// ciphertext's length.
+    #[serde(default)]
+    pub enabled: bool,
+    /// zstd compression level. Higher trades CPU for a smaller result;
+    /// 3 is zstd's own default and a reasonable balance.
+    #[serde(default = "default_compression_level")]
+    pub level: i32,
+}
+
+fn default_compression_level() -> i32 {
+    3
+}
+
+impl Default for CompressionConfig {
+    fn default() -> Self {
+        CompressionConfig {
+            enabled: false,
+            level: default_compression_level(),
+        }
+    }
+}
+
+/// Client-side content-addressed deduplication. See `crate::links` for
+/// the keyed content hash and the refcounted content-object sharing it
+/// rides on top of (the same mechanism `fs::AegisFS::link` uses).
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DedupConfig {
+    /// Hash each file's plaintext on flush and, if a content object
+    /// for that exact hash already exists, point at it instead of
+    /// re-uploading. Off by default: it costs an extra read per flush
+    /// to check for an existing match, which isn't worth paying for
+    /// buckets that don't have much duplicate content to begin with.
+    ///
+    /// Unlike `fs::AegisFS::link`, a dedup match never makes two paths
+    /// observe each other's future writes: `FileMetadata::content_linked`
+    /// stays `false` for a dedup-only share, so the first write to
+    /// either path forks it onto its own content object before the
+    /// upload (see `fs::AegisFS::flush_open_file`'s copy-on-write
+    /// branch) rather than overwriting the bytes the other path still
+    /// reads.
+    #[serde(default)]
+    pub enabled: bool,
+}
+
+/// Compliance access trail: who (uid/gid/pid) did what to which path,
+/// and whether it succeeded. See `crate::audit`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AuditConfig {
+    /// Records an event for every mutating and read FUSE op. Off by
+    /// default: it's an extra line of file I/O per op, which not every
+    /// mount wants to pay for.
+    #[serde(default)]
+    pub enabled: bool,
+    /// Append-only destination for audit events, one JSON object per
+    /// line. Required when `enabled` is set; see [`Config::validate`].
+    #[serde(default)]
+    pub log_path: Option<String>,
+}
+
+/// Local write-ahead journal for crash consistency. See `crate::journal`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct JournalConfig {
+    /// Records every write-back buffer's plaintext to `dir` right
+    /// before it's uploaded, replaying whatever's left on the next
+    /// mount. Off by default: it's an extra local write per flush,
+    /// which not every mount wants to pay for.
+    #[serde(default)]
+    pub enabled: bool,
+    /// Directory pending writes are recorded in. Required when
+    /// `enabled` is set; see [`Config::validate`].
+    #[serde(default)]
+    pub dir: Option<String>,
+}
+
+/// Configuration for the metrics endpoint. See `crate::metrics`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct MetricsConfig {
+    /// Serves `GET /metrics` in Prometheus text format on this address
+    /// (e.g. `"127.0.0.1:9090"`). `None` (the default) leaves the
+    /// endpoint disabled; op/backend/cache counters are still collected
+    /// either way, for embedders that want to read them directly via
+    /// `AegisFS::metrics`.
+    #[serde(default)]
+    pub bind_address: Option<String>,
+}
+
+/// Expands a leading `~` to `$HOME` and then every `${VAR}` reference,
+/// in that order, so `~/.aegis/${ENV}` works as expected. Errors if a
+/// referenced variable isn't set, rather than silently substituting an
+/// empty string — a missing credential should fail loudly at startup,
+/// not show up as an empty access key three layers down.
+fn expand_placeholders_in(value: &str) -> Result<String> {
+    expand_env_vars(&expand_home(value))
+}
+
+fn expand_home(value: &str) -> String {
+    let home = match std::env::var("HOME") {
+        Ok(home) => home,
+        Err(_) => return value.to_string(),
+    };
+    if value == "~" {
+        home
+    } else if let Some(rest) = value.strip_prefix("~/") {
+        format!("{}/{}", home.trim_end_matches('/'), rest)
+    } else {
+        value.to_string()
+    }
+}
+
+fn expand_env_vars(value: &str) -> Result<String> {
+    let mut out = String::with_capacity(value.len());
+    let mut chars = value.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '$' || chars.peek() != Some(&'{') {
+            out.push(c);
+            continue;
+        }
+        chars.next(); // consume '{'
+        let mut name = String::new();
+        let mut closed = false;
+        for c in chars.by_ref() {
+            if c == '}' {
+                closed = true;
+                break;
+            }
+            name.push(c);
+        }
+        if !closed {
+            return Err(AegisError::Config(format!("unterminated ${{...}} in config value {:?}", value)));
+        }
+        let resolved = std::env::var(&name).map_err(|_| {
+            AegisError::Config(format!("environment variable {} referenced in config but not set", name))
+        })?;
+        out.push_str(&resolved);
+    }
+    Ok(out)
+}
+
+/// How directories are represented in the bucket. Mirrors
+/// [`crate::fs::DirectoryMode`], kept as a separate (de)serializable
+/// type so the FUSE layer doesn't need to derive `serde` traits just
+/// for config parsing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DirectoryMode {
+    #[default]
+    Marker,
+    Implicit,
+}
+
+impl From<DirectoryMode> for crate::fs::DirectoryMode {
+    fn from(mode: DirectoryMode) -> Self {
+        match mode {
+            DirectoryMode::Marker => crate::fs::DirectoryMode::Marker,
+            DirectoryMode::Implicit => crate::fs::DirectoryMode::Implicit,
+        }
+    }
+}
+
+/// How aggressively cached attrs are trusted between kernel cache TTL
+/// refreshes. See `fs::AegisFS`'s getattr/read handling.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ConsistencyMode {
+    /// Trust the TTL; an external writer's change won't be visible
+    /// until it expires. Cheapest, least consistent.
+    #[default]
+    TtlBased,
+    /// Re-validate via a conditional HEAD/GET (ETag match) on every
+    /// access, so external changes are visible immediately at the cost
+    /// of one cheap round trip when nothing changed.
+    Strong,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Config {
+    pub s3: S3Config,
+    /// When set, mounts against `LocalStorage` at this root instead of
+    /// the configured S3 bucket. See `LocalConfig`.
+    #[serde(default)]
+    pub local: Option<LocalConfig>,
+    /// When set (and `local` isn't), mounts against `GcsStorage`
+    /// instead of the configured S3 bucket. See `GcsConfig`.
+    #[serde(default)]
+    pub gcs: Option<GcsConfig>,
+    /// When set, the master key is resolved by asking AWS KMS to
+    /// `Decrypt` the ciphertext blob read via `encryption.key_path` (or
+    /// `AEGIS_KEY`/stdin), instead of deriving it from a passphrase.
+    /// See [`crate::kms`].
+    #[serde(default)]
+    pub kms: Option<KmsConfig>,
+    pub encryption: EncryptionConfig,
+    #[serde(default)]
+    pub cache: CacheConfig,
+    #[serde(default)]
+    pub metrics: MetricsConfig,
+    #[serde(default)]
+    pub compression: CompressionConfig,
+    #[serde(default)]
+    pub dedup: DedupConfig,
+    #[serde(default)]
+    pub audit: AuditConfig,
+    #[serde(default)]
+    pub journal: JournalConfig,
+    #[serde(default)]
+    pub directory_mode: DirectoryMode,
+    #[serde(default)]
+    pub consistency_mode: ConsistencyMode,
+    /// Size, in bytes, of each independently-encrypted block a file is
+    /// split into (see `blockstore`), and the `blksize` reported to the
+    /// kernel, which influences its readahead. Larger blocks mean fewer,
+    /// bigger backend requests for sequential I/O but more wasted
+    /// re-encryption work on small random writes; smaller blocks are
+    /// the opposite. Must be a power of two. Only affects files created
+    /// after a change — an existing file keeps using whatever block
+    /// size its header was created with. See [`Self::validate`].
+    #[serde(default = "default_block_size")]
+    pub block_size: u64,
+    /// Refuses every mutating FUSE op with `EROFS` instead of touching
+    /// the backend. Overridable per-mount with `--read-only`. See
+    /// `fs::AegisFS::with_read_only`.
+    #[serde(default)]
+    pub read_only: bool,
+    /// Named overrides of `s3`/`encryption`/`cache`, selected per-run
+    /// with `--profile` instead of maintaining a whole separate config
+    /// file per bucket. See [`Self::profile`].
+    #[serde(default)]
+    pub profiles: std::collections::HashMap<String, ProfileOverride>,
+}
+
+/// A `[profiles.<name>]` table: overrides `s3`/`encryption`/`cache` for
+/// one named profile, falling back to the top-level config's own
+/// settings for whichever of the three it leaves out. See
+/// [`Config::profile`].
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ProfileOverride {
+    #[serde(default)]
+    pub s3: Option<S3Config>,
+    #[serde(default)]
+    pub encryption: Option<EncryptionConfig>,
+    #[serde(default)]
+    pub cache: Option<CacheConfig>,
+}
+
+/// The `s3`/`encryption`/`cache` settings a profile resolves to, once
+/// merged with the top-level config's defaults. Returned by
+/// [`Config::profile`] rather than a full `Config`, since nothing else
+/// (directory_mode, consistency_mode, metrics, read_only) varies
+/// per-profile.
+#[derive(Debug, Clone)]
+pub struct ResolvedProfile {
+    pub s3: S3Config,
+    pub encryption: EncryptionConfig,
+    pub cache: CacheConfig,
+}
+
+impl Config {
+    pub fn load(path: &str) -> Result<Config> {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| AegisError::Config(format!("reading {}: {}", path, e)))?;
+        let mut config: Config = toml::from_str(&contents)
+            .map_err(|e| AegisError::Config(format!("parsing {}: {}", path, e)))?;
+        config.expand_placeholders()?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Expands `${VAR}` environment-variable references and a leading
+    /// `~` in every field that plausibly holds a secret or a filesystem
+    /// path, so credentials can live in the environment (CI, containers)
+    /// instead of the TOML file, and paths can be written relative to
+    /// the home directory. Run before [`Self::validate`], so e.g. a
+    /// `key_path` of `~/.aegis/key` is checked for existence only after
+    /// being expanded to an absolute path.
+    fn expand_placeholders(&mut self) -> Result<()> {
+        for field in [&mut self.s3.access_key, &mut self.s3.secret_key, &mut self.s3.ca_cert_path] {
+            if let Some(value) = field {
+                *value = expand_placeholders_in(value)?;
+            }
+        }
+        self.encryption.key_path = expand_placeholders_in(&self.encryption.key_path)?;
+        if let Some(root) = self.local.as_mut().map(|l| &mut l.root) {
+            *root = expand_placeholders_in(root)?;
+        }
+        if let Some(gcs) = &mut self.gcs {
+            gcs.access_key = expand_placeholders_in(&gcs.access_key)?;
+            gcs.secret_key = expand_placeholders_in(&gcs.secret_key)?;
+        }
+        Ok(())
+    }
+
+    /// Catches the problems TOML parsing alone can't: an empty bucket
+    /// name, a key file that doesn't exist, a salt too short to be a
+    /// real Argon2id salt. Every problem found is collected into a
+    /// single [`AegisError::Config`] rather than stopping at the first
+    /// one, so a bad config file gets fixed in one pass instead of one
+    /// failed `mount` at a time. `algorithm`'s validity isn't checked
+    /// here: an unsupported value already fails to parse as
+    /// [`EncryptionAlgorithm`] before validation ever runs.
+    ///
+    /// Also normalizes `s3.prefix` to end with `/`, so callers never
+    /// have to handle the bare-vs-trailing-slash distinction themselves.
+    pub fn validate(&mut self) -> Result<()> {
+        let mut problems = Vec::new();
+
+        if self.s3.bucket.trim().is_empty() {
+            problems.push("s3.bucket must not be empty".to_string());
+        }
+        if self.s3.region.trim().is_empty() {
+            problems.push("s3.region must not be empty".to_string());
+        }
+        if self.s3.sse_kms_key_id.is_some() && self.s3.server_side_encryption.as_deref() != Some("aws:kms") {
+            problems.push("s3.sse_kms_key_id requires s3.server_side_encryption = \"aws:kms\"".to_string());
+        }
+
+        // A passphrase supplied via `AEGIS_KEY` or stdin never touches
+        // the filesystem, so `key_path` pointing at a real file isn't
+        // required in either case; `load_passphrase` is what actually
+        // enforces the secret itself isn't blank, at load time.
+        let key_path_required = std::env::var(KEY_ENV_VAR).is_err() && self.encryption.key_path != KEY_PATH_STDIN;
+        if key_path_required {
+            if self.encryption.key_path.trim().is_empty() {
+                problems.push("encryption.key_path must not be empty".to_string());
+            } else if !std::path::Path::new(&self.encryption.key_path).is_file() {
+                problems.push(format!(
+                    "encryption.key_path {:?} does not exist or is not a file",
+                    self.encryption.key_path
+                ));
+            }
+        }
+
+        // Argon2 rejects a salt shorter than 8 bytes outright, but the
+        // resulting error is a late, confusing one raised deep inside
+        // key derivation; catching it here points at the config line
+        // responsible instead.
+        if self.encryption.salt.len() < 8 {
+            problems.push("encryption.salt must be at least 8 bytes".to_string());
+        }
+
+        if self.block_size == 0 || !self.block_size.is_power_of_two() {
+            problems.push(format!("block_size {} must be a power of two", self.block_size));
+        }
+
+        if self.audit.enabled && self.audit.log_path.as_deref().map(str::trim).unwrap_or("").is_empty() {
+            problems.push("audit.log_path must be set when audit.enabled is true".to_string());
+        }
+
+        if self.journal.enabled && self.journal.dir.as_deref().map(str::trim).unwrap_or("").is_empty() {
+            problems.push("journal.dir must be set when journal.enabled is true".to_string());
+        }
+
+        if let Some(prefix) = &mut self.s3.prefix {
+            if !prefix.is_empty() && !prefix.ends_with('/') {
+                prefix.push('/');
+            }
+        }
+
+        if let Some(gcs) = &mut self.gcs {
+            if gcs.bucket.trim().is_empty() {
+                problems.push("gcs.bucket must not be empty".to_string());
+            }
+            if gcs.access_key.trim().is_empty() {
+                problems.push("gcs.access_key must not be empty".to_string());
+            }
+            if gcs.secret_key.trim().is_empty() {
+                problems.push("gcs.secret_key must not be empty".to_string());
+            }
+            if let Some(prefix) = &mut gcs.prefix {
+                if !prefix.is_empty() && !prefix.ends_with('/') {
+                    prefix.push('/');
+                }
+            }
+        }
+
+        if let Some(kms) = &self.kms {
+            if kms.key_arn.trim().is_empty() {
+                problems.push("kms.key_arn must not be empty".to_string());
+            }
+            if kms.region.trim().is_empty() {
+                problems.push("kms.region must not be empty".to_string());
+            }
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(AegisError::Config(problems.join("; ")))
+        }
+    }
+
+    /// Resolves the named profile's `s3`/`encryption`/`cache` settings,
+    /// falling back to this config's top-level settings for a field the
+    /// profile doesn't override, or entirely when `name` is `None`
+    /// (the implicit "default profile" every config has). Errors if
+    /// `name` is `Some` but isn't one of `self.profiles`.
+    pub fn profile(&self, name: Option<&str>) -> Result<ResolvedProfile> {
+        let profile = match name {
+            None => {
+                return Ok(ResolvedProfile {
+                    s3: self.s3.clone(),
+                    encryption: self.encryption.clone(),
+                    cache: self.cache.clone(),
+                });
+            }
+            Some(name) => self
+                .profiles
+                .get(name)
+                .ok_or_else(|| AegisError::Config(format!("no profile named {:?}", name)))?,
+        };
+        Ok(ResolvedProfile {
+            s3: profile.s3.clone().unwrap_or_else(|| self.s3.clone()),
+            encryption: profile.encryption.clone().unwrap_or_else(|| self.encryption.clone()),
+            cache: profile.cache.clone().unwrap_or_else(|| self.cache.clone()),
+        })
+    }
+
+    /// An annotated template config file, for the `init-config`
+    /// subcommand to write out for new users. Every field is present
+    /// with a safe placeholder, but `s3.bucket`, `s3.region`, and
+    /// `encryption.salt` still need real values before the result is
+    /// usable — the comments say so inline. [`Self::validate`] does not
+    /// recognize the placeholders themselves; it only rejects empty or
+    /// too-short values and a `key_path` that isn't an existing file.
+    pub fn default_config() -> &'static str {
+        r#"# AegisFS configuration. See the README for the full field reference.
+
+[s3]
+# Required: the bucket this mount serves from.
+bucket = "your-bucket-name"
+region = "us-east-1"
+# Uncomment to point at a non-AWS S3-compatible endpoint (e.g. MinIO).
+# endpoint = "https://minio.example.com"
+# access_key = "${AWS_ACCESS_KEY_ID}"
+# secret_key = "${AWS_SECRET_ACCESS_KEY}"
+# Uncomment to resolve credentials from a named profile (e.g. one set
+# up via `aws sso login`) instead of the default chain.
+# profile = "my-sso-profile"
+# Uncomment to assume this role via STS before talking to the bucket,
+# on top of whatever access_key/secret_key/profile resolve.
+# role_arn = "arn:aws:iam::123456789012:role/aegisfs"
+# Uncomment to bound in-flight requests and idle pooled connections
+# per host; unset leaves both at the SDK's own defaults.
+# max_concurrent_requests = 64
+# max_idle_connections_per_host = 32
+# Uncomment to cap upload/download bandwidth against this bucket, in
+# bytes/sec — useful when the mount shares a link with other traffic.
+# Each direction is one aggregate budget shared across every request
+# (including multipart parts) going that way; unset is unlimited.
+# max_upload_bytes_per_sec = 10485760
+# max_download_bytes_per_sec = 10485760
+# Uncomment to also request SSE at rest from S3 itself, on top of
+# AegisFS's own client-side encryption — for audit requirements that
+# call for both. "aws:kms" requires sse_kms_key_id too; "AES256" does not.
+# server_side_encryption = "aws:kms"
+# sse_kms_key_id = "arn:aws:kms:us-east-1:123456789012:key/abcd-1234"
+# Uncomment to change how long a stalled connection or unresponsive
+# request is given before it's failed (EAGAIN) rather than left to
+# hang; 10s/30s by default.
+# connect_timeout_secs = 10
+# read_timeout_secs = 30
+
+# Uncomment to mount against Google Cloud Storage via its
+# S3-interoperability endpoint instead of the [s3] bucket (the [s3]
+# table above must still be present). Requires an interoperability
+# HMAC key pair, not a service-account JSON key.
+# [gcs]
+# bucket = "your-bucket-name"
+# access_key = "${GCS_INTEROP_ACCESS_KEY}"
+# secret_key = "${GCS_INTEROP_SECRET}"
+
+# Uncomment to resolve the master key from AWS KMS instead of deriving
+# it from a passphrase: encryption.key_path (or AEGIS_KEY/stdin) is
+# then read as a base64-encoded ciphertext blob and sent to KMS
+# Decrypt at startup, so the plaintext master key is never stored.
+# Produce the blob out-of-band, e.g. `aws kms encrypt --key-id ... \
+# --plaintext fileb://master.key --output text --query CiphertextBlob`.
+# [kms]
+# key_arn = "arn:aws:kms:us-east-1:123456789012:key/your-key-id"
+# region = "us-east-1"
+
+[encryption]
+# Passphrase file for content encryption (or, with [kms] configured,
+# the base64 KMS ciphertext blob). Generate one with a CSPRNG and keep
+# it out of version control. Set to "-" to read it from stdin instead,
+# or set the AEGIS_KEY environment variable to supply it directly —
+# either avoids ever writing it to disk. AEGIS_KEY, when set, takes
+# priority over this.
+key_path = "~/.aegis/key"
+# Required: a random value, generated once per deployment and never
+# changed afterward. Changing it makes every existing object
+# undecryptable.
+salt = "CHANGE-ME-to-a-random-value"
+# Uncomment to pick a non-default AEAD algorithm (aes256-gcm is used
+# if this is left unset).
+# algorithm = "aes256-gcm-siv"
+
+[cache]
+enabled = false
+max_entries = 1024
+# How long (in seconds) the kernel trusts a getattr/lookup reply before
+# revalidating against the backend. Raise these on high-latency S3 to
+# cut revalidation traffic; lower towards 0 for workloads that need to
+# see another mount's writes sooner, at the cost of relisting more.
+# attr_ttl_secs = 1
+# entry_ttl_secs = 1
+# Bounds how long a write-back buffer can sit dirty, and how many
+# dirty bytes can accumulate across every open handle, before the
+# background flusher uploads it unasked — so a file held open
+# indefinitely (a log writer, a database) has a bounded data-loss
+# window and can't grow memory use without limit.
+# writeback_flush_interval_secs = 30
+# writeback_dirty_bytes_limit = 67108864
+
+# Uncomment to compress block plaintext with zstd before encrypting it.
+# Decided once per file and persisted in its header, so this only
+# affects files written after it's turned on.
+# [compression]
+# enabled = true
+# level = 3
+
+# Uncomment to hash each file's plaintext on flush and, if a content
+# object for that exact hash already exists, point at it instead of
+# re-uploading. Good for buckets with a lot of duplicate content (CI
+# artifacts, backups); costs an extra read per flush to check for a
+# match.
+# [dedup]
+# enabled = true
+
+# Uncomment to serve Prometheus-format metrics for monitoring.
+# [metrics]
+# bind_address = "127.0.0.1:9090"
+
+# Uncomment to record a compliance access trail: a timestamped
+# uid/gid/pid/path/success line per mutating and read op, appended to
+# log_path as JSON. Off by default since it's extra I/O per op.
+# [audit]
+# enabled = true
+# log_path = "/var/log/aegisfs/audit.log"
+
+# Uncomment to record every write-back buffer to local disk right
+# before it's uploaded, replaying whatever's left on the next mount —
+# bounds the data-loss window from a crash between a buffered write and
+# its upload to whatever was in flight at the time. Off by default
+# since it's an extra local write per flush.
+# [journal]
+# enabled = true
+# dir = "~/.aegis/journal"
+
+# Uncomment to change the size (in bytes, must be a power of two) of
+# each independently-encrypted block a file is split into, and the
+# blksize reported to the kernel. Larger blocks favor sequential I/O;
+# smaller ones favor small random writes. Only affects files created
+# after the change.
+# block_size = 65536
+
+# Uncomment to refuse every write, mkdir, rmdir, unlink, rename, and
+# setattr with EROFS instead of touching the backend. Useful for
+# auditing or serving a shared read-only dataset. Overridable per-mount
+# with `--read-only`.
+# read_only = true
+
+# Uncomment to manage another bucket from this same config file,
+# selected with `--profile work` instead of maintaining a whole
+# separate config file per bucket. A profile only needs to override
+# what differs; anything left out (here, [encryption]) falls back to
+# the top-level settings above.
+# [profiles.work]
+# [profiles.work.s3]
+# bucket = "work-bucket"
+# region = "us-west-2"
+"#
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn valid_config(key_path: String) -> Config {
+        Config {
+            s3: S3Config {
+                bucket: "test-bucket".into(),
+                region: "us-east-1".into(),
+                endpoint: None,
+                access_key: None,
+                secret_key: None,
+                read_endpoint: None,
+                restore_archived_objects: false,
+                profile: None,
+                role_arn: None,
+                ca_cert_path: None,
+                prefix: None,
+                danger_accept_invalid_certs: false,
+                max_upload_bytes_per_sec: None,
+                max_download_bytes_per_sec: None,
+                multipart_threshold_bytes: 16 * 1024 * 1024,
+                multipart_part_size_bytes: 8 * 1024 * 1024,
+                max_concurrent_requests: None,
+                max_idle_connections_per_host: None,
+                server_side_encryption: None,
+                sse_kms_key_id: None,
+                connect_timeout_secs: 10,
+                read_timeout_secs: 30,
+            },
+            local: None,
+            gcs: None,
+            kms: None,
+            encryption: EncryptionConfig {
+                key_path,
+                algorithm: Default::default(),
+                salt: "a-stable-test-salt".into(),
+                encrypt_filenames: false,
+                key_sentinel: ".aegisfs-key-sentinel".into(),
+            },
+            cache: CacheConfig::default(),
+            metrics: Default::default(),
+            compression: Default::default(),
+            dedup: Default::default(),
+            audit: Default::default(),
+            journal: Default::default(),
+            directory_mode: DirectoryMode::Marker,
+            consistency_mode: Default::default(),
+            block_size: crate::blockstore::BLOCK_SIZE,
+            read_only: false,
+            profiles: Default::default(),
+        }
+    }
+
+    #[test]
+    fn a_well_formed_config_validates_cleanly() {
+        let key_file = tempfile::NamedTempFile::new().unwrap();
+        let mut config = valid_config(key_file.path().to_str().unwrap().to_string());
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn an_empty_bucket_and_region_are_reported_together() {
+        let key_file = tempfile::NamedTempFile::new().unwrap();
+        let mut config = valid_config(key_file.path().to_str().unwrap().to_string());
+        config.s3.bucket = "  ".into();
+        config.s3.region = "".into();
+
+        let err = config.validate().unwrap_err().to_string();
+        assert!(err.contains("s3.bucket"), "{}", err);
+        assert!(err.contains("s3.region"), "{}", err);
+    }
+
+    #[test]
+    fn a_missing_key_file_is_reported() {
+        let mut config = valid_config("/nonexistent/path/to/key".into());
+        let err = config.validate().unwrap_err().to_string();
+        assert!(err.contains("key_path"), "{}", err);
+    }
+
+    #[test]
+    fn load_passphrase_reads_the_key_file_by_default() {
+        let mut key_file = tempfile::NamedTempFile::new().unwrap();
+        writeln!(key_file, "  a test passphrase  ").unwrap();
+        let config = valid_config(key_file.path().to_str().unwrap().to_string());
+
+        assert_eq!(config.encryption.load_passphrase().unwrap(), "a test passphrase");
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn load_passphrase_still_succeeds_against_a_world_readable_key_file() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let mut key_file = tempfile::NamedTempFile::new().unwrap();
+        writeln!(key_file, "a test passphrase").unwrap();
+        std::fs::set_permissions(key_file.path(), std::fs::Permissions::from_mode(0o644)).unwrap();
+        let config = valid_config(key_file.path().to_str().unwrap().to_string());
+
+        assert_eq!(config.encryption.load_passphrase().unwrap(), "a test passphrase");
+    }
+
+    #[test]
+    fn load_passphrase_prefers_the_env_var_over_the_key_file() {
+        std::env::set_var("AEGIS_KEY", "from-the-environment");
+        let mut key_file = tempfile::NamedTempFile::new().unwrap();
+        writeln!(key_file, "from-the-file").unwrap();
+        let config = valid_config(key_file.path().to_str().unwrap().to_string());
+
+        let result = config.encryption.load_passphrase();
+        std::env::remove_var("AEGIS_KEY");
+
+        assert_eq!(result.unwrap(), "from-the-environment");
+    }
+
+    #[test]
+    fn a_blank_passphrase_is_rejected_even_from_the_env_var() {
+        std::env::set_var("AEGIS_KEY", "   ");
+        let config = valid_config("/nonexistent/path/to/key".into());
+
+        let result = config.encryption.load_passphrase();
+        std::env::remove_var("AEGIS_KEY");
+
+        assert!(matches!(result, Err(AegisError::MissingKey(_))));
+    }
+
+    #[test]
+    fn a_key_path_of_dash_skips_the_file_existence_check() {
+        let mut config = valid_config("-".into());
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn an_unset_key_env_var_still_requires_a_real_key_file() {
+        std::env::remove_var("AEGIS_KEY");
+        let mut config = valid_config("/nonexistent/path/to/key".into());
+        let err = config.validate().unwrap_err().to_string();
+        assert!(err.contains("key_path"), "{}", err);
+    }
+
+    #[test]
+    fn a_salt_shorter_than_eight_bytes_is_reported() {
+        let key_file = tempfile::NamedTempFile::new().unwrap();
+        let mut config = valid_config(key_file.path().to_str().unwrap().to_string());
+        config.encryption.salt = "short".into();
+
+        let err = config.validate().unwrap_err().to_string();
+        assert!(err.contains("salt"), "{}", err);
+    }
+
+    #[test]
+    fn a_block_size_that_isnt_a_power_of_two_is_reported() {
+        let key_file = tempfile::NamedTempFile::new().unwrap();
+        let mut config = valid_config(key_file.path().to_str().unwrap().to_string());
+        config.block_size = 3000;
+
+        let err = config.validate().unwrap_err().to_string();
+        assert!(err.contains("block_size"), "{}", err);
+    }
+
+    #[test]
+    fn a_power_of_two_block_size_validates_cleanly() {
+        let key_file = tempfile::NamedTempFile::new().unwrap();
+        let mut config = valid_config(key_file.path().to_str().unwrap().to_string());
+        config.block_size = 4096;
+
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn a_prefix_without_a_trailing_slash_is_normalized() {
+        let mut key_file = tempfile::NamedTempFile::new().unwrap();
+        writeln!(key_file, "passphrase").unwrap();
+        let mut config = valid_config(key_file.path().to_str().unwrap().to_string());
+        config.s3.prefix = Some("tenants/acme".into());
+
+        config.validate().unwrap();
+        assert_eq!(config.s3.prefix.as_deref(), Some("tenants/acme/"));
+    }
+
+    #[test]
+    fn expand_env_vars_substitutes_a_set_variable() {
+        std::env::set_var("AEGISFS_TEST_CONFIG_SECRET", "s3kr1t");
+        let expanded = expand_env_vars("prefix-${AEGISFS_TEST_CONFIG_SECRET}-suffix").unwrap();
+        assert_eq!(expanded, "prefix-s3kr1t-suffix");
+        std::env::remove_var("AEGISFS_TEST_CONFIG_SECRET");
+    }
+
+    #[test]
+    fn expand_env_vars_errors_clearly_on_a_missing_variable() {
+        std::env::remove_var("AEGISFS_TEST_CONFIG_DEFINITELY_UNSET");
+        let err = expand_env_vars("${AEGISFS_TEST_CONFIG_DEFINITELY_UNSET}").unwrap_err().to_string();
+        assert!(err.contains("AEGISFS_TEST_CONFIG_DEFINITELY_UNSET"), "{}", err);
+    }
+
+    #[test]
+    fn expand_home_replaces_a_leading_tilde_slash() {
+        std::env::set_var("HOME", "/home/aegis");
+        assert_eq!(expand_home("~/.aegis/key"), "/home/aegis/.aegis/key");
+        assert_eq!(expand_home("not/a/tilde/path"), "not/a/tilde/path");
+    }
+
+    #[test]
+    fn default_config_is_valid_toml_with_the_documented_placeholders() {
+        let parsed: Config = toml::from_str(Config::default_config()).unwrap();
+        assert_eq!(parsed.s3.bucket, "your-bucket-name");
+        assert_eq!(parsed.encryption.key_path, "~/.aegis/key");
+        // The unexpanded `~/.aegis/key` placeholder isn't an existing
+        // file, so the template doesn't validate as-is.
+        assert!(parsed.clone().validate().is_err());
+    }
+
+    #[test]
+    fn load_expands_an_env_var_referenced_secret() {
+        std::env::set_var("AEGISFS_TEST_CONFIG_LOAD_SECRET", "loaded-from-env");
+        let mut key_file = tempfile::NamedTempFile::new().unwrap();
+        writeln!(key_file, "passphrase").unwrap();
+
+        let mut toml_file = tempfile::NamedTempFile::new().unwrap();
+        writeln!(
+            toml_file,
+            r#"
+            [s3]
+            bucket = "test-bucket"
+            region = "us-east-1"
+            secret_key = "${{AEGISFS_TEST_CONFIG_LOAD_SECRET}}"
+
+            [encryption]
+            key_path = {:?}
+            salt = "a-stable-test-salt"
+            "#,
+            key_file.path().to_str().unwrap()
+        )
+        .unwrap();
+
+        let config = Config::load(toml_file.path().to_str().unwrap()).unwrap();
+        assert_eq!(config.s3.secret_key.as_deref(), Some("loaded-from-env"));
+        std::env::remove_var("AEGISFS_TEST_CONFIG_LOAD_SECRET");
+    }
+
+    #[test]
+    fn a_gcs_section_is_validated_like_the_s3_one() {
+        let key_file = tempfile::NamedTempFile::new().unwrap();
+        let mut config = valid_config(key_file.path().to_str().unwrap().to_string());
+        config.gcs = Some(GcsConfig {
+            bucket: "  ".into(),
+            access_key: "".into(),
+            secret_key: "a-secret".into(),
+            prefix: Some("tenants/acme".into()),
+        });
+
+        let err = config.validate().unwrap_err().to_string();
+        assert!(err.contains("gcs.bucket"), "{}", err);
+        assert!(err.contains("gcs.access_key"), "{}", err);
+        assert!(!err.contains("gcs.secret_key"), "{}", err);
+    }
+
+    #[test]
+    fn a_well_formed_gcs_section_normalizes_its_prefix_and_validates_cleanly() {
+        let key_file = tempfile::NamedTempFile::new().unwrap();
+        let mut config = valid_config(key_file.path().to_str().unwrap().to_string());
+        config.gcs = Some(GcsConfig {
+            bucket: "my-bucket".into(),
+            access_key: "GOOG1EXAMPLE".into(),
+            secret_key: "a-secret".into(),
+            prefix: Some("tenants/acme".into()),
+        });
+
+        config.validate().unwrap();
+        assert_eq!(config.gcs.unwrap().prefix.as_deref(), Some("tenants/acme/"));
+    }
+
+    #[test]
+    fn a_kms_section_is_validated_like_the_gcs_one() {
+        let key_file = tempfile::NamedTempFile::new().unwrap();
+        let mut config = valid_config(key_file.path().to_str().unwrap().to_string());
+        config.kms = Some(KmsConfig { key_arn: "  ".into(), region: "".into() });
+
+        let err = config.validate().unwrap_err().to_string();
+        assert!(err.contains("kms.key_arn"), "{}", err);
+        assert!(err.contains("kms.region"), "{}", err);
+    }
+
+    #[test]
+    fn a_well_formed_kms_section_validates_cleanly() {
+        let key_file = tempfile::NamedTempFile::new().unwrap();
+        let mut config = valid_config(key_file.path().to_str().unwrap().to_string());
+        config.kms = Some(KmsConfig {
+            key_arn: "arn:aws:kms:us-east-1:123456789012:key/abcd-1234".into(),
+            region: "us-east-1".into(),
+        });
+
+        config.validate().unwrap();
+    }
+
+    #[test]
+    fn sse_kms_key_id_without_aws_kms_encryption_is_rejected() {
+        let key_file = tempfile::NamedTempFile::new().unwrap();
+        let mut config = valid_config(key_file.path().to_str().unwrap().to_string());
+        config.s3.sse_kms_key_id = Some("arn:aws:kms:us-east-1:123456789012:key/abcd-1234".into());
+
+        let err = config.validate().unwrap_err().to_string();
+        assert!(err.contains("sse_kms_key_id"), "{}", err);
+    }
+
+    #[test]
+    fn sse_kms_key_id_alongside_aws_kms_encryption_validates_cleanly() {
+        let key_file = tempfile::NamedTempFile::new().unwrap();
+        let mut config = valid_config(key_file.path().to_str().unwrap().to_string());
+        config.s3.server_side_encryption = Some("aws:kms".into());
+        config.s3.sse_kms_key_id = Some("arn:aws:kms:us-east-1:123456789012:key/abcd-1234".into());
+
+        config.validate().unwrap();
+    }
+
+    #[test]
+    fn an_already_trailing_slash_prefix_is_left_alone() {
+        let key_file = tempfile::NamedTempFile::new().unwrap();
+        let mut config = valid_config(key_file.path().to_str().unwrap().to_string());
+        config.s3.prefix = Some("tenants/acme/".into());
+
+        config.validate().unwrap();
+        assert_eq!(config.s3.prefix.as_deref(), Some("tenants/acme/"));
+    }
+
+    #[test]
+    fn profile_with_no_name_returns_the_top_level_settings() {
+        let key_file = tempfile::NamedTempFile::new().unwrap();
+        let config = valid_config(key_file.path().to_str().unwrap().to_string());
+
+        let resolved = config.profile(None).unwrap();
+        assert_eq!(resolved.s3.bucket, "test-bucket");
+        assert_eq!(resolved.encryption.salt, "a-stable-test-salt");
+    }
+
+    #[test]
+    fn an_unknown_profile_name_is_reported() {
+        let key_file = tempfile::NamedTempFile::new().unwrap();
+        let config = valid_config(key_file.path().to_str().unwrap().to_string());
+
+        let err = config.profile(Some("nope")).unwrap_err().to_string();
+        assert!(err.contains("nope"), "{}", err);
+    }
+
+    #[test]
+    fn a_profile_overriding_only_s3_still_falls_back_to_the_default_encryption_and_cache() {
+        let key_file = tempfile::NamedTempFile::new().unwrap();
+        let mut config = valid_config(key_file.path().to_str().unwrap().to_string());
+        config.profiles.insert(
+            "personal".into(),
+            ProfileOverride {
+                s3: Some(S3Config { bucket: "personal-bucket".into(), ..config.s3.clone() }),
+                encryption: None,
+                cache: None,
+            },
+        );
+
+        let resolved = config.profile(Some("personal")).unwrap();
+        assert_eq!(resolved.s3.bucket, "personal-bucket");
+        assert_eq!(resolved.encryption.salt, "a-stable-test-salt");
+        assert_eq!(resolved.cache.max_entries, config.cache.max_entries);
+    }
+
+    #[test]
+    fn profiles_round_trip_through_toml() {
+        let mut key_file = tempfile::NamedTempFile::new().unwrap();
+        writeln!(key_file, "passphrase").unwrap();
+
+        let mut toml_file = tempfile::NamedTempFile::new().unwrap();
+        writeln!(
+            toml_file,
+            r#"
+            [s3]
+            bucket = "default-bucket"
+            region = "us-east-1"
+
+            [encryption]
+            key_path = {:?}
+            salt = "a-stable-test-salt"
+
+            [profiles.work]
+            [profiles.work.s3]
+            bucket = "work-bucket"
+            region = "us-west-2"
+            "#,
+            key_file.path().to_str().unwrap()
+        )
+        .unwrap();
+
+        let config = Config::load(toml_file.path().to_str().unwrap()).unwrap();
+        assert_eq!(config.profile(None).unwrap().s3.bucket, "default-bucket");
+        assert_eq!(config.profile(Some("work")).unwrap().s3.bucket, "work-bucket");
+    }
+
+    #[test]
+    fn compression_defaults_to_disabled_when_the_table_is_omitted() {
+        let key_file = tempfile::NamedTempFile::new().unwrap();
+        let config = valid_config(key_file.path().to_str().unwrap().to_string());
+
+        assert!(!config.compression.enabled);
+        assert_eq!(config.compression.level, 3);
+    }
+
+    #[test]
+    fn an_explicit_compression_table_round_trips_through_toml() {
+        let mut key_file = tempfile::NamedTempFile::new().unwrap();
+        writeln!(key_file, "passphrase").unwrap();
+
+        let mut toml_file = tempfile::NamedTempFile::new().unwrap();
+        writeln!(
+            toml_file,
+            r#"
+            [s3]
+            bucket = "test-bucket"
+            region = "us-east-1"
+
+            [encryption]
+            key_path = {:?}
+            salt = "a-stable-test-salt"
+
+            [compression]
+            enabled = true
+            level = 9
+            "#,
+            key_file.path().to_str().unwrap()
+        )
+        .unwrap();
+
+        let config = Config::load(toml_file.path().to_str().unwrap()).unwrap();
+        assert!(config.compression.enabled);
+        assert_eq!(config.compression.level, 9);
+    }
+
+    #[test]
+    fn dedup_defaults_to_disabled_when_the_table_is_omitted() {
+        let key_file = tempfile::NamedTempFile::new().unwrap();
+        let config = valid_config(key_file.path().to_str().unwrap().to_string());
+
+        assert!(!config.dedup.enabled);
+    }
+
+    #[test]
+    fn an_explicit_dedup_table_round_trips_through_toml() {
+        let mut key_file = tempfile::NamedTempFile::new().unwrap();
+        writeln!(key_file, "passphrase").unwrap();
+
+        let mut toml_file = tempfile::NamedTempFile::new().unwrap();
+        writeln!(
+            toml_file,
+            r#"
+            [s3]
+            bucket = "test-bucket"
+            region = "us-east-1"
+
+            [encryption]
+            key_path = {:?}
+            salt = "a-stable-test-salt"
+
+            [dedup]
+            enabled = true
+            "#,
+            key_file.path().to_str().unwrap()
+        )
+        .unwrap();
+
+        let config = Config::load(toml_file.path().to_str().unwrap()).unwrap();
+        assert!(config.dedup.enabled);
+    }
+}
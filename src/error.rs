@@ -0,0 +1,137 @@
+use thiserror::Error;
+
+/// Errors that can surface from any layer of AegisFS (config, encryption,
+/// storage, or the FUSE filesystem glue).
+#[derive(Debug, Error)]
+pub enum AegisError {
+    #[error("storage backend error: {0}")]
+    Storage(String),
+
+    #[error("encryption error: {0}")]
+    Encryption(String),
+
+    #[error("config error: {0}")]
+    Config(String),
+
+    #[error("object not found: {0}")]
+    NotFound(String),
+
+    #[error("encryption key not found: {0}")]
+    MissingKey(String),
+
+    #[error("encryption key does not match this bucket (fingerprint mismatch against keystore object {0})")]
+    KeyMismatch(String),
+
+    #[error("invalid path: {0}")]
+    InvalidPath(String),
+
+    #[error("io error: {0}")]
+    Io(#[from] std::io::Error),
+
+    /// Another writer modified or deleted the object out from under an
+    /// operation that expected it unchanged — e.g. a different client
+    /// touching the bucket directly. See `blockstore::check_not_modified`.
+    #[error("conflict: {0}")]
+    Conflict(String),
+}
+
+pub type Result<T> = std::result::Result<T, AegisError>;
+
+impl AegisError {
+    /// Maps an AegisFS error onto the closest matching POSIX errno, for
+    /// returning from `fuser::Filesystem` callbacks.
+    pub fn to_errno(&self) -> i32 {
+        match self {
+            AegisError::NotFound(_) => libc::ENOENT,
+            AegisError::MissingKey(_) => libc::ENOENT,
+            AegisError::KeyMismatch(_) => libc::EACCES,
+            AegisError::InvalidPath(_) => libc::EINVAL,
+            AegisError::Storage(msg) => classify_storage_errno(msg),
+            AegisError::Encryption(_) => libc::EIO,
+            AegisError::Config(_) => libc::EINVAL,
+            AegisError::Io(_) => libc::EIO,
+            AegisError::Conflict(_) => libc::ESTALE,
+        }
+    }
+
+    /// A small, stable machine-readable identifier for this error,
+    /// suitable for the CLI's `--output json` error reporting. Stable
+    /// across releases: scripts may match on these strings.
+    pub fn code(&self) -> &'static str {
+        match self {
+            AegisError::NotFound(_) => "not_found",
+            AegisError::MissingKey(_) => "missing_key",
+            AegisError::KeyMismatch(_) => "key_mismatch",
+            AegisError::InvalidPath(_) => "invalid_path",
+            AegisError::Config(_) => "bad_config",
+            AegisError::Encryption(_) => "encryption_error",
+            AegisError::Io(_) => "io_error",
+            AegisError::Conflict(_) => "conflict",
+            AegisError::Storage(msg) => {
+                let lower = msg.to_lowercase();
+                if lower.contains("auth") || lower.contains("credential") || lower.contains("forbidden") {
+                    "backend_auth_failure"
+                } else {
+                    "storage_error"
+                }
+            }
+        }
+    }
+}
+
+/// By the time a backend error reaches [`AegisError::Storage`] it's
+/// already been flattened to a `String` (see the `.map_err` sites in
+/// `storage::s3`/`storage::gcs`), so this classifies it the same way
+/// [`AegisError::code`] does: by matching substrings the SDKs' own
+/// `Display` impls are known to produce for these cases. Access-denied
+/// and not-found are common enough that callers (e.g. `cp`, `rm`)
+/// behave very differently depending on which one they got back, so
+/// it's worth the extra digging over a blanket EIO.
+fn classify_storage_errno(msg: &str) -> i32 {
+    let lower = msg.to_lowercase();
+    if lower.contains("accessdenied") || lower.contains("access denied") || lower.contains("forbidden") || lower.contains("403") {
+        libc::EACCES
+    } else if lower.contains("nosuchkey") || lower.contains("nosuchbucket") || lower.contains("notfound") || lower.contains("not found") || lower.contains("404") {
+        libc::ENOENT
+    } else if lower.contains("slowdown")
+        || lower.contains("throttl")
+        || lower.contains("requestlimitexceeded")
+        || lower.contains("toomanyrequests")
+        || lower.contains("429")
+        || lower.contains("timed out")
+        || lower.contains("timeout")
+        || lower.contains("invalidobjectstate")
+        || lower.contains("object is archived")
+    {
+        libc::EAGAIN
+    } else {
+        libc::EIO
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn storage_errors_classify_by_message_substring() {
+        assert_eq!(AegisError::Storage("get a.txt: service error: AccessDenied".into()).to_errno(), libc::EACCES);
+        assert_eq!(AegisError::Storage("get a.txt: service error: NoSuchKey".into()).to_errno(), libc::ENOENT);
+        assert_eq!(AegisError::Storage("put a.txt: service error: SlowDown".into()).to_errno(), libc::EAGAIN);
+        assert_eq!(AegisError::Storage("put a.txt: connection reset".into()).to_errno(), libc::EIO);
+        assert_eq!(AegisError::Storage("get a.txt: request has timed out".into()).to_errno(), libc::EAGAIN);
+    }
+
+    #[test]
+    fn archived_glacier_object_maps_to_eagain() {
+        assert_eq!(
+            AegisError::Storage("get a.txt: dispatch failure: InvalidObjectState: object is archived".into()).to_errno(),
+            libc::EAGAIN
+        );
+    }
+
+    #[test]
+    fn conflict_maps_to_estale() {
+        assert_eq!(AegisError::Conflict("a.txt was modified externally".into()).to_errno(), libc::ESTALE);
+    }
+}
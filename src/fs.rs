@@ -0,0 +1,4391 @@
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+use fuser::{
+    FileAttr, FileType, Filesystem, ReplyAttr, ReplyCreate, ReplyData, ReplyDirectory,
+    ReplyEntry, ReplyStatfs, ReplyWrite, Request, TimeOrNow,
+};
+use libc::{EACCES, ENOENT};
+
+use crate::audit::AuditLog;
+use crate::config::{CompressionConfig, ConsistencyMode, DedupConfig};
+use crate::encryption::Encryptor;
+use crate::handles::{DirHandleTable, HandleTable, OpenFile};
+use crate::inode_table::InodeTable;
+use crate::journal::Journal;
+use crate::metadata::{FileKind, FileMetadata};
+use crate::metrics::Metrics;
+use crate::pathcrypt::PathCrypt;
+use crate::storage::StorageBackend;
+
+/// A cached getattr result under [`ConsistencyMode::Strong`]: re-used
+/// as long as the backend's ETag hasn't changed.
+struct CachedAttr {
+    etag: String,
+    size: u64,
+}
+
+/// Summary of one [`AegisFS::prefetch`] walk, for the `mount` CLI to
+/// log after a `--prefetch` warm-up completes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PrefetchStats {
+    pub directories: u64,
+    pub files: u64,
+    pub objects_visited: usize,
+    /// `true` if `max_depth` or `max_objects` cut the walk short before
+    /// it covered the whole bucket.
+    pub truncated: bool,
+}
+
+/// Name of the sidecar object used to represent an otherwise-empty
+/// directory. Centralized here so every place that needs to recognize,
+/// create, or skip a marker agrees on the exact name.
+pub const DIR_MARKER: &str = ".dir";
+
+/// Top-level namespace a hard-linked file's content is migrated into
+/// the first time it's ever linked (see [`AegisFS::link`]), keyed by a
+/// random [`crate::links::new_content_id`] rather than any real path so
+/// a file later recreated at the vacated path never collides with it.
+/// Never shown to users: `readdir` on the mount root explicitly skips
+/// this name, the same way it already skips [`DIR_MARKER`].
+pub(crate) const CONTENT_PREFIX: &str = ".aegisfs-content";
+
+const ROOT_INO: u64 = 1;
+
+/// Fallback attr/entry TTL for a mount built without going through
+/// [`crate::config::Config`] (which has its own, possibly different,
+/// `cache.attr_ttl_secs`/`cache.entry_ttl_secs`). See
+/// [`AegisFS::with_cache_ttls`] for the consistency-vs-performance
+/// tradeoff these are tuning.
+const DEFAULT_TTL: Duration = Duration::from_secs(1);
+
+/// How long a directory's cached subdirectory count (used for `nlink`,
+/// see [`AegisFS::subdir_count`]) is trusted before the next `getattr`
+/// relists to refresh it. `nlink` is therefore only eventually
+/// consistent: a `mkdir`/`rmdir` done elsewhere may not be reflected
+/// for up to this long, an acceptable tradeoff against relisting the
+/// directory on every single `getattr`.
+const NLINK_CACHE_TTL: Duration = Duration::from_secs(5);
+
+/// Default cap on resident open-file write-back buffers when a mount is
+/// built without going through [`crate::config::Config`] (which has its
+/// own, possibly different, `cache.max_open_handles`).
+const DEFAULT_HANDLE_CAPACITY: usize = 256;
+
+/// How far past a handle's resident write-back buffer a write's offset
+/// can land before `write()` gives up on buffering it at all. Below
+/// this, growing the buffer to cover the gap and zero-filling it (so a
+/// later read sees the hole, matching a regular sparse file) is cheap
+/// enough. Above it — e.g. a `seek`-then-write pattern common for VM
+/// disk images and databases, landing gigabytes past the current end —
+/// that zero-fill would itself allocate and process the whole gap in
+/// memory, defeating the point of a sparse write. Past this point,
+/// `write()` instead flushes what's buffered and writes straight
+/// through to [`crate::blockstore::write_range_with_block_size`], which
+/// only ever touches the blocks the write actually overlaps and leaves
+/// the rest as an implicit hole.
+const SPARSE_WRITE_GAP_THRESHOLD: u64 = 4 * 1024 * 1024;
+
+/// How directories are represented in the bucket.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DirectoryMode {
+    /// Every directory owns a `.dir` marker object (see [`DIR_MARKER`]).
+    #[default]
+    Marker,
+    /// Directories are inferred purely from key prefixes, matching the
+    /// convention used by the AWS console and most S3 tools: a
+    /// directory "exists" if anything is listed under its `prefix/`,
+    /// optionally including a zero-byte `prefix/` object. `mkdir`
+    /// creates that zero-byte object; there is no `.dir` marker.
+    Implicit,
+}
+
+/// AegisFS mounts a single S3 bucket as an encrypted POSIX filesystem.
+///
+/// Object keys double as paths: `"docs/readme.txt"` is a regular file,
+/// and, in [`DirectoryMode::Marker`], `"docs/.dir"` is the marker
+/// object that makes the otherwise-empty `"docs/"` prefix show up as a
+/// directory.
+pub struct AegisFS {
+    storage: Arc<dyn StorageBackend>,
+    encryptor: Encryptor,
+    inodes: InodeTable,
+    dir_mode: DirectoryMode,
+    /// Object-key prefix this mount is rooted at, e.g. `"tenants/acme"`
+    /// for a `--prefix tenants/acme` mount of a shared bucket. Logical
+    /// paths tracked in `inodes` never include it; it's spliced in only
+    /// at the point of talking to `storage`.
+    key_prefix: String,
+    /// When set, every path component is encrypted (see
+    /// [`PathCrypt`]) before being spliced into an object key, so the
+    /// backend never sees a plaintext file or directory name. `None`
+    /// (the default) keeps today's plaintext-path layout.
+    path_crypt: Option<PathCrypt>,
+    consistency_mode: ConsistencyMode,
+    attr_cache: Mutex<HashMap<String, CachedAttr>>,
+    /// Cached subdirectory counts keyed by directory path, for `nlink`.
+    /// See [`Self::subdir_count`] and [`NLINK_CACHE_TTL`].
+    nlink_cache: Mutex<HashMap<String, (u64, Instant)>>,
+    /// Per-handle write-back buffers populated by `open` and flushed by
+    /// `write`'s eviction path, `flush`, and `release`, so a file
+    /// written byte-by-byte produces one upload instead of one per
+    /// `write` syscall.
+    handles: Arc<HandleTable>,
+    /// Per-handle directory listing snapshots populated by `opendir` and
+    /// consumed by `readdir`/`releasedir`. See [`DirHandleTable`].
+    dir_handles: Arc<DirHandleTable>,
+    /// Reused across every FUSE callback's `block_on`, instead of
+    /// spinning up a fresh multi-threaded runtime per syscall.
+    runtime: tokio::runtime::Runtime,
+    /// Op counters, shared with the storage backend where it also
+    /// records backend requests and cache hits. See [`crate::metrics`].
+    metrics: Arc<Metrics>,
+    /// When set, every mutating op (`write`, `create`, `unlink`,
+    /// `mkdir`, `rmdir`, `rename`, `setattr`) returns `EROFS` without
+    /// touching the backend. See [`Self::with_read_only`].
+    read_only: bool,
+    /// How long the kernel trusts a `getattr` reply before
+    /// revalidating. See [`Self::with_cache_ttls`].
+    attr_ttl: Duration,
+    /// How long the kernel trusts a `lookup`/`mkdir`/`create` dentry
+    /// before revalidating. See [`Self::with_cache_ttls`].
+    entry_ttl: Duration,
+    /// Whether (and how aggressively) newly-written blocks are
+    /// zstd-compressed before encryption. See [`Self::with_compression`]
+    /// and `blockstore::write_range`.
+    compression: CompressionConfig,
+    /// Whether a flushed file's content is hashed and deduplicated
+    /// against an existing content object before being uploaded. See
+    /// [`Self::with_dedup`] and [`Self::try_dedup`].
+    dedup: DedupConfig,
+    /// Block size new files are created with (see [`crate::blockstore`])
+    /// and the `blksize` reported to the kernel. See
+    /// [`Self::with_block_size`] and `Config::block_size`.
+    block_size: u64,
+    /// When set, every instrumented op (the ones with a `metrics.op_*`
+    /// counter) also records a compliance access event against it. See
+    /// [`Self::with_audit_log`] and `crate::audit`.
+    audit: Option<Arc<AuditLog>>,
+    /// When set, every write-back buffer is durably recorded locally
+    /// before it's uploaded, and replayed on the next mount if the
+    /// upload never landed. See [`Self::with_journal`] and `crate::journal`.
+    journal: Option<Arc<Journal>>,
+}
+
+/// Everything needed to flush a mount's dirty write-back buffers from
+/// outside the [`AegisFS`] value itself, obtained via
+/// [`AegisFS::shutdown_handle`] before the filesystem is handed off to
+/// [`fuser::spawn_mount2`] (which takes it by value and runs it on a
+/// background thread with no way back in).
+pub struct ShutdownHandle {
+    storage: Arc<dyn StorageBackend>,
+    encryptor: Encryptor,
+    runtime: tokio::runtime::Handle,
+    key_prefix: String,
+    path_crypt: Option<PathCrypt>,
+    compression: CompressionConfig,
+    dedup: DedupConfig,
+    handles: Arc<HandleTable>,
+    block_size: u64,
+    journal: Option<Arc<Journal>>,
+}
+
+impl ShutdownHandle {
+    /// Uploads every dirty write-back buffer still resident, so a
+    /// graceful shutdown doesn't lose unflushed writes. Returns how
+    /// many handles were dirty and successfully flushed, for logging.
+    pub fn flush_all(&self) -> usize {
+        let mut flushed = 0;
+        for open in self.handles.take_all() {
+            if !open.dirty {
+                continue;
+            }
+            if AegisFS::flush_open_file(
+                &self.storage,
+                &self.encryptor,
+                &self.runtime,
+                &self.key_prefix,
+                self.path_crypt.as_ref(),
+                &self.compression,
+                &self.dedup,
+                &open,
+                self.block_size,
+                self.journal.as_ref(),
+            )
+            .is_ok()
+            {
+                flushed += 1;
+            }
+        }
+        flushed
+    }
+
+    /// Uploads `fh`'s write-back buffer if it's still dirty, clearing
+    /// the dirty flag on success — the same guarantee
+    /// `AegisFS::flush_handle` gives a live mount, but usable from the
+    /// background write-back flusher, which only has this handle's
+    /// clone, not the original `AegisFS`. A no-op for an unknown or
+    /// already-clean handle.
+    fn flush_one(&self, fh: u64) {
+        self.handles.with_handle(fh, |open| {
+            if !open.dirty {
+                return;
+            }
+            let result = AegisFS::flush_open_file(
+                &self.storage,
+                &self.encryptor,
+                &self.runtime,
+                &self.key_prefix,
+                self.path_crypt.as_ref(),
+                &self.compression,
+                &self.dedup,
+                open,
+                self.block_size,
+                self.journal.as_ref(),
+            );
+            if result.is_ok() {
+                open.mark_clean();
+            }
+        });
+    }
+
+    /// Runs forever on the calling thread, periodically uploading
+    /// write-back buffers that have sat dirty for at least
+    /// `flush_interval`, and — independently — eagerly flushing the
+    /// largest dirty buffers first whenever the total dirty bytes
+    /// across every handle exceeds `dirty_bytes_limit`, until back
+    /// under it. Bounds both the data-loss window for a file held open
+    /// indefinitely (a log writer, a database) and worst-case memory
+    /// growth from several such files being written at once. Meant to
+    /// be run on a dedicated thread; see [`AegisFS::spawn_writeback_flusher`].
+    fn run_writeback_flusher(&self, flush_interval: Duration, dirty_bytes_limit: u64) {
+        // Polls well inside `flush_interval` so a buffer isn't kept
+        // waiting much past its configured age before being picked up,
+        // without busy-looping when the interval is configured short.
+        let poll_interval = (flush_interval / 4).clamp(Duration::from_secs(1), flush_interval.max(Duration::from_secs(1)));
+        loop {
+            std::thread::sleep(poll_interval);
+
+            let dirty = self.handles.dirty_snapshot();
+            for fh in handles_due_for_flush(&dirty, Instant::now(), flush_interval, dirty_bytes_limit) {
+                self.flush_one(fh);
+            }
+        }
+    }
+}
+
+/// Decides which handles in `dirty` the write-back flusher should flush
+/// this tick: anything individually dirty for at least `flush_interval`,
+/// plus — independent of age — the largest dirty buffers first, until
+/// the total dirty bytes remaining would be at or under
+/// `dirty_bytes_limit`. A free function, rather than a method on
+/// [`ShutdownHandle`], so the policy can be tested against a synthetic
+/// snapshot without a real thread or real sleeps.
+fn handles_due_for_flush(dirty: &[(u64, usize, Instant)], now: Instant, flush_interval: Duration, dirty_bytes_limit: u64) -> Vec<u64> {
+    let mut due: Vec<u64> = dirty.iter().filter(|&&(_, _, since)| now.duration_since(since) >= flush_interval).map(|&(fh, _, _)| fh).collect();
+
+    let total_dirty_bytes: u64 = dirty.iter().map(|&(_, len, _)| len as u64).sum();
+    if total_dirty_bytes > dirty_bytes_limit {
+        let mut by_size: Vec<&(u64, usize, Instant)> = dirty.iter().collect();
+        by_size.sort_by(|a, b| b.1.cmp(&a.1));
+        let mut remaining = total_dirty_bytes;
+        for &&(fh, len, _) in &by_size {
+            if remaining <= dirty_bytes_limit {
+                break;
+            }
+            if !due.contains(&fh) {
+                due.push(fh);
+            }
+            remaining = remaining.saturating_sub(len as u64);
+        }
+    }
+
+    due
+}
+
+impl AegisFS {
+    pub fn new(storage: Arc<dyn StorageBackend>, encryptor: Encryptor) -> Self {
+        Self::with_dir_mode(storage, encryptor, DirectoryMode::default())
+    }
+
+    pub fn with_dir_mode(storage: Arc<dyn StorageBackend>, encryptor: Encryptor, dir_mode: DirectoryMode) -> Self {
+        Self::with_prefix(storage, encryptor, dir_mode, String::new())
+    }
+
+    pub fn with_prefix(
+        storage: Arc<dyn StorageBackend>,
+        encryptor: Encryptor,
+        dir_mode: DirectoryMode,
+        key_prefix: String,
+    ) -> Self {
+        Self::with_consistency_mode(storage, encryptor, dir_mode, key_prefix, ConsistencyMode::default())
+    }
+
+    pub fn with_consistency_mode(
+        storage: Arc<dyn StorageBackend>,
+        encryptor: Encryptor,
+        dir_mode: DirectoryMode,
+        key_prefix: String,
+        consistency_mode: ConsistencyMode,
+    ) -> Self {
+        Self::with_handle_capacity(storage, encryptor, dir_mode, key_prefix, consistency_mode, DEFAULT_HANDLE_CAPACITY)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn with_handle_capacity(
+        storage: Arc<dyn StorageBackend>,
+        encryptor: Encryptor,
+        dir_mode: DirectoryMode,
+        key_prefix: String,
+        consistency_mode: ConsistencyMode,
+        handle_capacity: usize,
+    ) -> Self {
+        Self::with_path_crypt(storage, encryptor, dir_mode, key_prefix, consistency_mode, handle_capacity, None)
+    }
+
+    /// As [`Self::with_handle_capacity`], additionally taking the
+    /// filename-encryption key derived from `EncryptionConfig`. Pass
+    /// `None` to keep today's plaintext-path layout.
+    #[allow(clippy::too_many_arguments)]
+    pub fn with_path_crypt(
+        storage: Arc<dyn StorageBackend>,
+        encryptor: Encryptor,
+        dir_mode: DirectoryMode,
+        key_prefix: String,
+        consistency_mode: ConsistencyMode,
+        handle_capacity: usize,
+        path_crypt: Option<PathCrypt>,
+    ) -> Self {
+        Self::with_metrics(
+            storage,
+            encryptor,
+            dir_mode,
+            key_prefix,
+            consistency_mode,
+            handle_capacity,
+            path_crypt,
+            Arc::new(Metrics::default()),
+        )
+    }
+
+    /// As [`Self::with_path_crypt`], additionally taking the op
+    /// counters this mount should record into. Embedders that want to
+    /// scrape the same counters the storage backend reports into (e.g.
+    /// via [`crate::metrics::serve`]) should build that `Arc` first and
+    /// pass a clone of it to both.
+    #[allow(clippy::too_many_arguments)]
+    pub fn with_metrics(
+        storage: Arc<dyn StorageBackend>,
+        encryptor: Encryptor,
+        dir_mode: DirectoryMode,
+        key_prefix: String,
+        consistency_mode: ConsistencyMode,
+        handle_capacity: usize,
+        path_crypt: Option<PathCrypt>,
+        metrics: Arc<Metrics>,
+    ) -> Self {
+        Self::with_read_only(
+            storage,
+            encryptor,
+            dir_mode,
+            key_prefix,
+            consistency_mode,
+            handle_capacity,
+            path_crypt,
+            metrics,
+            false,
+        )
+    }
+
+    /// As [`Self::with_metrics`], additionally taking whether the mount
+    /// should refuse every mutating op with `EROFS` instead of touching
+    /// the backend. See `config::Config::read_only`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn with_read_only(
+        storage: Arc<dyn StorageBackend>,
+        encryptor: Encryptor,
+        dir_mode: DirectoryMode,
+        key_prefix: String,
+        consistency_mode: ConsistencyMode,
+        handle_capacity: usize,
+        path_crypt: Option<PathCrypt>,
+        metrics: Arc<Metrics>,
+        read_only: bool,
+    ) -> Self {
+        Self::with_cache_ttls(
+            storage,
+            encryptor,
+            dir_mode,
+            key_prefix,
+            consistency_mode,
+            handle_capacity,
+            path_crypt,
+            metrics,
+            read_only,
+            DEFAULT_TTL,
+            DEFAULT_TTL,
+        )
+    }
+
+    /// As [`Self::with_read_only`], additionally taking the attr/entry
+    /// cache TTLs reported to the kernel on every `getattr`/`lookup`
+    /// reply. See `config::CacheConfig::attr_ttl_secs` and
+    /// `entry_ttl_secs`.
+    ///
+    /// Raising these cuts revalidation round-trips against a
+    /// high-latency backend like S3, at the cost of a longer window in
+    /// which the kernel can serve a stale size/mtime/nlink, or a stale
+    /// negative lookup, after a change made through another mount.
+    /// Lowering them (down to zero) tightens that window back up at the
+    /// cost of relisting more. `entry_ttl` only governs
+    /// `lookup`/`mkdir`/`create` replies; because `fuser::ReplyEntry`
+    /// accepts a single TTL applied to both the entry and its attrs, a
+    /// freshly-resolved entry's attrs can't usefully outlive
+    /// `entry_ttl` even when `attr_ttl` (used by plain `getattr`) is
+    /// set higher.
+    #[allow(clippy::too_many_arguments)]
+    pub fn with_cache_ttls(
+        storage: Arc<dyn StorageBackend>,
+        encryptor: Encryptor,
+        dir_mode: DirectoryMode,
+        key_prefix: String,
+        consistency_mode: ConsistencyMode,
+        handle_capacity: usize,
+        path_crypt: Option<PathCrypt>,
+        metrics: Arc<Metrics>,
+        read_only: bool,
+        attr_ttl: Duration,
+        entry_ttl: Duration,
+    ) -> Self {
+        Self::with_compression(
+            storage,
+            encryptor,
+            dir_mode,
+            key_prefix,
+            consistency_mode,
+            handle_capacity,
+            path_crypt,
+            metrics,
+            read_only,
+            attr_ttl,
+            entry_ttl,
+            CompressionConfig::default(),
+        )
+    }
+
+    /// As [`Self::with_cache_ttls`], additionally taking the
+    /// zstd-compression settings applied to newly-written blocks. See
+    /// `config::CompressionConfig` and `blockstore::write_range`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn with_compression(
+        storage: Arc<dyn StorageBackend>,
+        encryptor: Encryptor,
+        dir_mode: DirectoryMode,
+        key_prefix: String,
+        consistency_mode: ConsistencyMode,
+        handle_capacity: usize,
+        path_crypt: Option<PathCrypt>,
+        metrics: Arc<Metrics>,
+        read_only: bool,
+        attr_ttl: Duration,
+        entry_ttl: Duration,
+        compression: CompressionConfig,
+    ) -> Self {
+        Self::with_dedup(
+            storage,
+            encryptor,
+            dir_mode,
+            key_prefix,
+            consistency_mode,
+            handle_capacity,
+            path_crypt,
+            metrics,
+            read_only,
+            attr_ttl,
+            entry_ttl,
+            compression,
+            DedupConfig::default(),
+        )
+    }
+
+    /// As [`Self::with_compression`], additionally taking the
+    /// content-deduplication settings applied on flush. See
+    /// `config::DedupConfig` and [`Self::try_dedup`].
+    #[allow(clippy::too_many_arguments)]
+    pub fn with_dedup(
+        storage: Arc<dyn StorageBackend>,
+        encryptor: Encryptor,
+        dir_mode: DirectoryMode,
+        key_prefix: String,
+        consistency_mode: ConsistencyMode,
+        handle_capacity: usize,
+        path_crypt: Option<PathCrypt>,
+        metrics: Arc<Metrics>,
+        read_only: bool,
+        attr_ttl: Duration,
+        entry_ttl: Duration,
+        compression: CompressionConfig,
+        dedup: DedupConfig,
+    ) -> Self {
+        Self::with_block_size(
+            storage,
+            encryptor,
+            dir_mode,
+            key_prefix,
+            consistency_mode,
+            handle_capacity,
+            path_crypt,
+            metrics,
+            read_only,
+            attr_ttl,
+            entry_ttl,
+            compression,
+            dedup,
+            crate::blockstore::BLOCK_SIZE,
+        )
+    }
+
+    /// As [`Self::with_dedup`], additionally taking the block size new
+    /// files are created with and the `blksize` reported to the kernel.
+    /// See `Config::block_size`. Only affects files created after this
+    /// mount starts; an existing file keeps using whatever block size
+    /// its header was created with.
+    #[allow(clippy::too_many_arguments)]
+    pub fn with_block_size(
+        storage: Arc<dyn StorageBackend>,
+        encryptor: Encryptor,
+        dir_mode: DirectoryMode,
+        key_prefix: String,
+        consistency_mode: ConsistencyMode,
+        handle_capacity: usize,
+        path_crypt: Option<PathCrypt>,
+        metrics: Arc<Metrics>,
+        read_only: bool,
+        attr_ttl: Duration,
+        entry_ttl: Duration,
+        compression: CompressionConfig,
+        dedup: DedupConfig,
+        block_size: u64,
+    ) -> Self {
+        Self::with_audit_log(
+            storage,
+            encryptor,
+            dir_mode,
+            key_prefix,
+            consistency_mode,
+            handle_capacity,
+            path_crypt,
+            metrics,
+            read_only,
+            attr_ttl,
+            entry_ttl,
+            compression,
+            dedup,
+            block_size,
+            None,
+        )
+    }
+
+    /// As [`Self::with_block_size`], additionally taking the compliance
+    /// access trail every instrumented op records into when set. See
+    /// `config::AuditConfig` and [`crate::audit::AuditLog`].
+    #[allow(clippy::too_many_arguments)]
+    pub fn with_audit_log(
+        storage: Arc<dyn StorageBackend>,
+        encryptor: Encryptor,
+        dir_mode: DirectoryMode,
+        key_prefix: String,
+        consistency_mode: ConsistencyMode,
+        handle_capacity: usize,
+        path_crypt: Option<PathCrypt>,
+        metrics: Arc<Metrics>,
+        read_only: bool,
+        attr_ttl: Duration,
+        entry_ttl: Duration,
+        compression: CompressionConfig,
+        dedup: DedupConfig,
+        block_size: u64,
+        audit: Option<Arc<AuditLog>>,
+    ) -> Self {
+        Self::with_journal(
+            storage,
+            encryptor,
+            dir_mode,
+            key_prefix,
+            consistency_mode,
+            handle_capacity,
+            path_crypt,
+            metrics,
+            read_only,
+            attr_ttl,
+            entry_ttl,
+            compression,
+            dedup,
+            block_size,
+            audit,
+            None,
+        )
+    }
+
+    /// As [`Self::with_audit_log`], additionally taking the local
+    /// write-ahead journal every write-back buffer is recorded into
+    /// before it's uploaded. See `config::JournalConfig` and
+    /// [`crate::journal::Journal`].
+    ///
+    /// If `journal` is set, [`crate::journal::Journal::replay`] runs
+    /// once here, before the mount serves its first request, so any
+    /// write left pending by an unclean shutdown reaches the backend
+    /// before anything else can observe the file it belongs to.
+    #[allow(clippy::too_many_arguments)]
+    pub fn with_journal(
+        storage: Arc<dyn StorageBackend>,
+        encryptor: Encryptor,
+        dir_mode: DirectoryMode,
+        key_prefix: String,
+        consistency_mode: ConsistencyMode,
+        handle_capacity: usize,
+        path_crypt: Option<PathCrypt>,
+        metrics: Arc<Metrics>,
+        read_only: bool,
+        attr_ttl: Duration,
+        entry_ttl: Duration,
+        compression: CompressionConfig,
+        dedup: DedupConfig,
+        block_size: u64,
+        audit: Option<Arc<AuditLog>>,
+        journal: Option<Arc<Journal>>,
+    ) -> Self {
+        let runtime = tokio::runtime::Runtime::new().expect("failed to start tokio runtime");
+        if let Some(journal) = &journal {
+            if let Err(e) = runtime.block_on(journal.replay(storage.as_ref(), &encryptor)) {
+                eprintln!("journal replay failed: {e}");
+            }
+        }
+
+        AegisFS {
+            storage,
+            encryptor,
+            inodes: InodeTable::new(),
+            dir_mode,
+            key_prefix: key_prefix.trim_matches('/').to_string(),
+            path_crypt,
+            consistency_mode,
+            attr_cache: Mutex::new(HashMap::new()),
+            nlink_cache: Mutex::new(HashMap::new()),
+            handles: Arc::new(HandleTable::new(handle_capacity)),
+            dir_handles: Arc::new(DirHandleTable::new()),
+            runtime,
+            metrics,
+            read_only,
+            attr_ttl,
+            entry_ttl,
+            compression,
+            dedup,
+            block_size,
+            audit,
+            journal,
+        }
+    }
+
+    /// The op counters this mount records into, for wiring up
+    /// [`crate::metrics::serve`] against the same instance the storage
+    /// backend (if it supports metrics) also reports into.
+    pub fn metrics(&self) -> Arc<Metrics> {
+        Arc::clone(&self.metrics)
+    }
+
+    /// Records one access event against `self.audit`, a no-op when no
+    /// audit log is configured. `req` is the `fuser::Request` every
+    /// `Filesystem` callback already receives, which carries the
+    /// calling uid/gid/pid.
+    fn audit(&self, req: &Request, op: &str, path: &str, success: bool) {
+        if let Some(audit) = &self.audit {
+            audit.record(req.uid(), req.gid(), req.pid(), op, path, success);
+        }
+    }
+
+    /// A handle to this mount's write-back state that outlives `self`,
+    /// for a caller (e.g. a signal handler) that needs to flush
+    /// everything still dirty after `self` has already been handed off
+    /// to [`fuser::spawn_mount2`] and is no longer reachable.
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        ShutdownHandle {
+            storage: Arc::clone(&self.storage),
+            encryptor: self.encryptor.clone(),
+            runtime: self.runtime.handle().clone(),
+            key_prefix: self.key_prefix.clone(),
+            path_crypt: self.path_crypt.clone(),
+            compression: self.compression.clone(),
+            dedup: self.dedup.clone(),
+            handles: Arc::clone(&self.handles),
+            block_size: self.block_size,
+            journal: self.journal.clone(),
+        }
+    }
+
+    /// Spawns a background thread that periodically uploads dirty
+    /// write-back buffers per `flush_interval`/`dirty_bytes_limit` —
+    /// see `config::CacheConfig::writeback_flush_interval_secs` and
+    /// `writeback_dirty_bytes_limit`, and
+    /// `ShutdownHandle::run_writeback_flusher` for the policy. The
+    /// thread runs for as long as the process does; there's no
+    /// cooperative way to stop it, since a mount's lifetime is the
+    /// process's (it's unmounted by killing the process or the kernel
+    /// tearing the mountpoint down, not by dropping an `AegisFS`).
+    pub fn spawn_writeback_flusher(&self, flush_interval: Duration, dirty_bytes_limit: u64) -> std::thread::JoinHandle<()> {
+        let shutdown = self.shutdown_handle();
+        std::thread::spawn(move || shutdown.run_writeback_flusher(flush_interval, dirty_bytes_limit))
+    }
+
+    /// Walks the bucket once via delimiter listing, breadth-first from
+    /// the mount root, populating the inode table (and, in
+    /// [`ConsistencyMode::Strong`], the attribute cache) before the
+    /// first real `lookup`/`readdir`/`getattr` ever arrives. This is
+    /// the expensive work `readdir` would otherwise pay piecemeal on a
+    /// cold mount's first `ls` of each directory; doing it once upfront
+    /// trades mount latency for snappier early use.
+    ///
+    /// Bounded on both axes so a huge bucket can't make `mount` hang
+    /// indefinitely: `max_depth` caps how many directory levels deep
+    /// the walk descends (the root is depth 0), and `max_objects` caps
+    /// the total number of files and directories visited. Either limit
+    /// being hit is reported via [`PrefetchStats::truncated`] rather
+    /// than treated as an error — a partial warm-up is still strictly
+    /// better than none.
+    pub fn prefetch(&self, max_depth: usize, max_objects: usize) -> PrefetchStats {
+        let mut stats = PrefetchStats::default();
+        let mut queue = std::collections::VecDeque::new();
+        queue.push_back((String::new(), 0usize));
+
+        while let Some((dir_path, depth)) = queue.pop_front() {
+            if stats.objects_visited >= max_objects {
+                stats.truncated = true;
+                break;
+            }
+
+            let logical_prefix = if dir_path.is_empty() { String::new() } else { format!("{}/", dir_path) };
+            let list_prefix = self.storage_key(&logical_prefix);
+
+            let listing = match self.block_on(self.storage.list_with_delimiter(&list_prefix, "/")) {
+                Ok(listing) => listing,
+                Err(_) => continue,
+            };
+
+            let mut seen = std::collections::HashSet::new();
+            let classified = listing
+                .objects
+                .iter()
+                .filter_map(|obj| Self::classify_listed_object(&obj.key[list_prefix.len()..]))
+                .chain(listing.common_prefixes.iter().map(|prefix| Self::classify_listed_prefix(&prefix[list_prefix.len()..])));
+
+            for (raw_name, kind) in classified {
+                if stats.objects_visited >= max_objects {
+                    stats.truncated = true;
+                    break;
+                }
+                let name = self.decrypt_listed_name(raw_name);
+                if name.is_empty() || Self::is_dir_marker(&name) || name == CONTENT_PREFIX || !seen.insert(name.clone()) {
+                    continue;
+                }
+                let child_path = Self::child_path(&dir_path, &name);
+                self.get_or_create_ino(&child_path);
+                stats.objects_visited += 1;
+
+                if kind == FileType::Directory {
+                    stats.directories += 1;
+                    if depth + 1 < max_depth {
+                        queue.push_back((child_path, depth + 1));
+                    } else {
+                        stats.truncated = true;
+                    }
+                } else {
+                    stats.files += 1;
+                    if self.consistency_mode == ConsistencyMode::Strong {
+                        if let Ok(size) = self.file_size(&child_path) {
+                            self.cache_attr(&child_path, size);
+                        }
+                    }
+                }
+            }
+        }
+
+        stats
+    }
+
+    /// Maps a logical, mount-relative path to the actual object key,
+    /// encrypting each path component first when filename encryption is
+    /// enabled, then splicing in `key_prefix` when one is configured.
+    fn storage_key(&self, path: &str) -> String {
+        Self::storage_key_with_prefix(&self.key_prefix, self.path_crypt.as_ref(), path)
+    }
+
+    /// Normalizes a mount-relative path before it's turned into a
+    /// storage key: collapses duplicate slashes and `.` components,
+    /// resolves `..` against what's been consumed so far, and rejects
+    /// any path that would climb above the mount root with
+    /// [`crate::error::AegisError::InvalidPath`]. Needed wherever a path arrives as a
+    /// single user-supplied string rather than being walked one
+    /// component at a time through `inode_table` (which can't produce
+    /// `..` on its own) — currently the `get`/`put` CLI subcommands.
+    pub fn normalize_path(path: &str) -> crate::error::Result<String> {
+        let mut components: Vec<&str> = Vec::new();
+        for part in path.split('/') {
+            match part {
+                "" | "." => {}
+                ".." => {
+                    if components.pop().is_none() {
+                        return Err(crate::error::AegisError::InvalidPath(format!("{:?} climbs above the mount root", path)));
+                    }
+                }
+                component => components.push(component),
+            }
+        }
+        Ok(components.join("/"))
+    }
+
+    /// As [`Self::storage_key`], but usable without a live `&self` (e.g.
+    /// from inside a write-back flush closure that only captured clones
+    /// of `key_prefix` and `path_crypt`, or from a CLI subcommand like
+    /// `get`/`put` that reads a single object without mounting).
+    pub fn storage_key_with_prefix(key_prefix: &str, path_crypt: Option<&PathCrypt>, path: &str) -> String {
+        let path = match path_crypt {
+            Some(path_crypt) if !path.is_empty() => path_crypt.encrypt_path(path),
+            _ => path.to_string(),
+        };
+        if key_prefix.is_empty() {
+            path
+        } else if path.is_empty() {
+            format!("{}/", key_prefix)
+        } else {
+            format!("{}/{}", key_prefix, path)
+        }
+    }
+
+    /// The logical (pre-`storage_key`) path of the content object
+    /// `content_id` identifies, for splicing through the same
+    /// `path_crypt`/`key_prefix` machinery as every other storage key.
+    fn content_object_path(content_id: &str) -> String {
+        format!("{}/{}", CONTENT_PREFIX, content_id)
+    }
+
+    /// Resolves the storage key a file's content actually lives at:
+    /// `path_key` itself if `meta.content_id` is `None` (the common,
+    /// never-linked case), or the shared content-addressed key if it's
+    /// `Some` (see [`Self::link`]). Free of `&self` so it's usable from
+    /// the static-style [`Self::flush_open_file`] eviction closure.
+    fn resolve_content_key(key_prefix: &str, path_crypt: Option<&PathCrypt>, path_key: &str, meta: &FileMetadata) -> String {
+        match &meta.content_id {
+            Some(id) => Self::storage_key_with_prefix(key_prefix, path_crypt, &Self::content_object_path(id)),
+            None => path_key.to_string(),
+        }
+    }
+
+    /// As [`Self::resolve_content_key`], but loading `path`'s own
+    /// metadata first, for callers that don't already have it loaded.
+    /// Tolerates a missing or corrupt sidecar by falling back to
+    /// `None` (i.e. "content lives at its own key"), matching
+    /// [`crate::metadata::load_or_default`]'s tolerance elsewhere — a
+    /// file predating hard links, or one some external tool wrote
+    /// directly, never has a sidecar claiming otherwise.
+    fn content_key(&self, path: &str) -> String {
+        let path_key = self.storage_key(path);
+        let meta = self.block_on(crate::metadata::load(self.storage.as_ref(), &path_key)).ok();
+        match meta.as_ref().and_then(|m| m.content_id.as_ref()) {
+            Some(id) => Self::storage_key_with_prefix(&self.key_prefix, self.path_crypt.as_ref(), &Self::content_object_path(id)),
+            None => path_key,
+        }
+    }
+
+    /// Returns `true` if `path` exists as a directory, checking the
+    /// marker object or the listing, depending on `dir_mode`.
+    fn is_directory(&self, path: &str) -> bool {
+        match self.dir_mode {
+            DirectoryMode::Marker => {
+                let marker_path = Self::child_path(path, DIR_MARKER);
+                self.block_on(self.storage.head(&self.storage_key(&marker_path))).is_ok()
+            }
+            DirectoryMode::Implicit => {
+                let list_prefix = self.storage_key(&format!("{}/", path));
+                matches!(self.block_on(self.storage.list(&list_prefix)), Ok(objs) if !objs.is_empty())
+            }
+        }
+    }
+
+    /// `true` if `path` has any real entry underneath it — a file or a
+    /// subdirectory — once the directory's own marker and internal
+    /// sidecars are filtered out, the same way [`Filesystem::readdir`]
+    /// classifies listed keys. `rmdir` uses this to refuse deleting a
+    /// non-empty directory instead of silently dropping its marker and
+    /// orphaning whatever was still inside, per POSIX `rmdir(2)`.
+    fn directory_has_children(&self, path: &str) -> crate::error::Result<bool> {
+        let logical_prefix = if path.is_empty() { String::new() } else { format!("{}/", path) };
+        let list_prefix = self.storage_key(&logical_prefix);
+        let listing = self.block_on(self.storage.list_with_delimiter(&list_prefix, "/"))?;
+
+        let mut classified = listing
+            .objects
+            .iter()
+            .filter_map(|obj| Self::classify_listed_object(&obj.key[list_prefix.len()..]))
+            .chain(listing.common_prefixes.iter().map(|prefix| Self::classify_listed_prefix(&prefix[list_prefix.len()..])));
+
+        Ok(classified.any(|(name, _)| !name.is_empty() && name != CONTENT_PREFIX))
+    }
+
+    /// Returns a file's plaintext length without downloading (or
+    /// decrypting) any of its content: the block store keeps this in
+    /// the small header object, so this is a single cheap fetch
+    /// regardless of how large the file actually is. `getattr`/`lookup`
+    /// use this so `ls -l` over a directory of large files costs one
+    /// header read per entry instead of a full download.
+    fn file_size(&self, path: &str) -> crate::error::Result<u64> {
+        self.block_on(crate::blockstore::total_len(self.storage.as_ref(), &self.content_key(path)))
+    }
+
+    /// Resolves `parent` to a path and verifies it's actually a known,
+    /// existing directory, so `create`/`mkdir` can't blindly compose a
+    /// key under a removed or bogus parent. Returns the errno to reply
+    /// with on failure.
+    fn resolve_existing_dir(&self, parent: u64) -> std::result::Result<String, i32> {
+        let parent_path = self.path_for_ino(parent).ok_or(ENOENT)?;
+        if parent == ROOT_INO || self.is_directory(&parent_path) {
+            return Ok(parent_path);
+        }
+        if self.is_file(&parent_path) {
+            return Err(libc::ENOTDIR);
+        }
+        Err(ENOENT)
+    }
+
+    /// The key `mkdir` should write to materialize an (otherwise empty)
+    /// directory, given the current `dir_mode`.
+    fn dir_placeholder_key(&self, path: &str) -> String {
+        Self::dir_placeholder_key_for_mode(self.dir_mode, path)
+    }
+
+    fn dir_placeholder_key_for_mode(mode: DirectoryMode, path: &str) -> String {
+        match mode {
+            DirectoryMode::Marker => Self::child_path(path, DIR_MARKER),
+            DirectoryMode::Implicit => format!("{}/", path),
+        }
+    }
+
+    /// Returns the inode for `path`, allocating a new one if this is the
+    /// first time it has been seen.
+    fn get_or_create_ino(&self, path: &str) -> u64 {
+        self.inodes.get_or_create_ino(path)
+    }
+
+    fn path_for_ino(&self, ino: u64) -> Option<String> {
+        self.inodes.path_for_ino(ino)
+    }
+
+    fn child_path(parent: &str, name: &str) -> String {
+        if parent.is_empty() {
+            name.to_string()
+        } else {
+            format!("{}/{}", parent, name)
+        }
+    }
+
+    /// True if `name` is the reserved directory marker and should never
+    /// be exposed to callers as a real filesystem entry.
+    pub(crate) fn is_dir_marker(name: &str) -> bool {
+        name == DIR_MARKER
+    }
+
+    /// Suffixes the block store and metadata sidecar append directly
+    /// onto a (possibly encrypted) path's last component. `readdir`
+    /// lists raw object keys, so these have to be peeled off before the
+    /// component underneath can be decrypted.
+    const LISTED_NAME_SUFFIXES: [&str; 3] = [".blockhdr", ".meta", ".block"];
+
+    /// True if `name` names an internal object (the directory marker, a
+    /// block store/metadata sidecar suffix, or the hard-link/dedup
+    /// content-addressed namespace) rather than something a user
+    /// created. These are implementation details of how files,
+    /// directories, and shared content are represented on the backend,
+    /// so `lookup`, `unlink`, `rename`, `create`, and `mkdir` all reject
+    /// them the same way `readdir` already hides them via
+    /// [`Self::classify_listed_object`] — letting a real object land on
+    /// one of these keys would corrupt an unrelated file's metadata or
+    /// block header, or plant/erase entries in [`CONTENT_PREFIX`]'s
+    /// content store.
+    fn is_reserved_name(name: &str) -> bool {
+        Self::is_dir_marker(name) || name == CONTENT_PREFIX || Self::LISTED_NAME_SUFFIXES.iter().any(|suffix| name.ends_with(suffix))
+    }
+
+    /// Collapses a single `readdir`-listed direct object key (one with
+    /// no further `/` under the directory's prefix — see
+    /// [`crate::storage::DelimitedListing::objects`]) into the one
+    /// logical entry a user should see, or `None` if the key has nothing
+    /// to show on its own: this directory's own `.dir` marker, or a key
+    /// that ends in none of the recognized sidecar suffixes.
+    ///
+    /// A file is stored as up to three sibling objects under the same
+    /// base name — `name.blockhdr`, `name.meta`, `name.block/<index>` —
+    /// so naively treating each listed key as its own entry would show
+    /// up to three bogus children per real file; this strips the
+    /// recognized sidecar suffix back off to get the one name a file
+    /// should be shown under.
+    pub(crate) fn classify_listed_object(rest: &str) -> Option<(&str, FileType)> {
+        if rest == DIR_MARKER {
+            return None;
+        }
+        Self::LISTED_NAME_SUFFIXES.iter().find_map(|suffix| rest.strip_suffix(suffix)).map(|base| (base, FileType::RegularFile))
+    }
+
+    /// Collapses a single `readdir`-listed common prefix (a nested group
+    /// — see [`crate::storage::DelimitedListing::common_prefixes`] —
+    /// with `prefix` already stripped) into the one logical entry a
+    /// user should see.
+    ///
+    /// A file's blocks are grouped under `name.block/`, indistinguishable
+    /// from a real subdirectory without checking for this suffix; every
+    /// other group is a real subdirectory (its own `.dir` marker nests
+    /// one level deeper than the group itself).
+    pub(crate) fn classify_listed_prefix(prefix: &str) -> (&str, FileType) {
+        let name = prefix.strip_suffix('/').unwrap_or(prefix);
+        match name.strip_suffix(".block") {
+            Some(base) => (base, FileType::RegularFile),
+            None => (name, FileType::Directory),
+        }
+    }
+
+    /// Recovers the logical name of a `readdir`-listed entry. A no-op
+    /// when filename encryption is off; otherwise strips any recognized
+    /// suffix, decrypts the component underneath, and reattaches the
+    /// suffix. Falls back to the raw (still-encrypted) name if it
+    /// doesn't decrypt, rather than dropping the entry entirely.
+    fn decrypt_listed_name(&self, name: &str) -> String {
+        let path_crypt = match &self.path_crypt {
+            Some(path_crypt) => path_crypt,
+            None => return name.to_string(),
+        };
+        for suffix in Self::LISTED_NAME_SUFFIXES {
+            if let Some(encoded) = name.strip_suffix(suffix) {
+                if let Ok(decrypted) = path_crypt.decrypt_component(encoded) {
+                    return format!("{}{}", decrypted, suffix);
+                }
+            }
+        }
+        path_crypt.decrypt_component(name).unwrap_or_else(|_| name.to_string())
+    }
+
+    fn dir_attr(ino: u64, nlink: u32, block_size: u32) -> FileAttr {
+        let now = SystemTime::now();
+        FileAttr {
+            ino,
+            size: 0,
+            blocks: 0,
+            atime: now,
+            mtime: now,
+            ctime: now,
+            crtime: now,
+            kind: FileType::Directory,
+            perm: 0o755,
+            nlink,
+            uid: 0,
+            gid: 0,
+            rdev: 0,
+            blksize: block_size,
+            flags: 0,
+        }
+    }
+
+    fn file_attr(ino: u64, size: u64, block_size: u32) -> FileAttr {
+        let now = SystemTime::now();
+        FileAttr {
+            ino,
+            size,
+            blocks: (size + 511) / 512,
+            atime: now,
+            mtime: now,
+            ctime: now,
+            crtime: now,
+            kind: FileType::RegularFile,
+            perm: 0o644,
+            nlink: 1,
+            uid: 0,
+            gid: 0,
+            rdev: 0,
+            blksize: block_size,
+            flags: 0,
+        }
+    }
+
+    /// As [`Self::file_attr`], but reporting `meta`'s mode/owner/times
+    /// instead of the hardcoded defaults, `meta.kind` instead of always
+    /// reporting a regular file, and the caller-supplied `nlink`
+    /// (mirroring [`Self::dir_attr_from_meta`]) instead of a hardcoded
+    /// `1` — see [`Self::file_nlink`] for how a hard-linked file's real
+    /// count is resolved.
+    fn file_attr_from_meta(ino: u64, size: u64, meta: &FileMetadata, nlink: u32, block_size: u32) -> FileAttr {
+        let kind = match meta.kind {
+            FileKind::Symlink => FileType::Symlink,
+            FileKind::File | FileKind::Directory => FileType::RegularFile,
+        };
+        FileAttr {
+            ino,
+            size,
+            blocks: (size + 511) / 512,
+            atime: meta.atime(),
+            mtime: meta.mtime(),
+            ctime: meta.ctime(),
+            crtime: meta.ctime(),
+            kind,
+            perm: meta.mode as u16,
+            nlink,
+            uid: meta.uid,
+            gid: meta.gid,
+            rdev: 0,
+            blksize: block_size,
+            flags: 0,
+        }
+    }
+
+    /// As [`Self::dir_attr`], but reporting `meta`'s mode/owner/times
+    /// instead of the hardcoded defaults.
+    fn dir_attr_from_meta(ino: u64, meta: &FileMetadata, nlink: u32, block_size: u32) -> FileAttr {
+        FileAttr {
+            ino,
+            size: 0,
+            blocks: 0,
+            atime: meta.atime(),
+            mtime: meta.mtime(),
+            ctime: meta.ctime(),
+            crtime: meta.ctime(),
+            kind: FileType::Directory,
+            perm: meta.mode as u16,
+            nlink,
+            uid: meta.uid,
+            gid: meta.gid,
+            rdev: 0,
+            blksize: block_size,
+            flags: 0,
+        }
+    }
+
+    fn block_on<F: std::future::Future>(&self, future: F) -> F::Output {
+        self.runtime.block_on(future)
+    }
+
+    /// Counts `path`'s immediate subdirectories via a delimiter-based
+    /// listing, so [`Self::nlink_for_dir`] can report correct Unix
+    /// `nlink` semantics (`2 + subdirectory_count`) instead of always
+    /// claiming `2`. A `.block/` group under a file sibling also shows
+    /// up as a `CommonPrefixes` entry, so entries are run back through
+    /// [`Self::classify_listed_prefix`] the same way `readdir` does, to
+    /// tell a real subdirectory from one of those.
+    ///
+    /// Cached for [`NLINK_CACHE_TTL`] so `getattr` isn't O(listing) on
+    /// every call; see that constant for the staleness tradeoff.
+    fn subdir_count(&self, path: &str) -> u64 {
+        if let Some((count, fetched_at)) = self.nlink_cache.lock().unwrap().get(path) {
+            if fetched_at.elapsed() < NLINK_CACHE_TTL {
+                return *count;
+            }
+        }
+
+        let logical_prefix = if path.is_empty() { String::new() } else { format!("{}/", path) };
+        let list_prefix = self.storage_key(&logical_prefix);
+        let count = match self.block_on(self.storage.list_with_delimiter(&list_prefix, "/")) {
+            Ok(listing) => listing
+                .common_prefixes
+                .iter()
+                .filter(|prefix| Self::classify_listed_prefix(&prefix[list_prefix.len()..]).1 == FileType::Directory)
+                .count() as u64,
+            Err(_) => 0,
+        };
+        self.nlink_cache.lock().unwrap().insert(path.to_string(), (count, Instant::now()));
+        count
+    }
+
+    /// `path`'s `nlink` under correct Unix directory semantics: itself,
+    /// its parent's entry for it, and one more per subdirectory.
+    fn nlink_for_dir(&self, path: &str) -> u32 {
+        2 + self.subdir_count(path) as u32
+    }
+
+    /// A file's `nlink`: `1` unless `meta.content_linked` is set, in
+    /// which case it's the live count of paths sharing that content via
+    /// a real hard link (see [`crate::links`]) — `content_key` is
+    /// assumed already resolved from `meta`, so a caller that's just
+    /// called [`Self::resolve_content_key`]/[`Self::content_key`]
+    /// doesn't pay for it twice. A `content_id` with `content_linked`
+    /// still `false` is only a dedup match, not a hard link — those
+    /// paths are logically independent files, so this deliberately
+    /// reports `1` rather than the storage-level refcount, which would
+    /// otherwise leak an implementation detail of dedup into `stat(2)`.
+    fn file_nlink(&self, meta: &FileMetadata, content_key: &str) -> u32 {
+        if meta.content_linked {
+            self.block_on(crate::links::read_nlink(self.storage.as_ref(), content_key))
+        } else {
+            1
+        }
+    }
+
+    /// Total bytes and file count across every object under this
+    /// mount's prefix, for [`Self::statfs`]. Counts `.blockhdr` objects
+    /// rather than deduping every listed key, since each logical file
+    /// contributes exactly one. Returns `(0, 0)` if the listing fails,
+    /// rather than surfacing an error `df` has no good way to show.
+    fn usage_totals(&self) -> (u64, u64) {
+        let list_prefix = self.storage_key("");
+        match self.block_on(self.storage.list(&list_prefix)) {
+            Ok(objects) => {
+                let used_bytes: u64 = objects.iter().map(|o| o.size).sum();
+                let file_count = objects.iter().filter(|o| o.key.ends_with(".blockhdr")).count() as u64;
+                (used_bytes, file_count)
+            }
+            Err(_) => (0, 0),
+        }
+    }
+
+    /// Copies every storage object backing `path` to the equivalent key
+    /// under `dst_path`, leaving the originals in place. For a file
+    /// that's its header and blocks ([`crate::blockstore::copy_all`]);
+    /// for a directory, every nested file is copied the same way (its
+    /// block ciphertext is bound to its own path as AAD, so it can't
+    /// just be carried over key-for-key), and everything else under the
+    /// prefix (markers, metadata sidecars) is plain JSON or empty and
+    /// copied key-for-key with the prefix swapped.
+    ///
+    /// A hard-linked file (`content_id` set — see [`Self::link`]) has
+    /// nothing at its own key to copy; instead this registers one more
+    /// reference to its shared content for `dst_path`, about to point
+    /// at it too. Paired with [`Self::delete_tree`] releasing `path`'s
+    /// own reference right after, `rename`'s "copy then delete" nets to
+    /// zero change in the shared refcount — a pure move of one name.
+    fn copy_tree(&self, path: &str, dst_path: &str, is_dir: bool) -> std::result::Result<(), i32> {
+        if is_dir {
+            let list_prefix = self.storage_key(&format!("{}/", path));
+            let dst_prefix = self.storage_key(&format!("{}/", dst_path));
+            let objects = self.block_on(self.storage.list(&list_prefix)).map_err(|e| e.to_errno())?;
+
+            let mut file_base_keys = std::collections::HashSet::new();
+            for obj in &objects {
+                if let Some(base) = crate::blockstore::base_key_of_block(&obj.key) {
+                    file_base_keys.insert(base.to_string());
+                } else if let Some(base) = obj.key.strip_suffix(".blockhdr") {
+                    file_base_keys.insert(base.to_string());
+                }
+            }
+            for base in &file_base_keys {
+                let rest = &base[list_prefix.len()..];
+                let dst_base = format!("{}{}", dst_prefix, rest);
+                self.block_on(crate::blockstore::copy_all(self.storage.as_ref(), &self.encryptor, base, &dst_base))
+                    .map_err(|e| e.to_errno())?;
+            }
+            for obj in objects {
+                if obj.key.ends_with(".blockhdr") || crate::blockstore::base_key_of_block(&obj.key).is_some() {
+                    continue;
+                }
+                let rest = &obj.key[list_prefix.len()..];
+                let dst_key = format!("{}{}", dst_prefix, rest);
+                self.block_on(self.storage.copy(&obj.key, &dst_key)).map_err(|e| e.to_errno())?;
+            }
+            // The directory's own metadata sidecar sits at `{path}.meta`,
+            // alongside the directory rather than nested under its
+            // prefix, so the listing above never sees it.
+            let _ = self.block_on(self.storage.copy(
+                &format!("{}.meta", self.storage_key(path)),
+                &format!("{}.meta", self.storage_key(dst_path)),
+            ));
+            Ok(())
+        } else {
+            let path_key = self.storage_key(path);
+            let dst_key = self.storage_key(dst_path);
+            let meta = self.block_on(crate::metadata::load(self.storage.as_ref(), &path_key)).ok();
+
+            match meta.as_ref().and_then(|m| m.content_id.as_ref()) {
+                Some(id) => {
+                    let content_key = Self::storage_key_with_prefix(&self.key_prefix, self.path_crypt.as_ref(), &Self::content_object_path(id));
+                    self.block_on(crate::links::acquire(self.storage.as_ref(), &content_key)).map_err(|e| e.to_errno())?;
+                }
+                None => {
+                    self.block_on(crate::blockstore::copy_all(self.storage.as_ref(), &self.encryptor, &path_key, &dst_key))
+                        .map_err(|e| e.to_errno())?;
+                }
+            }
+            // Best-effort: a file written before metadata sidecars
+            // existed has none to copy, and that's fine. This is also
+            // what carries a hard-linked file's `content_id` pointer
+            // over to `dst_path`.
+            let _ = self.block_on(self.storage.copy(&format!("{}.meta", path_key), &format!("{}.meta", dst_key)));
+            Ok(())
+        }
+    }
+
+    /// Deletes every storage object backing `path`: a file's header and
+    /// blocks, or (for a directory) every object listed under its
+    /// prefix. A directory's objects are deleted in one batch where the
+    /// backend supports it (see [`StorageBackend::delete_batch`]),
+    /// rather than one request per object.
+    fn delete_tree(&self, path: &str, is_dir: bool) -> std::result::Result<(), i32> {
+        if is_dir {
+            let list_prefix = self.storage_key(&format!("{}/", path));
+            let objects = self.block_on(self.storage.list(&list_prefix)).map_err(|e| e.to_errno())?;
+            let keys: Vec<String> = objects.into_iter().map(|obj| obj.key).collect();
+            let _ = self.block_on(self.storage.delete_batch(&keys));
+            self.block_on(crate::metadata::delete(self.storage.as_ref(), &self.storage_key(path)));
+            Ok(())
+        } else {
+            self.delete_file_content(path).map_err(|e| e.to_errno())?;
+            self.block_on(crate::metadata::delete(self.storage.as_ref(), &self.storage_key(path)));
+            Ok(())
+        }
+    }
+
+    /// Deletes `path`'s content: its own header and blocks if it was
+    /// never linked, or (if `content_id` is set) one reference to the
+    /// shared content, deleting that content too once the last
+    /// reference is gone. Shared by [`Self::delete_tree`]'s file branch
+    /// and `unlink`, which both need this and nothing else — `path`'s
+    /// own `.meta` sidecar is always going away regardless, so deleting
+    /// it is left to the caller.
+    fn delete_file_content(&self, path: &str) -> crate::error::Result<()> {
+        let path_key = self.storage_key(path);
+        let meta = self.block_on(crate::metadata::load(self.storage.as_ref(), &path_key)).ok();
+
+        match meta.as_ref().and_then(|m| m.content_id.as_ref()) {
+            Some(id) => {
+                let content_key = Self::storage_key_with_prefix(&self.key_prefix, self.path_crypt.as_ref(), &Self::content_object_path(id));
+                if self.block_on(crate::links::release(self.storage.as_ref(), &content_key))? == 0 {
+                    self.block_on(crate::blockstore::delete_all(self.storage.as_ref(), &content_key))?;
+                }
+                Ok(())
+            }
+            None => self.block_on(crate::blockstore::delete_all(self.storage.as_ref(), &path_key)),
+        }
+    }
+
+    /// `true` if `path` names an existing file (not a directory),
+    /// checked via its block header. A hard-linked file's header no
+    /// longer lives at its own key (see [`Self::link`]), so a `.meta`
+    /// sidecar claiming a `content_id` counts as existing too.
+    fn is_file(&self, path: &str) -> bool {
+        let path_key = self.storage_key(path);
+        if self.block_on(crate::blockstore::head(self.storage.as_ref(), &path_key)).is_ok() {
+            return true;
+        }
+        matches!(
+            self.block_on(crate::metadata::load(self.storage.as_ref(), &path_key)),
+            Ok(meta) if meta.content_id.is_some()
+        )
+    }
+
+    /// Encrypts and uploads a dirty write-back buffer as a single
+    /// whole-file write, overwriting whatever was previously stored at
+    /// `open_file.path`. Takes its dependencies by value/reference
+    /// rather than `&self` so it can run from inside the `FnOnce`
+    /// eviction callback `HandleTable::open` invokes, which fires while
+    /// a *different* handle is being opened.
+    #[allow(clippy::too_many_arguments)]
+    fn flush_open_file(
+        storage: &Arc<dyn StorageBackend>,
+        encryptor: &Encryptor,
+        runtime: &tokio::runtime::Handle,
+        key_prefix: &str,
+        path_crypt: Option<&PathCrypt>,
+        compression: &CompressionConfig,
+        dedup: &DedupConfig,
+        open_file: &OpenFile,
+        block_size: u64,
+        journal: Option<&Arc<Journal>>,
+    ) -> crate::error::Result<()> {
+        let key = Self::storage_key_with_prefix(key_prefix, path_crypt, &open_file.path);
+        let mut meta = runtime.block_on(crate::metadata::load(storage.as_ref(), &key)).ok();
+
+        let (content_key, should_upload) = match meta.as_mut() {
+            Some(meta) if dedup.enabled && meta.content_id.is_none() => runtime.block_on(Self::try_dedup(
+                storage.as_ref(),
+                encryptor,
+                key_prefix,
+                path_crypt,
+                &key,
+                meta,
+                &open_file.buffer,
+            ))?,
+            Some(meta) if meta.content_id.is_some() && !meta.content_linked => runtime.block_on(Self::cow_detach_and_dedup(
+                storage.as_ref(),
+                encryptor,
+                key_prefix,
+                path_crypt,
+                &key,
+                meta,
+                &open_file.buffer,
+                dedup,
+            ))?,
+            Some(meta) => (Self::resolve_content_key(key_prefix, path_crypt, &key, meta), true),
+            None => (key, true),
+        };
+
+        if !should_upload {
+            return Ok(());
+        }
+
+        // Only a non-deduped file's own content lives at a key nothing
+        // else writes to; a shared, content-addressed object (dedup hit
+        // or hard link) can be touched by any other path referencing it,
+        // so it's out of scope for this check. See `FileMetadata::content_etag`.
+        let tracks_etag = meta.as_ref().is_some_and(|m| m.content_id.is_none());
+        let expected_etag = meta.as_ref().and_then(|m| m.content_etag.clone()).filter(|_| tracks_etag);
+        runtime.block_on(crate::blockstore::check_not_modified(storage.as_ref(), &content_key, expected_etag.as_deref()))?;
+
+        // Recorded locally before the upload, and removed once it
+        // succeeds, so a crash mid-upload leaves a record behind for
+        // `Journal::replay` to finish on the next mount. Best-effort:
+        // a local-disk failure here must never fail the flush itself.
+        let record = journal.and_then(|journal| journal.begin(&content_key, 0, &open_file.buffer, compression.enabled, compression.level).ok());
+
+        let result = runtime.block_on(crate::blockstore::write_range_with_block_size(
+            storage.as_ref(),
+            encryptor,
+            &content_key,
+            0,
+            &open_file.buffer,
+            compression.enabled,
+            compression.level,
+            block_size,
+        ));
+
+        if result.is_ok() {
+            if let (Some(journal), Some(record)) = (journal, record) {
+                journal.commit(record).ok();
+            }
+            if tracks_etag {
+                if let (Ok(fresh), Some(meta)) = (runtime.block_on(crate::blockstore::head(storage.as_ref(), &content_key)), meta.as_mut()) {
+                    meta.content_etag = fresh.etag;
+                    let _ = runtime.block_on(crate::metadata::save(storage.as_ref(), &key, meta));
+                }
+            }
+        }
+
+        result
+    }
+
+    /// Only ever called when `meta.content_id` is still `None` (this
+    /// file has never been hard-linked or deduped before, or has just
+    /// been detached from a past dedup match by
+    /// [`Self::cow_detach_and_dedup`]): hashes `plaintext` under the
+    /// bucket's own data key (see [`crate::links::content_hash`]) and
+    /// either points `meta` at an existing content object sharing that
+    /// hash — in which case the caller must skip the upload entirely,
+    /// since the bytes are already there — or claims the hash as a
+    /// fresh content object so a future identical file can dedup
+    /// against it. A file that dedups this way always flushes with
+    /// `content_linked` left `false`, so a later write to it is routed
+    /// through `cow_detach_and_dedup` rather than writing straight
+    /// through to the shared object; only a real `link()` sets
+    /// `content_linked` to get that write-through behavior.
+    async fn try_dedup(
+        storage: &dyn StorageBackend,
+        encryptor: &Encryptor,
+        key_prefix: &str,
+        path_crypt: Option<&PathCrypt>,
+        key: &str,
+        meta: &mut FileMetadata,
+        plaintext: &[u8],
+    ) -> crate::error::Result<(String, bool)> {
+        let id = crate::links::content_hash(encryptor.key(), plaintext);
+        let content_key = Self::storage_key_with_prefix(key_prefix, path_crypt, &Self::content_object_path(&id));
+
+        let is_hit = crate::blockstore::head(storage, &content_key).await.is_ok();
+        if is_hit {
+            crate::links::acquire(storage, &content_key).await?;
+        }
+        let _ = crate::blockstore::delete_all(storage, key).await;
+
+        meta.content_id = Some(id);
+        crate::metadata::save(storage, key, meta).await?;
+        Ok((content_key, !is_hit))
+    }
+
+    /// Breaks `meta`'s dedup-only share of a content object before
+    /// writing `plaintext`: releases its reference to the old content
+    /// key (deleting the content too if that was the last reference),
+    /// forgets `content_id`, then re-runs [`Self::try_dedup`] against
+    /// the new bytes (or, with dedup disabled, just writes straight to
+    /// `key`). Only ever reached for `content_linked == false` — see
+    /// the call site in [`Self::flush_open_file`]. Without this, a
+    /// write to a file that once happened to match another file's bytes
+    /// would silently overwrite that unrelated file's content too,
+    /// since both would otherwise still point at the same shared
+    /// object.
+    async fn cow_detach_and_dedup(
+        storage: &dyn StorageBackend,
+        encryptor: &Encryptor,
+        key_prefix: &str,
+        path_crypt: Option<&PathCrypt>,
+        key: &str,
+        meta: &mut FileMetadata,
+        plaintext: &[u8],
+        dedup: &DedupConfig,
+    ) -> crate::error::Result<(String, bool)> {
+        if let Some(id) = meta.content_id.take() {
+            let old_content_key = Self::storage_key_with_prefix(key_prefix, path_crypt, &Self::content_object_path(&id));
+            if crate::links::release(storage, &old_content_key).await? == 0 {
+                crate::blockstore::delete_all(storage, &old_content_key).await?;
+            }
+        }
+        meta.content_etag = None;
+        if dedup.enabled {
+            Self::try_dedup(storage, encryptor, key_prefix, path_crypt, key, meta, plaintext).await
+        } else {
+            crate::metadata::save(storage, key, meta).await?;
+            Ok((key.to_string(), true))
+        }
+    }
+
+    /// Uploads `fh`'s write-back buffer if it's dirty, clearing the
+    /// dirty flag on success. Shared by `flush` (on `close()`) and
+    /// `fsync`/`fdatasync()`, which both need the exact same
+    /// "confirm the upload completed" guarantee. Returns `None` for an
+    /// unknown handle, which both callers treat as a no-op rather than
+    /// an error.
+    fn flush_handle(&self, fh: u64) -> Option<crate::error::Result<()>> {
+        let storage = Arc::clone(&self.storage);
+        let encryptor = self.encryptor.clone();
+        let runtime = self.runtime.handle().clone();
+        let key_prefix = self.key_prefix.clone();
+        let path_crypt = self.path_crypt.clone();
+        let compression = self.compression.clone();
+        let dedup = self.dedup.clone();
+        let block_size = self.block_size;
+        let journal = self.journal.clone();
+
+        self.handles.with_handle(fh, move |open| {
+            if !open.dirty {
+                return Ok(());
+            }
+            let result = Self::flush_open_file(
+                &storage,
+                &encryptor,
+                &runtime,
+                &key_prefix,
+                path_crypt.as_ref(),
+                &compression,
+                &dedup,
+                open,
+                block_size,
+                journal.as_ref(),
+            );
+            if result.is_ok() {
+                open.mark_clean();
+            }
+            result
+        })
+    }
+}
+
+impl Filesystem for AegisFS {
+    fn lookup(&mut self, req: &Request, parent: u64, name: &OsStr, reply: ReplyEntry) {
+        self.metrics.op_lookup.fetch_add(1, Ordering::Relaxed);
+        let name = match name.to_str() {
+            Some(n) => n,
+            None => {
+                reply.error(ENOENT);
+                return;
+            }
+        };
+
+        // Markers and sidecar objects are implementation details of how
+        // directories and files are represented; they must never
+        // resolve as a lookup-able entry.
+        if Self::is_reserved_name(name) {
+            reply.error(ENOENT);
+            return;
+        }
+
+        let parent_path = match self.path_for_ino(parent) {
+            Some(p) => p,
+            None => {
+                reply.error(ENOENT);
+                return;
+            }
+        };
+        let path = Self::child_path(&parent_path, name);
+
+        if self.is_directory(&path) {
+            let ino = self.get_or_create_ino(&path);
+            let meta = self.block_on(crate::metadata::load_or_default(self.storage.as_ref(), &self.storage_key(&path), 0o755));
+            self.audit(req, "lookup", &path, true);
+            reply.entry(&self.entry_ttl, &Self::dir_attr_from_meta(ino, &meta, self.nlink_for_dir(&path), self.block_size as u32), 0);
+            return;
+        }
+
+        let key = self.storage_key(&path);
+        match self.file_size(&path) {
+            Ok(size) => {
+                let ino = self.get_or_create_ino(&path);
+                let meta = self.block_on(crate::metadata::load_or_default(self.storage.as_ref(), &key, 0o644));
+                let content_key = Self::resolve_content_key(&self.key_prefix, self.path_crypt.as_ref(), &key, &meta);
+                let nlink = self.file_nlink(&meta, &content_key);
+                self.audit(req, "lookup", &path, true);
+                reply.entry(&self.entry_ttl, &Self::file_attr_from_meta(ino, size, &meta, nlink, self.block_size as u32), 0);
+            }
+            Err(e) => {
+                self.audit(req, "lookup", &path, false);
+                reply.error(e.to_errno());
+            }
+        }
+    }
+
+    fn getattr(&mut self, req: &Request, ino: u64, reply: ReplyAttr) {
+        self.metrics.op_getattr.fetch_add(1, Ordering::Relaxed);
+        let path = match self.path_for_ino(ino) {
+            Some(p) => p,
+            None => {
+                reply.error(ENOENT);
+                return;
+            }
+        };
+
+        if ino == ROOT_INO {
+            self.audit(req, "getattr", &path, true);
+            reply.attr(&self.attr_ttl, &Self::dir_attr(ino, self.nlink_for_dir(""), self.block_size as u32));
+            return;
+        }
+
+        if self.is_directory(&path) {
+            let meta = self.block_on(crate::metadata::load_or_default(self.storage.as_ref(), &self.storage_key(&path), 0o755));
+            self.audit(req, "getattr", &path, true);
+            reply.attr(&self.attr_ttl, &Self::dir_attr_from_meta(ino, &meta, self.nlink_for_dir(&path), self.block_size as u32));
+            return;
+        }
+
+        let key = self.storage_key(&path);
+
+        if self.consistency_mode == ConsistencyMode::Strong {
+            if let Some(size) = self.revalidate_cached_size(&path) {
+                let meta = self.block_on(crate::metadata::load_or_default(self.storage.as_ref(), &key, 0o644));
+                let content_key = Self::resolve_content_key(&self.key_prefix, self.path_crypt.as_ref(), &key, &meta);
+                let nlink = self.file_nlink(&meta, &content_key);
+                self.audit(req, "getattr", &path, true);
+                reply.attr(&self.attr_ttl, &Self::file_attr_from_meta(ino, size, &meta, nlink, self.block_size as u32));
+                return;
+            }
+        }
+
+        match self.file_size(&path) {
+            Ok(size) => {
+                if self.consistency_mode == ConsistencyMode::Strong {
+                    self.cache_attr(&path, size);
+                }
+                let meta = self.block_on(crate::metadata::load_or_default(self.storage.as_ref(), &key, 0o644));
+                let content_key = Self::resolve_content_key(&self.key_prefix, self.path_crypt.as_ref(), &key, &meta);
+                let nlink = self.file_nlink(&meta, &content_key);
+                self.audit(req, "getattr", &path, true);
+                reply.attr(&self.attr_ttl, &Self::file_attr_from_meta(ino, size, &meta, nlink, self.block_size as u32));
+            }
+            Err(e) => {
+                self.audit(req, "getattr", &path, false);
+                reply.error(e.to_errno());
+            }
+        }
+    }
+
+    /// Backs `access`: checks `mask` (libc's `R_OK`/`W_OK`/`X_OK`,
+    /// OR'd together, or `F_OK` for a bare existence check) against
+    /// `mode`'s owner/group/other permission triad, picking the triad
+    /// the same way the kernel would — the owner's if `uid` matches,
+    /// the group's if `gid` matches, otherwise "other" — and letting
+    /// uid 0 (root) through unconditionally, same as every other POSIX
+    /// filesystem.
+    fn check_access(mode: u32, owner_uid: u32, owner_gid: u32, uid: u32, gid: u32, mask: i32) -> bool {
+        if mask == libc::F_OK || uid == 0 {
+            return true;
+        }
+        let triad = if uid == owner_uid {
+            (mode >> 6) & 0o7
+        } else if gid == owner_gid {
+            (mode >> 3) & 0o7
+        } else {
+            mode & 0o7
+        };
+        let requested = mask as u32 & 0o7;
+        triad & requested == requested
+    }
+
+    /// Permission probe `open()`/`default_permissions` issue before
+    /// actually opening a path, so access control (enforced here
+    /// against `setattr`-maintained mode/uid/gid) is checked up front
+    /// rather than only implicitly via some later operation failing.
+    fn access(&mut self, req: &Request, ino: u64, mask: i32, reply: fuser::ReplyEmpty) {
+        self.metrics.op_access.fetch_add(1, Ordering::Relaxed);
+        let path = match self.path_for_ino(ino) {
+            Some(p) => p,
+            None => {
+                reply.error(ENOENT);
+                return;
+            }
+        };
+
+        let (mode, owner_uid, owner_gid) = if ino == ROOT_INO {
+            (0o755, 0, 0)
+        } else if self.is_directory(&path) {
+            let meta = self.block_on(crate::metadata::load_or_default(self.storage.as_ref(), &self.storage_key(&path), 0o755));
+            (meta.mode, meta.uid, meta.gid)
+        } else {
+            let key = self.storage_key(&path);
+            let meta = self.block_on(crate::metadata::load_or_default(self.storage.as_ref(), &key, 0o644));
+            (meta.mode, meta.uid, meta.gid)
+        };
+
+        if Self::check_access(mode, owner_uid, owner_gid, req.uid(), req.gid(), mask) {
+            self.audit(req, "access", &path, true);
+            reply.ok();
+        } else {
+            self.audit(req, "access", &path, false);
+            reply.error(EACCES);
+        }
+    }
+
+    /// In [`ConsistencyMode::Strong`], returns the cached size for
+    /// `path` if a conditional request confirms the object's ETag
+    /// hasn't changed since it was cached. Returns `None` on a cache
+    /// miss or a detected change, so the caller falls back to a full
+    /// fetch (and should then call `cache_attr` to repopulate).
+    fn revalidate_cached_size(&self, path: &str) -> Option<u64> {
+        let cached_etag = {
+            let cache = self.attr_cache.lock().unwrap();
+            cache.get(path).map(|c| c.etag.clone())
+        }?;
+
+        match self.block_on(crate::blockstore::head_if_none_match(self.storage.as_ref(), &self.content_key(path), &cached_etag)) {
+            Ok(None) => self.attr_cache.lock().unwrap().get(path).map(|c| c.size),
+            _ => None,
+        }
+    }
+
+    fn cache_attr(&self, path: &str, size: u64) {
+        if let Ok(meta) = self.block_on(crate::blockstore::head(self.storage.as_ref(), &self.content_key(path))) {
+            if let Some(etag) = meta.etag {
+                self.attr_cache.lock().unwrap().insert(path.to_string(), CachedAttr { etag, size });
+            }
+        }
+    }
+
+    /// Handles `truncate`/`chmod`/`chown`/`utimes`, all of which land
+    /// here as a single `setattr` call. Since the backend has no
+    /// concept of file metadata, mode/owner/times are persisted in a
+    /// `FileMetadata` sidecar (see [`crate::metadata`]); `size` goes
+    /// through [`crate::blockstore::truncate`], which only touches the
+    /// blocks that straddle the new end.
+    #[allow(clippy::too_many_arguments)]
+    fn setattr(
+        &mut self,
+        req: &Request,
+        ino: u64,
+        mode: Option<u32>,
+        uid: Option<u32>,
+        gid: Option<u32>,
+        size: Option<u64>,
+        atime: Option<TimeOrNow>,
+        mtime: Option<TimeOrNow>,
+        _ctime: Option<SystemTime>,
+        _fh: Option<u64>,
+        _crtime: Option<SystemTime>,
+        _chgtime: Option<SystemTime>,
+        _bkuptime: Option<SystemTime>,
+        _flags: Option<u32>,
+        reply: ReplyAttr,
+    ) {
+        self.metrics.op_setattr.fetch_add(1, Ordering::Relaxed);
+        if self.read_only {
+            reply.error(libc::EROFS);
+            return;
+        }
+        let path = match self.path_for_ino(ino) {
+            Some(p) => p,
+            None => {
+                reply.error(ENOENT);
+                return;
+            }
+        };
+
+        if ino == ROOT_INO {
+            // The mount root has no sidecar to persist against;
+            // chmod/chown/utimes on it are accepted as no-ops.
+            self.audit(req, "setattr", &path, true);
+            reply.attr(&self.attr_ttl, &Self::dir_attr(ino, self.nlink_for_dir(""), self.block_size as u32));
+            return;
+        }
+
+        let key = self.storage_key(&path);
+        let is_dir = self.is_directory(&path);
+        let default_mode = if is_dir { 0o755 } else { 0o644 };
+        let mut meta = self.block_on(crate::metadata::load_or_default(self.storage.as_ref(), &key, default_mode));
+
+        if let Some(m) = mode {
+            meta.mode = m & 0o7777;
+        }
+        if let Some(u) = uid {
+            meta.uid = u;
+        }
+        if let Some(g) = gid {
+            meta.gid = g;
+        }
+        if let Some(a) = atime {
+            meta.set_atime(match a {
+                TimeOrNow::SpecificTime(t) => t,
+                TimeOrNow::Now => SystemTime::now(),
+            });
+        }
+        if let Some(m) = mtime {
+            meta.set_mtime(match m {
+                TimeOrNow::SpecificTime(t) => t,
+                TimeOrNow::Now => SystemTime::now(),
+            });
+        }
+        meta.touch_ctime();
+
+        let content_key = Self::resolve_content_key(&self.key_prefix, self.path_crypt.as_ref(), &key, &meta);
+
+        if !is_dir {
+            if let Some(new_len) = size {
+                if let Err(e) = self.block_on(crate::blockstore::truncate(self.storage.as_ref(), &self.encryptor, &content_key, new_len)) {
+                    self.audit(req, "setattr", &path, false);
+                    reply.error(e.to_errno());
+                    return;
+                }
+            }
+        }
+
+        if let Err(e) = self.block_on(crate::metadata::save(self.storage.as_ref(), &key, &meta)) {
+            self.audit(req, "setattr", &path, false);
+            reply.error(e.to_errno());
+            return;
+        }
+        self.attr_cache.lock().unwrap().remove(&path);
+
+        let attr = if is_dir {
+            Self::dir_attr_from_meta(ino, &meta, self.nlink_for_dir(&path), self.block_size as u32)
+        } else {
+            let current_size = self.block_on(crate::blockstore::total_len(self.storage.as_ref(), &content_key)).unwrap_or(0);
+            let nlink = self.file_nlink(&meta, &content_key);
+            Self::file_attr_from_meta(ino, current_size, &meta, nlink, self.block_size as u32)
+        };
+        self.audit(req, "setattr", &path, true);
+        reply.attr(&self.attr_ttl, &attr);
+    }
+
+    /// Reads back the target of a [`Self::symlink`]-created entry. The
+    /// target is stored as the entry's whole (encrypted) content, so
+    /// this is just a plain read of everything that's there.
+    fn readlink(&mut self, _req: &Request, ino: u64, reply: ReplyData) {
+        let path = match self.path_for_ino(ino) {
+            Some(p) => p,
+            None => {
+                reply.error(ENOENT);
+                return;
+            }
+        };
+        let key = self.storage_key(&path);
+
+        let len = match self.block_on(crate::blockstore::total_len(self.storage.as_ref(), &key)) {
+            Ok(len) => len,
+            Err(_) => {
+                reply.error(ENOENT);
+                return;
+            }
+        };
+        match self.block_on(crate::blockstore::read_range(self.storage.as_ref(), &self.encryptor, &key, 0, len)) {
+            Ok(data) => reply.data(&data),
+            Err(e) => reply.error(e.to_errno()),
+        }
+    }
+
+    fn open(&mut self, req: &Request, ino: u64, _flags: i32, reply: fuser::ReplyOpen) {
+        self.metrics.op_open.fetch_add(1, Ordering::Relaxed);
+        let path = match self.path_for_ino(ino) {
+            Some(p) => p,
+            None => {
+                reply.error(ENOENT);
+                return;
+            }
+        };
+
+        let key = self.content_key(&path);
+        let len = self.block_on(crate::blockstore::total_len(self.storage.as_ref(), &key)).unwrap_or(0);
+        let buffer = self
+            .block_on(crate::blockstore::read_range(self.storage.as_ref(), &self.encryptor, &key, 0, len))
+            .unwrap_or_default();
+
+        let storage = Arc::clone(&self.storage);
+        let encryptor = self.encryptor.clone();
+        let runtime = self.runtime.handle().clone();
+        let key_prefix = self.key_prefix.clone();
+        let path_crypt = self.path_crypt.clone();
+        let compression = self.compression.clone();
+        let dedup = self.dedup.clone();
+        let block_size = self.block_size;
+        let journal = self.journal.clone();
+        let fh = self.handles.open(&path, buffer, move |evicted| {
+            let _ = Self::flush_open_file(&storage, &encryptor, &runtime, &key_prefix, path_crypt.as_ref(), &compression, &dedup, evicted, block_size, journal.as_ref());
+        });
+        self.audit(req, "open", &path, true);
+        reply.opened(fh, 0);
+    }
+
+    fn read(
+        &mut self,
+        req: &Request,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+        size: u32,
+        _flags: i32,
+        _lock_owner: Option<u64>,
+        reply: ReplyData,
+    ) {
+        self.metrics.op_read.fetch_add(1, Ordering::Relaxed);
+        let path = match self.path_for_ino(ino) {
+            Some(p) => p,
+            None => {
+                reply.error(ENOENT);
+                return;
+            }
+        };
+
+        // Reads against an open handle must see its own unflushed
+        // writes, so they're served from the write-back buffer rather
+        // than the (possibly stale) backend state — unless a far write
+        // bypassed the buffer (see `SPARSE_WRITE_GAP_THRESHOLD`), in
+        // which case `readable_range` returns `None` and this falls
+        // through to the backend read below like an unknown handle would.
+        let from_buffer = self
+            .handles
+            .with_handle(fh, |open| open.readable_range(offset as u64, size as u64).map(|range| open.buffer[range].to_vec()))
+            .flatten();
+        if let Some(data) = from_buffer {
+            self.metrics.bytes_read.fetch_add(data.len() as u64, Ordering::Relaxed);
+            self.audit(req, "read", &path, true);
+            reply.data(&data);
+            return;
+        }
+
+        let key = self.content_key(&path);
+        match self.block_on(crate::blockstore::read_range(self.storage.as_ref(), &self.encryptor, &key, offset as u64, size as u64)) {
+            Ok(data) => {
+                self.metrics.bytes_read.fetch_add(data.len() as u64, Ordering::Relaxed);
+                self.audit(req, "read", &path, true);
+                reply.data(&data);
+            }
+            Err(e) => {
+                self.audit(req, "read", &path, false);
+                reply.error(e.to_errno());
+            }
+        }
+    }
+
+    fn mkdir(
+        &mut self,
+        req: &Request,
+        parent: u64,
+        name: &OsStr,
+        mode: u32,
+        umask: u32,
+        reply: ReplyEntry,
+    ) {
+        self.metrics.op_mkdir.fetch_add(1, Ordering::Relaxed);
+        if self.read_only {
+            reply.error(libc::EROFS);
+            return;
+        }
+        let name = match name.to_str() {
+            Some(n) => n,
+            None => {
+                reply.error(ENOENT);
+                return;
+            }
+        };
+        if Self::is_reserved_name(name) {
+            reply.error(libc::EPERM);
+            return;
+        }
+        let parent_path = match self.resolve_existing_dir(parent) {
+            Ok(p) => p,
+            Err(errno) => {
+                reply.error(errno);
+                return;
+            }
+        };
+        let path = Self::child_path(&parent_path, name);
+        let placeholder_key = self.dir_placeholder_key(&path);
+
+        let ciphertext = match self.encryptor.encrypt(&[]) {
+            Ok(c) => c,
+            Err(_) => {
+                reply.error(libc::EIO);
+                return;
+            }
+        };
+
+        match self.block_on(self.storage.put(&self.storage_key(&placeholder_key), ciphertext)) {
+            Ok(()) => {
+                let perm = mode & !umask & 0o7777;
+                let meta = FileMetadata::new_file(perm, req.uid(), req.gid());
+                if let Err(e) = self.block_on(crate::metadata::save(self.storage.as_ref(), &self.storage_key(&path), &meta)) {
+                    self.audit(req, "mkdir", &path, false);
+                    reply.error(e.to_errno());
+                    return;
+                }
+                let ino = self.get_or_create_ino(&path);
+                // A directory just created by this call can't have any
+                // subdirectories of its own yet, so nlink is trivially 2
+                // without needing to list it.
+                self.audit(req, "mkdir", &path, true);
+                reply.entry(&self.entry_ttl, &Self::dir_attr_from_meta(ino, &meta, 2, self.block_size as u32), 0);
+            }
+            Err(e) => {
+                self.audit(req, "mkdir", &path, false);
+                reply.error(e.to_errno());
+            }
+        }
+    }
+
+    /// Stores `target` as the new entry's whole (encrypted) content and
+    /// flags its metadata [`FileKind::Symlink`], so `getattr`/`lookup`
+    /// report it as a symlink rather than a regular file. `readlink`
+    /// reads the content straight back.
+    fn symlink(&mut self, req: &Request, parent: u64, link_name: &OsStr, target: &std::path::Path, reply: ReplyEntry) {
+        let name = match link_name.to_str() {
+            Some(n) => n,
+            None => {
+                reply.error(ENOENT);
+                return;
+            }
+        };
+        let target = match target.to_str() {
+            Some(t) => t,
+            None => {
+                reply.error(ENOENT);
+                return;
+            }
+        };
+        let parent_path = match self.resolve_existing_dir(parent) {
+            Ok(p) => p,
+            Err(errno) => {
+                reply.error(errno);
+                return;
+            }
+        };
+        let path = Self::child_path(&parent_path, name);
+        let key = self.storage_key(&path);
+
+        match self.block_on(crate::blockstore::write_range_with_block_size(
+            self.storage.as_ref(),
+            &self.encryptor,
+            &key,
+            0,
+            target.as_bytes(),
+            self.compression.enabled,
+            self.compression.level,
+            self.block_size,
+        )) {
+            Ok(()) => {
+                let meta = FileMetadata::new_symlink(0o777, req.uid(), req.gid());
+                if let Err(e) = self.block_on(crate::metadata::save(self.storage.as_ref(), &key, &meta)) {
+                    reply.error(e.to_errno());
+                    return;
+                }
+                let ino = self.get_or_create_ino(&path);
+                reply.entry(&self.entry_ttl, &Self::file_attr_from_meta(ino, target.len() as u64, &meta, 1, self.block_size as u32), 0);
+            }
+            Err(e) => reply.error(e.to_errno()),
+        }
+    }
+
+    /// Directory deletion goes exclusively through `rmdir`; `unlink` will
+    /// not touch a directory placeholder object even if somehow asked
+    /// to. Per POSIX `rmdir(2)`, this only ever removes an empty
+    /// directory: [`Self::directory_has_children`] rejects a non-empty
+    /// one with `ENOTEMPTY` rather than deleting the marker and leaving
+    /// its contents orphaned. Recursive removal is a separate, explicit
+    /// operation (`rename`'s destination-replacement path goes through
+    /// [`Self::delete_tree`] instead).
+    fn rmdir(&mut self, req: &Request, parent: u64, name: &OsStr, reply: fuser::ReplyEmpty) {
+        self.metrics.op_rmdir.fetch_add(1, Ordering::Relaxed);
+        if self.read_only {
+            reply.error(libc::EROFS);
+            return;
+        }
+        let name = match name.to_str() {
+            Some(n) => n,
+            None => {
+                reply.error(ENOENT);
+                return;
+            }
+        };
+        let parent_path = match self.path_for_ino(parent) {
+            Some(p) => p,
+            None => {
+                reply.error(ENOENT);
+                return;
+            }
+        };
+        let path = Self::child_path(&parent_path, name);
+
+        match self.directory_has_children(&path) {
+            Ok(true) => {
+                self.audit(req, "rmdir", &path, false);
+                reply.error(libc::ENOTEMPTY);
+                return;
+            }
+            Ok(false) => {}
+            Err(e) => {
+                self.audit(req, "rmdir", &path, false);
+                reply.error(e.to_errno());
+                return;
+            }
+        }
+
+        let placeholder_key = self.dir_placeholder_key(&path);
+
+        match self.block_on(self.storage.delete(&self.storage_key(&placeholder_key))) {
+            Ok(()) => {
+                self.block_on(crate::metadata::delete(self.storage.as_ref(), &self.storage_key(&path)));
+                self.inodes.remove(&path);
+                self.audit(req, "rmdir", &path, true);
+                reply.ok();
+            }
+            Err(e) => {
+                self.audit(req, "rmdir", &path, false);
+                reply.error(e.to_errno());
+            }
+        }
+    }
+
+    fn unlink(&mut self, req: &Request, parent: u64, name: &OsStr, reply: fuser::ReplyEmpty) {
+        self.metrics.op_unlink.fetch_add(1, Ordering::Relaxed);
+        if self.read_only {
+            reply.error(libc::EROFS);
+            return;
+        }
+        let name = match name.to_str() {
+            Some(n) => n,
+            None => {
+                reply.error(ENOENT);
+                return;
+            }
+        };
+
+        // Defense in depth: even if a caller somehow has a name handle
+        // for the marker or a sidecar object, refuse to unlink it.
+        // Directories are removed with rmdir only.
+        if Self::is_reserved_name(name) {
+            reply.error(libc::EPERM);
+            return;
+        }
+
+        let parent_path = match self.path_for_ino(parent) {
+            Some(p) => p,
+            None => {
+                reply.error(ENOENT);
+                return;
+            }
+        };
+        let path = Self::child_path(&parent_path, name);
+
+        match self.delete_file_content(&path) {
+            Ok(()) => {
+                self.block_on(crate::metadata::delete(self.storage.as_ref(), &self.storage_key(&path)));
+                self.inodes.remove(&path);
+                self.audit(req, "unlink", &path, true);
+                reply.ok();
+            }
+            Err(e) => {
+                self.audit(req, "unlink", &path, false);
+                reply.error(e.to_errno());
+            }
+        }
+    }
+
+    /// Creates a new hard link at `newparent/newname` sharing `ino`'s
+    /// content. Backends have no native hard-link primitive, so the
+    /// first time a path is ever linked, its content is migrated to a
+    /// shared, content-addressed key under [`CONTENT_PREFIX`] that
+    /// every linking path's `.meta` sidecar then points at instead of
+    /// holding content directly (see [`crate::metadata::FileMetadata::content_id`]);
+    /// a refcount object colocated with that content (see
+    /// [`crate::links`]) tracks how many paths still reference it, so
+    /// `unlink` only deletes it once the last one is gone. Only plain
+    /// files can be linked — not directories, and not symlinks, which
+    /// keeps `readlink`/`symlink` untouched by this indirection. Note
+    /// that unlike a real inode, each linked path keeps its own
+    /// `FileMetadata` sidecar: `dst_path` starts out as a copy of
+    /// `src_path`'s mode/owner/times, but a later `chmod` on one won't
+    /// retroactively change the other's.
+    fn link(&mut self, req: &Request, ino: u64, newparent: u64, newname: &OsStr, reply: ReplyEntry) {
+        self.metrics.op_link.fetch_add(1, Ordering::Relaxed);
+        if self.read_only {
+            reply.error(libc::EROFS);
+            return;
+        }
+        let newname = match newname.to_str() {
+            Some(n) => n,
+            None => {
+                reply.error(ENOENT);
+                return;
+            }
+        };
+        if Self::is_reserved_name(newname) {
+            reply.error(libc::EPERM);
+            return;
+        }
+
+        let src_path = match self.path_for_ino(ino) {
+            Some(p) => p,
+            None => {
+                reply.error(ENOENT);
+                return;
+            }
+        };
+        if self.is_directory(&src_path) {
+            reply.error(libc::EPERM);
+            return;
+        }
+
+        let new_parent_path = match self.resolve_existing_dir(newparent) {
+            Ok(p) => p,
+            Err(errno) => {
+                self.audit(req, "link", &src_path, false);
+                reply.error(errno);
+                return;
+            }
+        };
+        let dst_path = Self::child_path(&new_parent_path, newname);
+        if self.is_directory(&dst_path) || self.is_file(&dst_path) {
+            self.audit(req, "link", &src_path, false);
+            reply.error(libc::EEXIST);
+            return;
+        }
+
+        let src_key = self.storage_key(&src_path);
+        let mut meta = self.block_on(crate::metadata::load_or_default(self.storage.as_ref(), &src_key, 0o644));
+        if meta.kind == FileKind::Symlink {
+            reply.error(libc::EPERM);
+            return;
+        }
+        // From here on this path's content is shared through a real
+        // hard link rather than a dedup match, even if it already had a
+        // `content_id` from deduping against something else — see
+        // `FileMetadata::content_linked`.
+        meta.content_linked = true;
+
+        let content_key = match meta.content_id.clone() {
+            Some(id) => Self::storage_key_with_prefix(&self.key_prefix, self.path_crypt.as_ref(), &Self::content_object_path(&id)),
+            None => {
+                // First ever link against this path: migrate its
+                // content to a fresh content id so `src_path` and
+                // `dst_path` can both point at it without fighting
+                // over the original path-bound key.
+                let id = crate::links::new_content_id();
+                let content_key = Self::storage_key_with_prefix(&self.key_prefix, self.path_crypt.as_ref(), &Self::content_object_path(&id));
+                if let Err(e) = self.block_on(crate::blockstore::copy_all(self.storage.as_ref(), &self.encryptor, &src_key, &content_key)) {
+                    self.audit(req, "link", &src_path, false);
+                    reply.error(e.to_errno());
+                    return;
+                }
+                if let Err(e) = self.block_on(crate::blockstore::delete_all(self.storage.as_ref(), &src_key)) {
+                    self.audit(req, "link", &src_path, false);
+                    reply.error(e.to_errno());
+                    return;
+                }
+                meta.content_id = Some(id);
+                if let Err(e) = self.block_on(crate::metadata::save(self.storage.as_ref(), &src_key, &meta)) {
+                    self.audit(req, "link", &src_path, false);
+                    reply.error(e.to_errno());
+                    return;
+                }
+                content_key
+            }
+        };
+
+        let nlink = match self.block_on(crate::links::acquire(self.storage.as_ref(), &content_key)) {
+            Ok(n) => n,
+            Err(e) => {
+                self.audit(req, "link", &src_path, false);
+                reply.error(e.to_errno());
+                return;
+            }
+        };
+
+        let dst_key = self.storage_key(&dst_path);
+        if let Err(e) = self.block_on(crate::metadata::save(self.storage.as_ref(), &dst_key, &meta)) {
+            self.audit(req, "link", &src_path, false);
+            reply.error(e.to_errno());
+            return;
+        }
+
+        let size = self.block_on(crate::blockstore::total_len(self.storage.as_ref(), &content_key)).unwrap_or(0);
+        self.attr_cache.lock().unwrap().remove(&src_path);
+        let dst_ino = self.get_or_create_ino(&dst_path);
+        self.audit(req, "link", &dst_path, true);
+        reply.entry(&self.entry_ttl, &Self::file_attr_from_meta(dst_ino, size, &meta, nlink, self.block_size as u32), 0);
+    }
+
+    /// Moves `parent/name` to `newparent/newname` by copying the
+    /// underlying object(s) to the new key(s) and deleting the
+    /// originals, then updating `inodes` to match. A directory's
+    /// entire contents move with it. `RENAME_NOREPLACE` rejects an
+    /// existing destination with `EEXIST`; `RENAME_EXCHANGE` swaps the
+    /// two paths' contents instead of overwriting.
+    fn rename(
+        &mut self,
+        req: &Request,
+        parent: u64,
+        name: &OsStr,
+        newparent: u64,
+        newname: &OsStr,
+        flags: u32,
+        reply: fuser::ReplyEmpty,
+    ) {
+        self.metrics.op_rename.fetch_add(1, Ordering::Relaxed);
+        if self.read_only {
+            reply.error(libc::EROFS);
+            return;
+        }
+        let name = match name.to_str() {
+            Some(n) => n,
+            None => {
+                reply.error(ENOENT);
+                return;
+            }
+        };
+        let newname = match newname.to_str() {
+            Some(n) => n,
+            None => {
+                reply.error(ENOENT);
+                return;
+            }
+        };
+        if Self::is_reserved_name(name) || Self::is_reserved_name(newname) {
+            reply.error(libc::EPERM);
+            return;
+        }
+
+        let parent_path = match self.path_for_ino(parent) {
+            Some(p) => p,
+            None => {
+                reply.error(ENOENT);
+                return;
+            }
+        };
+        let new_parent_path = match self.path_for_ino(newparent) {
+            Some(p) => p,
+            None => {
+                reply.error(ENOENT);
+                return;
+            }
+        };
+        let src_path = Self::child_path(&parent_path, name);
+        let dst_path = Self::child_path(&new_parent_path, newname);
+
+        let noreplace = flags & (libc::RENAME_NOREPLACE as u32) != 0;
+        let exchange = flags & (libc::RENAME_EXCHANGE as u32) != 0;
+        if noreplace && exchange {
+            reply.error(libc::EINVAL);
+            return;
+        }
+
+        let src_is_dir = self.is_directory(&src_path);
+        if !src_is_dir && !self.is_file(&src_path) {
+            reply.error(ENOENT);
+            return;
+        }
+
+        let dst_is_dir = self.is_directory(&dst_path);
+        let dst_exists = dst_is_dir || self.is_file(&dst_path);
+
+        if exchange {
+            if !dst_exists {
+                reply.error(ENOENT);
+                return;
+            }
+            if src_is_dir != dst_is_dir {
+                // Swapping a file with a directory isn't something a
+                // plain prefix swap over object keys can express
+                // atomically; real filesystems support it, but it's an
+                // edge case not worth the complexity here.
+                reply.error(libc::EINVAL);
+                return;
+            }
+
+            let tmp_path = format!("{}.aegisfs-rename-exchange-tmp", dst_path);
+            let result = (|| -> std::result::Result<(), i32> {
+                self.copy_tree(&dst_path, &tmp_path, dst_is_dir)?;
+                self.delete_tree(&dst_path, dst_is_dir)?;
+                self.copy_tree(&src_path, &dst_path, src_is_dir)?;
+                self.delete_tree(&src_path, src_is_dir)?;
+                self.copy_tree(&tmp_path, &src_path, dst_is_dir)?;
+                self.delete_tree(&tmp_path, dst_is_dir)
+            })();
+            let success = result.is_ok();
+            match result {
+                Ok(()) => {
+                    if src_is_dir {
+                        self.inodes.rename_prefix(&dst_path, "\u{0}aegisfs-rename-exchange-tmp");
+                        self.inodes.rename_prefix(&src_path, &dst_path);
+                        self.inodes.rename_prefix("\u{0}aegisfs-rename-exchange-tmp", &src_path);
+                    } else {
+                        self.inodes.rename(&dst_path, "\u{0}aegisfs-rename-exchange-tmp");
+                        self.inodes.rename(&src_path, &dst_path);
+                        self.inodes.rename("\u{0}aegisfs-rename-exchange-tmp", &src_path);
+                    }
+                    self.attr_cache.lock().unwrap().remove(&src_path);
+                    self.attr_cache.lock().unwrap().remove(&dst_path);
+                    reply.ok();
+                }
+                Err(errno) => reply.error(errno),
+            }
+            self.audit(req, "rename", &format!("{} -> {}", src_path, dst_path), success);
+            return;
+        }
+
+        if dst_exists {
+            if noreplace {
+                reply.error(libc::EEXIST);
+                return;
+            }
+            if dst_is_dir != src_is_dir {
+                reply.error(if dst_is_dir { libc::EISDIR } else { libc::ENOTDIR });
+                return;
+            }
+            if let Err(errno) = self.delete_tree(&dst_path, dst_is_dir) {
+                self.audit(req, "rename", &format!("{} -> {}", src_path, dst_path), false);
+                reply.error(errno);
+                return;
+            }
+        }
+
+        if let Err(errno) = self.copy_tree(&src_path, &dst_path, src_is_dir) {
+            self.audit(req, "rename", &format!("{} -> {}", src_path, dst_path), false);
+            reply.error(errno);
+            return;
+        }
+        if let Err(errno) = self.delete_tree(&src_path, src_is_dir) {
+            self.audit(req, "rename", &format!("{} -> {}", src_path, dst_path), false);
+            reply.error(errno);
+            return;
+        }
+
+        if src_is_dir {
+            self.inodes.rename_prefix(&src_path, &dst_path);
+        } else {
+            self.inodes.rename(&src_path, &dst_path);
+        }
+        self.attr_cache.lock().unwrap().remove(&src_path);
+        self.audit(req, "rename", &format!("{} -> {}", src_path, dst_path), true);
+        reply.ok();
+    }
+
+    fn create(
+        &mut self,
+        req: &Request,
+        parent: u64,
+        name: &OsStr,
+        mode: u32,
+        umask: u32,
+        _flags: i32,
+        reply: ReplyCreate,
+    ) {
+        self.metrics.op_create.fetch_add(1, Ordering::Relaxed);
+        if self.read_only {
+            reply.error(libc::EROFS);
+            return;
+        }
+        let name = match name.to_str() {
+            Some(n) => n,
+            None => {
+                reply.error(ENOENT);
+                return;
+            }
+        };
+        if Self::is_reserved_name(name) {
+            reply.error(libc::EPERM);
+            return;
+        }
+        let parent_path = match self.resolve_existing_dir(parent) {
+            Ok(p) => p,
+            Err(errno) => {
+                reply.error(errno);
+                return;
+            }
+        };
+        let path = Self::child_path(&parent_path, name);
+        let key = self.storage_key(&path);
+
+        match self.block_on(crate::blockstore::create_empty_with_block_size(self.storage.as_ref(), &key, self.block_size)) {
+            Ok(()) => {
+                let perm = mode & !umask & 0o7777;
+                let meta = FileMetadata::new_file(perm, req.uid(), req.gid());
+                if let Err(e) = self.block_on(crate::metadata::save(self.storage.as_ref(), &key, &meta)) {
+                    self.audit(req, "create", &path, false);
+                    reply.error(e.to_errno());
+                    return;
+                }
+                let ino = self.get_or_create_ino(&path);
+
+                let storage = Arc::clone(&self.storage);
+                let encryptor = self.encryptor.clone();
+                let runtime = self.runtime.handle().clone();
+                let key_prefix = self.key_prefix.clone();
+                let path_crypt = self.path_crypt.clone();
+                let compression = self.compression.clone();
+                let dedup = self.dedup.clone();
+                let block_size = self.block_size;
+                let journal = self.journal.clone();
+                let fh = self.handles.open(&path, Vec::new(), move |evicted| {
+                    let _ = Self::flush_open_file(&storage, &encryptor, &runtime, &key_prefix, path_crypt.as_ref(), &compression, &dedup, evicted, block_size, journal.as_ref());
+                });
+
+                self.audit(req, "create", &path, true);
+                reply.created(&self.entry_ttl, &Self::file_attr_from_meta(ino, 0, &meta, 1, self.block_size as u32), 0, fh, 0);
+            }
+            Err(e) => {
+                self.audit(req, "create", &path, false);
+                reply.error(e.to_errno());
+            }
+        }
+    }
+
+    fn write(
+        &mut self,
+        req: &Request,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+        data: &[u8],
+        _write_flags: u32,
+        _flags: i32,
+        _lock_owner: Option<u64>,
+        reply: ReplyWrite,
+    ) {
+        self.metrics.op_write.fetch_add(1, Ordering::Relaxed);
+        if self.read_only {
+            reply.error(libc::EROFS);
+            return;
+        }
+        self.metrics.bytes_written.fetch_add(data.len() as u64, Ordering::Relaxed);
+        let path = match self.path_for_ino(ino) {
+            Some(p) => p,
+            None => {
+                reply.error(ENOENT);
+                return;
+            }
+        };
+
+        let buffer_len = self.handles.with_handle(fh, |open| open.buffer.len() as u64);
+        if let Some(buffer_len) = buffer_len {
+            if (offset as u64).saturating_sub(buffer_len) > SPARSE_WRITE_GAP_THRESHOLD {
+                // Bypass the buffer entirely rather than zero-filling a
+                // potentially huge gap in memory: flush what's resident
+                // first so this write lands after it, then go straight
+                // through the already-sparse blockstore path.
+                if let Some(Err(e)) = self.flush_handle(fh) {
+                    self.audit(req, "write", &path, false);
+                    reply.error(e.to_errno());
+                    return;
+                }
+                let key = self.content_key(&path);
+                return match self.block_on(crate::blockstore::write_range_with_block_size(
+                    self.storage.as_ref(),
+                    &self.encryptor,
+                    &key,
+                    offset as u64,
+                    data,
+                    self.compression.enabled,
+                    self.compression.level,
+                    self.block_size,
+                )) {
+                    Ok(()) => {
+                        self.handles.with_handle(fh, |open| open.extend_logical_len(offset as u64 + data.len() as u64));
+                        self.audit(req, "write", &path, true);
+                        reply.written(data.len() as u32)
+                    }
+                    Err(e) => {
+                        self.audit(req, "write", &path, false);
+                        reply.error(e.to_errno())
+                    }
+                };
+            }
+        }
+
+        let written = self.handles.with_handle(fh, |open| {
+            let end = offset as usize + data.len();
+            if open.buffer.len() < end {
+                open.buffer.resize(end, 0);
+            }
+            open.buffer[offset as usize..end].copy_from_slice(data);
+            open.mark_dirty();
+            open.extend_logical_len(end as u64);
+        });
+
+        match written {
+            Some(()) => {
+                self.audit(req, "write", &path, true);
+                reply.written(data.len() as u32)
+            }
+            // No resident buffer for this handle (e.g. a stale/unknown
+            // fh) — fall back to a direct range write so the syscall
+            // still succeeds correctly, just without write coalescing.
+            None => {
+                let key = self.content_key(&path);
+                match self.block_on(crate::blockstore::write_range_with_block_size(
+                    self.storage.as_ref(),
+                    &self.encryptor,
+                    &key,
+                    offset as u64,
+                    data,
+                    self.compression.enabled,
+                    self.compression.level,
+                    self.block_size,
+                )) {
+                    Ok(()) => {
+                        self.audit(req, "write", &path, true);
+                        reply.written(data.len() as u32)
+                    }
+                    Err(e) => {
+                        self.audit(req, "write", &path, false);
+                        reply.error(e.to_errno())
+                    }
+                }
+            }
+        }
+    }
+
+    fn flush(&mut self, _req: &Request, _ino: u64, fh: u64, _lock_owner: u64, reply: fuser::ReplyEmpty) {
+        match self.flush_handle(fh) {
+            // Unknown handle: nothing resident to flush, not an error.
+            None | Some(Ok(())) => reply.ok(),
+            Some(Err(e)) => reply.error(e.to_errno()),
+        }
+    }
+
+    /// Guarantees a dirty write-back buffer has actually reached
+    /// durable storage, not just been accepted into memory — the same
+    /// upload `flush` performs on `close()`, run here for
+    /// `fsync()`/`fdatasync()` instead. `datasync` is ignored: content
+    /// and metadata are always written together via
+    /// `blockstore::write_range`, so there's no metadata-only sync to
+    /// skip.
+    fn fsync(&mut self, _req: &Request, _ino: u64, fh: u64, _datasync: bool, reply: fuser::ReplyEmpty) {
+        match self.flush_handle(fh) {
+            None | Some(Ok(())) => reply.ok(),
+            Some(Err(e)) => reply.error(e.to_errno()),
+        }
+    }
+
+    /// As `fsync`, but for a directory handle. A no-op: directories
+    /// have no write-back buffer to flush, since every `mkdir`/`rmdir`
+    /// already writes its marker object synchronously.
+    fn fsyncdir(&mut self, _req: &Request, _ino: u64, _fh: u64, _datasync: bool, reply: fuser::ReplyEmpty) {
+        reply.ok();
+    }
+
+    fn release(
+        &mut self,
+        req: &Request,
+        _ino: u64,
+        fh: u64,
+        _flags: i32,
+        _lock_owner: Option<u64>,
+        _flush: bool,
+        reply: fuser::ReplyEmpty,
+    ) {
+        self.metrics.op_release.fetch_add(1, Ordering::Relaxed);
+        if let Some(open) = self.handles.release(fh) {
+            if open.dirty {
+                let key = self.content_key(&open.path);
+                let _ = self.block_on(crate::blockstore::write_range(
+                    self.storage.as_ref(),
+                    &self.encryptor,
+                    &key,
+                    0,
+                    &open.buffer,
+                    self.compression.enabled,
+                    self.compression.level,
+                ));
+            }
+            self.audit(req, "release", &open.path, true);
+        }
+        reply.ok();
+    }
+
+    /// Lists `path`'s full, current contents: `.`/`..` plus every
+    /// visible child, classified the same way `readdir` always has.
+    /// Pulled out of `readdir` so `opendir` can snapshot exactly this
+    /// into a [`DirHandleTable`] handle instead of `readdir` re-running
+    /// it on every call.
+    fn list_directory_entries(&mut self, ino: u64, path: &str) -> crate::error::Result<Vec<(u64, FileType, String)>> {
+        let logical_prefix = if path.is_empty() {
+            String::new()
+        } else {
+            format!("{}/", path)
+        };
+        let list_prefix = self.storage_key(&logical_prefix);
+
+        let listing = self.block_on(self.storage.list_with_delimiter(&list_prefix, "/"))?;
+
+        let mut entries = vec![(ino, FileType::Directory, ".".to_string()), (ino, FileType::Directory, "..".to_string())];
+
+        let mut seen = std::collections::HashSet::new();
+        let classified = listing
+            .objects
+            .iter()
+            .filter_map(|obj| Self::classify_listed_object(&obj.key[list_prefix.len()..]))
+            .chain(listing.common_prefixes.iter().map(|prefix| Self::classify_listed_prefix(&prefix[list_prefix.len()..])));
+        for (raw_name, kind) in classified {
+            let name = self.decrypt_listed_name(raw_name);
+            if name.is_empty() || Self::is_dir_marker(&name) || name == CONTENT_PREFIX || !seen.insert(name.clone()) {
+                continue;
+            }
+            let child_path = Self::child_path(path, &name);
+            // `classify_listed_object`/`classify_listed_prefix` can only
+            // tell a file from a directory by object-key shape, so a
+            // real symlink still comes back as `RegularFile` here;
+            // refine it against its `.meta` sidecar the same way
+            // `getattr`/`lookup` do.
+            let kind = if kind == FileType::RegularFile {
+                let meta = self.block_on(crate::metadata::load_or_default(self.storage.as_ref(), &self.storage_key(&child_path), 0o644));
+                if meta.kind == FileKind::Symlink {
+                    FileType::Symlink
+                } else {
+                    FileType::RegularFile
+                }
+            } else {
+                kind
+            };
+            let child_ino = self.get_or_create_ino(&child_path);
+            entries.push((child_ino, kind, name));
+        }
+
+        Ok(entries)
+    }
+
+    /// Snapshots `path`'s listing into a [`DirHandleTable`] handle that
+    /// `readdir` serves from by offset and `releasedir` drops, so a
+    /// directory that changes mid-iteration (an entry added or removed
+    /// between `readdir` batches) can't make the kernel's offset-based
+    /// protocol skip or duplicate entries the way re-listing on every
+    /// call could.
+    fn opendir(&mut self, req: &Request, ino: u64, _flags: i32, reply: fuser::ReplyOpen) {
+        self.metrics.op_opendir.fetch_add(1, Ordering::Relaxed);
+        let path = match self.path_for_ino(ino) {
+            Some(p) => p,
+            None => {
+                reply.error(ENOENT);
+                return;
+            }
+        };
+
+        match self.list_directory_entries(ino, &path) {
+            Ok(entries) => {
+                let fh = self.dir_handles.open(entries);
+                self.audit(req, "opendir", &path, true);
+                reply.opened(fh, 0);
+            }
+            Err(e) => {
+                self.audit(req, "opendir", &path, false);
+                reply.error(e.to_errno());
+            }
+        }
+    }
+
+    fn readdir(
+        &mut self,
+        req: &Request,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+        mut reply: ReplyDirectory,
+    ) {
+        self.metrics.op_readdir.fetch_add(1, Ordering::Relaxed);
+        let path = match self.path_for_ino(ino) {
+            Some(p) => p,
+            None => {
+                reply.error(ENOENT);
+                return;
+            }
+        };
+
+        // `fh` should always be a snapshot `opendir` just took, but
+        // fall back to listing directly if it isn't (e.g. a caller that
+        // never went through `opendir`) rather than erroring out.
+        let entries = match self.dir_handles.with_handle(fh, |entries| entries.to_vec()) {
+            Some(entries) => entries,
+            None => match self.list_directory_entries(ino, &path) {
+                Ok(entries) => entries,
+                Err(e) => {
+                    self.audit(req, "readdir", &path, false);
+                    reply.error(e.to_errno());
+                    return;
+                }
+            },
+        };
+
+        for (i, (entry_ino, kind, name)) in entries.into_iter().enumerate().skip(offset as usize) {
+            if reply.add(entry_ino, (i + 1) as i64, kind, name) {
+                break;
+            }
+        }
+        self.audit(req, "readdir", &path, true);
+        reply.ok();
+    }
+
+    fn releasedir(&mut self, req: &Request, ino: u64, fh: u64, _flags: i32, reply: fuser::ReplyEmpty) {
+        self.metrics.op_releasedir.fetch_add(1, Ordering::Relaxed);
+        self.dir_handles.release(fh);
+        if let Some(path) = self.path_for_ino(ino) {
+            self.audit(req, "releasedir", &path, true);
+        }
+        reply.ok();
+    }
+
+    /// AegisFS doesn't track a real capacity limit (S3 and most local
+    /// filesystems don't have one worth reporting), so this reports a
+    /// large virtual capacity and computes `used`/`files` by listing
+    /// everything under the mount's prefix and summing/counting, rather
+    /// than maintaining a running total that could drift from reality.
+    /// Good enough for `df` and GUI file managers; not meant to be
+    /// exact under concurrent writers.
+    fn statfs(&mut self, _req: &Request, _ino: u64, reply: ReplyStatfs) {
+        const VIRTUAL_CAPACITY_BYTES: u64 = 16 * 1024 * 1024 * 1024 * 1024; // 16 TiB
+        let block_size = self.block_size as u32;
+
+        let (used_bytes, file_count) = self.usage_totals();
+
+        let total_blocks = VIRTUAL_CAPACITY_BYTES / block_size as u64;
+        let used_blocks = (used_bytes + block_size as u64 - 1) / block_size as u64;
+        let free_blocks = total_blocks.saturating_sub(used_blocks);
+
+        reply.statfs(
+            total_blocks,
+            free_blocks,
+            free_blocks,
+            file_count,
+            u64::MAX - file_count,
+            block_size,
+            255,
+            block_size,
+        );
+    }
+
+    /// For a whole-file copy (both offsets zero and `len` covering at
+    /// least the source's entire length) uses [`blockstore::copy_all`],
+    /// which duplicates every block without a full client-side
+    /// read/write — but still has to decrypt and re-encrypt each one,
+    /// since a block's ciphertext is bound as AAD to its own storage
+    /// key and can't be copied byte-for-byte onto a different one. Any
+    /// other offsets, or a `len` shorter than the source, fall back to
+    /// a decrypt/splice/reencrypt path instead, since a partial range
+    /// can't be expressed as a single object copy.
+    fn copy_file_range(
+        &mut self,
+        _req: &Request,
+        ino_in: u64,
+        _fh_in: u64,
+        offset_in: i64,
+        ino_out: u64,
+        _fh_out: u64,
+        offset_out: i64,
+        len: u64,
+        _flags: u32,
+        reply: ReplyWrite,
+    ) {
+        let src_path = match self.path_for_ino(ino_in) {
+            Some(p) => p,
+            None => {
+                reply.error(ENOENT);
+                return;
+            }
+        };
+        let dst_path = match self.path_for_ino(ino_out) {
+            Some(p) => p,
+            None => {
+                reply.error(ENOENT);
+                return;
+            }
+        };
+
+        if offset_in == 0 && offset_out == 0 {
+            let src_key = self.content_key(&src_path);
+            let src_len = self.block_on(crate::blockstore::total_len(self.storage.as_ref(), &src_key)).unwrap_or(0);
+            if len >= src_len {
+                let dst_key = self.content_key(&dst_path);
+                match self.block_on(crate::blockstore::copy_all(self.storage.as_ref(), &self.encryptor, &src_key, &dst_key)) {
+                    Ok(()) => {
+                        reply.written(src_len as u32);
+                        return;
+                    }
+                    Err(e) => {
+                        reply.error(e.to_errno());
+                        return;
+                    }
+                }
+            }
+        }
+
+        let src_key = self.content_key(&src_path);
+        let copied = match self.block_on(crate::blockstore::read_range(self.storage.as_ref(), &self.encryptor, &src_key, offset_in as u64, len)) {
+            Ok(data) => data,
+            Err(e) => {
+                reply.error(e.to_errno());
+                return;
+            }
+        };
+
+        let dst_key = self.content_key(&dst_path);
+        match self.block_on(crate::blockstore::write_range(
+            self.storage.as_ref(),
+            &self.encryptor,
+            &dst_key,
+            offset_out as u64,
+            &copied,
+            self.compression.enabled,
+            self.compression.level,
+        )) {
+            Ok(()) => reply.written(copied.len() as u32),
+            Err(e) => reply.error(e.to_errno()),
+        }
+    }
+
+    /// `FALLOC_FL_PUNCH_HOLE` zeros `[offset, offset + length)` without
+    /// changing the file's length, via [`crate::blockstore::punch_hole`].
+    /// The default mode (no flags, or `FALLOC_FL_KEEP_SIZE`) is meant to
+    /// preallocate storage for a future write; since there's no backend
+    /// notion of reserving space ahead of time, that's a no-op unless it
+    /// would also extend the file (bare default mode, without
+    /// `KEEP_SIZE`), in which case it's the same as a `setattr` size
+    /// bump. Any other flag (`COLLAPSE_RANGE`, `ZERO_RANGE`,
+    /// `INSERT_RANGE`) is rejected, since none of them are implemented.
+    fn fallocate(&mut self, _req: &Request, ino: u64, _fh: u64, offset: i64, length: i64, mode: i32, reply: fuser::ReplyEmpty) {
+        if self.read_only {
+            reply.error(libc::EROFS);
+            return;
+        }
+        if offset < 0 || length <= 0 {
+            reply.error(libc::EINVAL);
+            return;
+        }
+        let path = match self.path_for_ino(ino) {
+            Some(p) => p,
+            None => {
+                reply.error(ENOENT);
+                return;
+            }
+        };
+        let key = self.content_key(&path);
+
+        if mode & libc::FALLOC_FL_PUNCH_HOLE != 0 {
+            return match self.block_on(crate::blockstore::punch_hole(self.storage.as_ref(), &self.encryptor, &key, offset as u64, length as u64)) {
+                Ok(()) => reply.ok(),
+                Err(e) => reply.error(e.to_errno()),
+            };
+        }
+        if mode & !libc::FALLOC_FL_KEEP_SIZE != 0 {
+            reply.error(libc::EOPNOTSUPP);
+            return;
+        }
+
+        let new_len = offset as u64 + length as u64;
+        let current_len = self.block_on(crate::blockstore::total_len(self.storage.as_ref(), &key)).unwrap_or(0);
+        if mode & libc::FALLOC_FL_KEEP_SIZE != 0 || new_len <= current_len {
+            reply.ok();
+            return;
+        }
+        match self.block_on(crate::blockstore::truncate_with_block_size(self.storage.as_ref(), &self.encryptor, &key, new_len, self.block_size)) {
+            Ok(()) => {
+                self.attr_cache.lock().unwrap().remove(&path);
+                reply.ok();
+            }
+            Err(e) => reply.error(e.to_errno()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryStorage;
+
+    /// A real `AegisFS` over an in-memory backend, now that `storage`
+    /// is `Arc<dyn StorageBackend>` rather than a concrete `S3Storage`.
+    fn test_fs(dir_mode: DirectoryMode, key_prefix: &str) -> AegisFS {
+        let encryptor = Encryptor::new(&[7u8; 32]);
+        AegisFS::with_prefix(Arc::new(MemoryStorage::new()), encryptor, dir_mode, key_prefix.to_string())
+    }
+
+    fn test_fs_with_dedup(dir_mode: DirectoryMode) -> AegisFS {
+        AegisFS::with_dedup(
+            Arc::new(MemoryStorage::new()),
+            Encryptor::new(&[7u8; 32]),
+            dir_mode,
+            String::new(),
+            ConsistencyMode::default(),
+            DEFAULT_HANDLE_CAPACITY,
+            None,
+            Arc::new(crate::metrics::Metrics::default()),
+            false,
+            DEFAULT_TTL,
+            DEFAULT_TTL,
+            CompressionConfig::default(),
+            DedupConfig { enabled: true },
+        )
+    }
+
+    fn test_fs_with_path_crypt(dir_mode: DirectoryMode) -> AegisFS {
+        let key = [7u8; 32];
+        AegisFS::with_path_crypt(
+            Arc::new(MemoryStorage::new()),
+            Encryptor::new(&key),
+            dir_mode,
+            String::new(),
+            ConsistencyMode::default(),
+            DEFAULT_HANDLE_CAPACITY,
+            Some(crate::pathcrypt::PathCrypt::new(&key)),
+        )
+    }
+
+    #[test]
+    fn with_metrics_shares_the_given_instance_rather_than_minting_its_own() {
+        let metrics = Arc::new(crate::metrics::Metrics::default());
+        let fs = AegisFS::with_metrics(
+            Arc::new(MemoryStorage::new()),
+            Encryptor::new(&[7u8; 32]),
+            DirectoryMode::default(),
+            String::new(),
+            ConsistencyMode::default(),
+            DEFAULT_HANDLE_CAPACITY,
+            None,
+            Arc::clone(&metrics),
+        );
+
+        metrics.op_read.fetch_add(1, Ordering::Relaxed);
+        assert_eq!(fs.metrics().op_read.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn with_read_only_is_stored_on_the_instance() {
+        let fs = AegisFS::with_read_only(
+            Arc::new(MemoryStorage::new()),
+            Encryptor::new(&[7u8; 32]),
+            DirectoryMode::default(),
+            String::new(),
+            ConsistencyMode::default(),
+            DEFAULT_HANDLE_CAPACITY,
+            None,
+            Arc::new(crate::metrics::Metrics::default()),
+            true,
+        );
+
+        assert!(fs.read_only);
+    }
+
+    #[test]
+    fn with_cache_ttls_is_stored_on_the_instance_and_with_read_only_falls_back_to_the_default() {
+        let fs = AegisFS::with_cache_ttls(
+            Arc::new(MemoryStorage::new()),
+            Encryptor::new(&[7u8; 32]),
+            DirectoryMode::default(),
+            String::new(),
+            ConsistencyMode::default(),
+            DEFAULT_HANDLE_CAPACITY,
+            None,
+            Arc::new(crate::metrics::Metrics::default()),
+            false,
+            Duration::from_secs(30),
+            Duration::from_secs(10),
+        );
+        assert_eq!(fs.attr_ttl, Duration::from_secs(30));
+        assert_eq!(fs.entry_ttl, Duration::from_secs(10));
+
+        let fs = test_fs(DirectoryMode::Marker, "");
+        assert_eq!(fs.attr_ttl, DEFAULT_TTL);
+        assert_eq!(fs.entry_ttl, DEFAULT_TTL);
+    }
+
+    #[test]
+    fn with_compression_is_stored_on_the_instance_and_with_cache_ttls_falls_back_to_disabled() {
+        let fs = AegisFS::with_compression(
+            Arc::new(MemoryStorage::new()),
+            Encryptor::new(&[7u8; 32]),
+            DirectoryMode::default(),
+            String::new(),
+            ConsistencyMode::default(),
+            DEFAULT_HANDLE_CAPACITY,
+            None,
+            Arc::new(crate::metrics::Metrics::default()),
+            false,
+            DEFAULT_TTL,
+            DEFAULT_TTL,
+            CompressionConfig { enabled: true, level: 7 },
+        );
+        assert!(fs.compression.enabled);
+        assert_eq!(fs.compression.level, 7);
+
+        let fs = test_fs(DirectoryMode::Marker, "");
+        assert!(!fs.compression.enabled);
+    }
+
+    #[test]
+    fn with_dedup_is_stored_on_the_instance_and_with_compression_falls_back_to_disabled() {
+        let fs = AegisFS::with_dedup(
+            Arc::new(MemoryStorage::new()),
+            Encryptor::new(&[7u8; 32]),
+            DirectoryMode::default(),
+            String::new(),
+            ConsistencyMode::default(),
+            DEFAULT_HANDLE_CAPACITY,
+            None,
+            Arc::new(crate::metrics::Metrics::default()),
+            false,
+            DEFAULT_TTL,
+            DEFAULT_TTL,
+            CompressionConfig::default(),
+            DedupConfig { enabled: true },
+        );
+        assert!(fs.dedup.enabled);
+
+        let fs = test_fs(DirectoryMode::Marker, "");
+        assert!(!fs.dedup.enabled);
+    }
+
+    #[test]
+    fn with_block_size_is_stored_on_the_instance_and_with_dedup_falls_back_to_the_blockstore_default() {
+        let fs = AegisFS::with_block_size(
+            Arc::new(MemoryStorage::new()),
+            Encryptor::new(&[7u8; 32]),
+            DirectoryMode::default(),
+            String::new(),
+            ConsistencyMode::default(),
+            DEFAULT_HANDLE_CAPACITY,
+            None,
+            Arc::new(crate::metrics::Metrics::default()),
+            false,
+            DEFAULT_TTL,
+            DEFAULT_TTL,
+            CompressionConfig::default(),
+            DedupConfig::default(),
+            4096,
+        );
+        assert_eq!(fs.block_size, 4096);
+
+        let fs = test_fs(DirectoryMode::Marker, "");
+        assert_eq!(fs.block_size, crate::blockstore::BLOCK_SIZE);
+    }
+
+    #[test]
+    fn with_audit_log_records_nothing_until_one_is_supplied_and_with_block_size_falls_back_to_none() {
+        let fs = test_fs(DirectoryMode::Marker, "");
+        assert!(fs.audit.is_none());
+
+        let dir = tempfile::tempdir().unwrap();
+        let log_path = dir.path().join("audit.log");
+        let fs = AegisFS::with_audit_log(
+            Arc::new(MemoryStorage::new()),
+            Encryptor::new(&[7u8; 32]),
+            DirectoryMode::default(),
+            String::new(),
+            ConsistencyMode::default(),
+            DEFAULT_HANDLE_CAPACITY,
+            None,
+            Arc::new(crate::metrics::Metrics::default()),
+            false,
+            DEFAULT_TTL,
+            DEFAULT_TTL,
+            CompressionConfig::default(),
+            DedupConfig::default(),
+            crate::blockstore::BLOCK_SIZE,
+            Some(Arc::new(crate::audit::AuditLog::open(log_path.to_str().unwrap()).unwrap())),
+        );
+        assert!(fs.audit.is_some());
+    }
+
+    #[test]
+    fn with_journal_records_nothing_until_one_is_supplied_and_with_audit_log_falls_back_to_none() {
+        let fs = test_fs(DirectoryMode::Marker, "");
+        assert!(fs.journal.is_none());
+
+        let dir = tempfile::tempdir().unwrap();
+        let fs = AegisFS::with_journal(
+            Arc::new(MemoryStorage::new()),
+            Encryptor::new(&[7u8; 32]),
+            DirectoryMode::default(),
+            String::new(),
+            ConsistencyMode::default(),
+            DEFAULT_HANDLE_CAPACITY,
+            None,
+            Arc::new(crate::metrics::Metrics::default()),
+            false,
+            DEFAULT_TTL,
+            DEFAULT_TTL,
+            CompressionConfig::default(),
+            DedupConfig::default(),
+            crate::blockstore::BLOCK_SIZE,
+            None,
+            Some(Arc::new(crate::journal::Journal::open(dir.path()).unwrap())),
+        );
+        assert!(fs.journal.is_some());
+    }
+
+    #[test]
+    fn a_custom_block_size_is_reported_as_the_blksize_of_a_newly_created_file_and_directory() {
+        let fs = AegisFS::with_block_size(
+            Arc::new(MemoryStorage::new()),
+            Encryptor::new(&[7u8; 32]),
+            DirectoryMode::default(),
+            String::new(),
+            ConsistencyMode::default(),
+            DEFAULT_HANDLE_CAPACITY,
+            None,
+            Arc::new(crate::metrics::Metrics::default()),
+            false,
+            DEFAULT_TTL,
+            DEFAULT_TTL,
+            CompressionConfig::default(),
+            DedupConfig::default(),
+            4096,
+        );
+        let key = fs.storage_key("f.txt");
+        fs.block_on(crate::blockstore::create_empty_with_block_size(fs.storage.as_ref(), &key, fs.block_size)).unwrap();
+        let meta = FileMetadata::new_file(0o644, 0, 0);
+        let attr = AegisFS::file_attr_from_meta(1, 0, &meta, 1, fs.block_size as u32);
+        assert_eq!(attr.blksize, 4096);
+
+        let dir_attr = AegisFS::dir_attr(2, 1, fs.block_size as u32);
+        assert_eq!(dir_attr.blksize, 4096);
+    }
+
+    #[test]
+    fn implicit_mode_infers_directories_from_externally_written_keys() {
+        let fs = test_fs(DirectoryMode::Implicit, "");
+        fs.block_on(fs.storage.put("a/b/c.txt", b"x".to_vec())).unwrap();
+
+        assert!(fs.is_directory("a"));
+        assert!(fs.is_directory("a/b"));
+        assert!(!fs.is_directory("a/b/c.txt"));
+        assert!(!fs.is_directory("other"));
+    }
+
+    #[test]
+    fn nlink_for_dir_counts_real_subdirectories_not_files_or_block_groups() {
+        let fs = test_fs(DirectoryMode::Marker, "");
+        fs.block_on(fs.storage.put("docs/.dir", vec![])).unwrap();
+        fs.block_on(fs.storage.put("docs/a/.dir", vec![])).unwrap();
+        fs.block_on(fs.storage.put("docs/b/.dir", vec![])).unwrap();
+        fs.block_on(crate::blockstore::write_range(fs.storage.as_ref(), &fs.encryptor, "docs/c.txt", 0, b"hi", false, 0)).unwrap();
+
+        assert_eq!(fs.nlink_for_dir("docs"), 4);
+    }
+
+    #[test]
+    fn nlink_for_dir_is_the_bare_minimum_for_a_directory_with_no_subdirectories() {
+        let fs = test_fs(DirectoryMode::Marker, "");
+        fs.block_on(fs.storage.put("empty/.dir", vec![])).unwrap();
+
+        assert_eq!(fs.nlink_for_dir("empty"), 2);
+    }
+
+    #[test]
+    fn directory_has_children_is_false_for_a_directory_with_only_its_own_marker() {
+        let fs = test_fs(DirectoryMode::Marker, "");
+        fs.block_on(fs.storage.put("empty/.dir", vec![])).unwrap();
+
+        assert!(!fs.directory_has_children("empty").unwrap());
+    }
+
+    #[test]
+    fn directory_has_children_is_true_for_a_directory_containing_a_file() {
+        let fs = test_fs(DirectoryMode::Marker, "");
+        fs.block_on(fs.storage.put("docs/.dir", vec![])).unwrap();
+        fs.block_on(crate::blockstore::write_range(fs.storage.as_ref(), &fs.encryptor, "docs/report.txt", 0, b"hi", false, 0)).unwrap();
+
+        assert!(fs.directory_has_children("docs").unwrap());
+    }
+
+    #[test]
+    fn directory_has_children_is_true_for_a_directory_containing_only_a_subdirectory() {
+        let fs = test_fs(DirectoryMode::Marker, "");
+        fs.block_on(fs.storage.put("docs/.dir", vec![])).unwrap();
+        fs.block_on(fs.storage.put("docs/nested/.dir", vec![])).unwrap();
+
+        assert!(fs.directory_has_children("docs").unwrap());
+    }
+
+    #[test]
+    fn nlink_for_dir_is_cached_until_the_ttl_elapses() {
+        let fs = test_fs(DirectoryMode::Marker, "");
+        fs.block_on(fs.storage.put("docs/.dir", vec![])).unwrap();
+
+        assert_eq!(fs.nlink_for_dir("docs"), 2);
+
+        // A subdirectory added after the first call isn't picked up
+        // while the cached count is still fresh.
+        fs.block_on(fs.storage.put("docs/a/.dir", vec![])).unwrap();
+        assert_eq!(fs.nlink_for_dir("docs"), 2);
+
+        fs.nlink_cache.lock().unwrap().clear();
+        assert_eq!(fs.nlink_for_dir("docs"), 3);
+    }
+
+    #[test]
+    fn marker_mode_only_treats_dirs_with_a_dot_dir_marker_as_directories() {
+        let fs = test_fs(DirectoryMode::Marker, "");
+        fs.block_on(fs.storage.put("docs/.dir", vec![])).unwrap();
+
+        assert!(fs.is_directory("docs"));
+        assert!(!fs.is_directory("other"));
+    }
+
+    #[test]
+    fn marker_mode_survives_an_empty_directory_created_via_mkdirs_own_placeholder() {
+        // `mkdir` writes exactly `dir_placeholder_key`, nothing else, so
+        // an empty directory is just the marker object with no
+        // children. `is_directory` must treat that as a real directory
+        // rather than mistaking "listing is empty" for "not a
+        // directory" the way a listing-based check would.
+        let fs = test_fs(DirectoryMode::Marker, "");
+        let placeholder_key = fs.dir_placeholder_key("empty");
+        fs.block_on(fs.storage.put(&fs.storage_key(&placeholder_key), vec![])).unwrap();
+
+        assert!(fs.is_directory("empty"));
+    }
+
+    #[test]
+    fn storage_key_prefix_is_trimmed_and_spliced() {
+        let fs = test_fs(DirectoryMode::Marker, "/tenants/acme/");
+        assert_eq!(fs.storage_key("a.txt"), "tenants/acme/a.txt");
+        assert_eq!(fs.storage_key(""), "tenants/acme/");
+
+        let unprefixed = test_fs(DirectoryMode::Marker, "");
+        assert_eq!(unprefixed.storage_key("a.txt"), "a.txt");
+    }
+
+    #[test]
+    fn path_crypt_hides_the_plaintext_path_from_the_object_key() {
+        let fs = test_fs_with_path_crypt(DirectoryMode::Marker);
+        let key = fs.storage_key("docs/secret-report.txt");
+        assert!(!key.contains("docs"));
+        assert!(!key.contains("secret-report"));
+        assert_eq!(key.matches('/').count(), 1);
+    }
+
+    #[test]
+    fn readdir_decrypts_names_back_to_their_plaintext_form() {
+        let fs = test_fs_with_path_crypt(DirectoryMode::Marker);
+        let key = fs.storage_key("secret.txt");
+        fs.block_on(crate::blockstore::create_empty(fs.storage.as_ref(), &key)).unwrap();
+        fs.block_on(crate::metadata::save(
+            fs.storage.as_ref(),
+            &key,
+            &FileMetadata::new_file(0o644, 0, 0),
+        ))
+        .unwrap();
+
+        let list_prefix = fs.storage_key("");
+        let objects = fs.block_on(fs.storage.list(&list_prefix)).unwrap();
+        let names: std::collections::HashSet<_> = objects
+            .iter()
+            .map(|obj| {
+                let rest = &obj.key[list_prefix.len()..];
+                let raw_name = rest.split('/').next().unwrap_or(rest);
+                fs.decrypt_listed_name(raw_name)
+            })
+            .collect();
+
+        assert!(names.contains("secret.txt.blockhdr"));
+        assert!(names.contains("secret.txt.meta"));
+    }
+
+    #[test]
+    fn is_reserved_name_rejects_the_marker_and_every_sidecar_suffix() {
+        assert!(AegisFS::is_reserved_name(".dir"));
+        assert!(AegisFS::is_reserved_name("secret.txt.blockhdr"));
+        assert!(AegisFS::is_reserved_name("secret.txt.meta"));
+        assert!(AegisFS::is_reserved_name("secret.txt.block"));
+        assert!(AegisFS::is_reserved_name(CONTENT_PREFIX));
+        assert!(!AegisFS::is_reserved_name("secret.txt"));
+    }
+
+    #[test]
+    fn create_and_mkdir_reject_reserved_names_before_touching_storage() {
+        // `create`/`mkdir` both guard on `is_reserved_name` before doing
+        // anything with storage, the same way `lookup`/`unlink`/`rename`
+        // already did — exercised here directly since
+        // `fuser::Request`/`Reply*` can't be constructed in a unit test
+        // (see `create_resolves_a_subdirectory_parent_...` above).
+        // Landing a real object on one of these names would corrupt an
+        // unrelated file's metadata/block header, or plant a bogus entry
+        // in the hard-link/dedup content store.
+        for name in ["anything.meta", "anything.blockhdr", "anything.block", DIR_MARKER, CONTENT_PREFIX] {
+            assert!(AegisFS::is_reserved_name(name), "{name:?} should be rejected by create/mkdir's guard");
+        }
+        assert!(!AegisFS::is_reserved_name("report.txt"));
+    }
+
+    #[test]
+    fn classify_listed_object_collapses_a_files_sidecar_objects() {
+        assert_eq!(AegisFS::classify_listed_object("secret.txt.blockhdr"), Some(("secret.txt", FileType::RegularFile)));
+        assert_eq!(AegisFS::classify_listed_object("secret.txt.meta"), Some(("secret.txt", FileType::RegularFile)));
+    }
+
+    #[test]
+    fn classify_listed_object_hides_the_directorys_own_marker() {
+        assert_eq!(AegisFS::classify_listed_object(".dir"), None);
+    }
+
+    #[test]
+    fn classify_listed_prefix_reports_a_real_subdirectory() {
+        assert_eq!(AegisFS::classify_listed_prefix("notes/"), ("notes", FileType::Directory));
+    }
+
+    #[test]
+    fn classify_listed_prefix_reports_a_files_block_group_as_the_file() {
+        // A file's blocks are grouped under `name.block/`, which must
+        // not be mistaken for a real subdirectory.
+        assert_eq!(AegisFS::classify_listed_prefix("secret.txt.block/"), ("secret.txt", FileType::RegularFile));
+    }
+
+    #[test]
+    fn check_access_grants_root_every_mode_regardless_of_owner() {
+        assert!(AegisFS::check_access(0o600, 1000, 1000, 0, 0, libc::R_OK | libc::W_OK | libc::X_OK));
+    }
+
+    #[test]
+    fn check_access_f_ok_only_requires_the_caller_to_have_resolved_a_path() {
+        assert!(AegisFS::check_access(0o000, 1000, 1000, 2000, 2000, libc::F_OK));
+    }
+
+    #[test]
+    fn check_access_uses_the_owner_triad_when_uids_match() {
+        assert!(AegisFS::check_access(0o600, 1000, 1000, 1000, 2000, libc::R_OK | libc::W_OK));
+        assert!(!AegisFS::check_access(0o600, 1000, 1000, 1000, 2000, libc::X_OK));
+    }
+
+    #[test]
+    fn check_access_falls_back_to_the_group_triad_when_only_gid_matches() {
+        assert!(AegisFS::check_access(0o640, 1000, 1000, 2000, 1000, libc::R_OK));
+        assert!(!AegisFS::check_access(0o640, 1000, 1000, 2000, 1000, libc::W_OK));
+    }
+
+    #[test]
+    fn check_access_falls_back_to_the_other_triad_when_neither_uid_nor_gid_match() {
+        assert!(AegisFS::check_access(0o644, 1000, 1000, 2000, 2000, libc::R_OK));
+        assert!(!AegisFS::check_access(0o644, 1000, 1000, 2000, 2000, libc::W_OK));
+    }
+
+    /// Runs the same listing + classification `readdir` does, against
+    /// the root of `fs`, and returns the deduplicated (name, kind) pairs.
+    fn list_root_entries(fs: &AegisFS) -> Vec<(String, FileType)> {
+        let list_prefix = fs.storage_key("");
+        let listing = fs.block_on(fs.storage.list_with_delimiter(&list_prefix, "/")).unwrap();
+
+        let mut seen = std::collections::HashSet::new();
+        let mut entries = Vec::new();
+        let classified = listing
+            .objects
+            .iter()
+            .filter_map(|obj| AegisFS::classify_listed_object(&obj.key[list_prefix.len()..]))
+            .chain(listing.common_prefixes.iter().map(|prefix| AegisFS::classify_listed_prefix(&prefix[list_prefix.len()..])));
+        for (raw_name, kind) in classified {
+            let name = fs.decrypt_listed_name(raw_name);
+            if name == CONTENT_PREFIX || !seen.insert(name.clone()) {
+                continue;
+            }
+            let kind = if kind == FileType::RegularFile {
+                let meta = fs.block_on(crate::metadata::load_or_default(fs.storage.as_ref(), &fs.storage_key(&name), 0o644));
+                if meta.kind == FileKind::Symlink { FileType::Symlink } else { FileType::RegularFile }
+            } else {
+                kind
+            };
+            entries.push((name, kind));
+        }
+        entries
+    }
+
+    #[test]
+    fn readdir_collapses_a_files_sidecar_objects_into_one_entry() {
+        // Regression test: a file used to list as three bogus entries
+        // (`name.blockhdr`, `name.block` misclassified as a directory,
+        // `name.meta`) instead of one `RegularFile` named `name`.
+        let fs = test_fs(DirectoryMode::Marker, "");
+        let key = fs.storage_key("report.txt");
+        fs.block_on(crate::blockstore::write_range(fs.storage.as_ref(), &fs.encryptor, &key, 0, b"hello", false, 0)).unwrap();
+        fs.block_on(crate::metadata::save(fs.storage.as_ref(), &key, &FileMetadata::new_file(0o644, 0, 0))).unwrap();
+
+        assert_eq!(list_root_entries(&fs), vec![("report.txt".to_string(), FileType::RegularFile)]);
+    }
+
+    #[test]
+    fn readdir_reports_a_symlink_entry_distinctly_from_a_regular_file() {
+        let fs = test_fs(DirectoryMode::Marker, "");
+        let key = fs.storage_key("link");
+        fs.block_on(crate::blockstore::write_range(fs.storage.as_ref(), &fs.encryptor, &key, 0, b"target.txt", false, 0)).unwrap();
+        fs.block_on(crate::metadata::save(fs.storage.as_ref(), &key, &FileMetadata::new_symlink(0o777, 0, 0))).unwrap();
+
+        assert_eq!(list_root_entries(&fs), vec![("link".to_string(), FileType::Symlink)]);
+    }
+
+    #[test]
+    fn root_readdir_and_lookup_assign_the_same_child_the_same_inode() {
+        // Regression coverage for a root-directory entry's inode: both
+        // `readdir`'s per-entry loop and `lookup` must allocate it
+        // through `get_or_create_ino(&child_path)`, never anything
+        // derived from the listing's position/offset, or the two would
+        // disagree and a tool that caches by inode could end up
+        // reading/opening the wrong file.
+        let fs = test_fs(DirectoryMode::Marker, "");
+        let key = fs.storage_key("report.txt");
+        fs.block_on(crate::blockstore::write_range(fs.storage.as_ref(), &fs.encryptor, &key, 0, b"hello", false, 0)).unwrap();
+        fs.block_on(crate::metadata::save(fs.storage.as_ref(), &key, &FileMetadata::new_file(0o644, 0, 0))).unwrap();
+
+        // What `lookup` does for "report.txt" under the root.
+        let lookup_ino = fs.get_or_create_ino("report.txt");
+
+        // What `readdir`'s root branch does for the same entry: list,
+        // classify the listed key, and allocate its inode the same way.
+        let list_prefix = fs.storage_key("");
+        let listing = fs.block_on(fs.storage.list_with_delimiter(&list_prefix, "/")).unwrap();
+        let (raw_name, _) = listing
+            .objects
+            .iter()
+            .find_map(|obj| AegisFS::classify_listed_object(&obj.key[list_prefix.len()..]))
+            .expect("report.txt should be listed under the root prefix");
+        let name = fs.decrypt_listed_name(raw_name);
+        let child_path = AegisFS::child_path("", &name);
+        let readdir_ino = fs.get_or_create_ino(&child_path);
+
+        assert_eq!(lookup_ino, readdir_ino);
+    }
+
+    #[test]
+    fn prefetch_populates_the_inode_table_for_every_visited_path() {
+        let fs = test_fs(DirectoryMode::Marker, "");
+        fs.block_on(fs.storage.put("docs/.dir", vec![])).unwrap();
+        let key = fs.storage_key("docs/report.txt");
+        fs.block_on(crate::blockstore::write_range(fs.storage.as_ref(), &fs.encryptor, &key, 0, b"hello", false, 0)).unwrap();
+        fs.block_on(crate::metadata::save(fs.storage.as_ref(), &key, &FileMetadata::new_file(0o644, 0, 0))).unwrap();
+
+        let stats = fs.prefetch(8, 1_000);
+
+        assert_eq!(stats.directories, 1);
+        assert_eq!(stats.files, 1);
+        assert!(!stats.truncated);
+        assert!(fs.inodes.ino_for_path("docs").is_some());
+        assert!(fs.inodes.ino_for_path("docs/report.txt").is_some());
+    }
+
+    #[test]
+    fn prefetch_warms_the_attr_cache_under_strong_consistency() {
+        let fs = AegisFS::with_consistency_mode(Arc::new(MemoryStorage::new()), Encryptor::new(&[7u8; 32]), DirectoryMode::Marker, String::new(), ConsistencyMode::Strong);
+        let key = fs.storage_key("report.txt");
+        fs.block_on(crate::blockstore::write_range(fs.storage.as_ref(), &fs.encryptor, &key, 0, b"hello", false, 0)).unwrap();
+        fs.block_on(crate::metadata::save(fs.storage.as_ref(), &key, &FileMetadata::new_file(0o644, 0, 0))).unwrap();
+
+        fs.prefetch(8, 1_000);
+
+        assert!(fs.revalidate_cached_size("report.txt").is_some());
+    }
+
+    #[test]
+    fn prefetch_reports_truncated_once_the_object_limit_is_hit() {
+        let fs = test_fs(DirectoryMode::Marker, "");
+        for i in 0..5 {
+            let key = fs.storage_key(&format!("file{}.txt", i));
+            fs.block_on(crate::blockstore::write_range(fs.storage.as_ref(), &fs.encryptor, &key, 0, b"x", false, 0)).unwrap();
+            fs.block_on(crate::metadata::save(fs.storage.as_ref(), &key, &FileMetadata::new_file(0o644, 0, 0))).unwrap();
+        }
+
+        let stats = fs.prefetch(8, 2);
+
+        assert!(stats.truncated);
+        assert_eq!(stats.objects_visited, 2);
+    }
+
+    #[test]
+    fn prefetch_reports_truncated_once_the_depth_limit_is_hit() {
+        let fs = test_fs(DirectoryMode::Marker, "");
+        fs.block_on(fs.storage.put("a/.dir", vec![])).unwrap();
+        fs.block_on(fs.storage.put("a/b/.dir", vec![])).unwrap();
+
+        let stats = fs.prefetch(1, 1_000);
+
+        assert!(stats.truncated);
+        assert!(fs.inodes.ino_for_path("a").is_some());
+        assert!(fs.inodes.ino_for_path("a/b").is_none());
+    }
+
+    #[test]
+    fn create_resolves_a_subdirectory_parent_and_creates_the_file_under_it() {
+        // Exercises the same sequence `create()` runs for a non-root
+        // parent (`resolve_existing_dir` -> `child_path` ->
+        // `blockstore::create_empty` -> `metadata::save`) directly,
+        // since `fuser::Request`/`ReplyCreate` can't be constructed in a
+        // unit test. Regression coverage for the subdirectory branch
+        // specifically, as opposed to the already-covered root case.
+        let fs = test_fs(DirectoryMode::Marker, "");
+        fs.block_on(fs.storage.put("docs/.dir", vec![])).unwrap();
+        let parent_ino = fs.get_or_create_ino("docs");
+
+        let parent_path = fs.resolve_existing_dir(parent_ino).unwrap();
+        assert_eq!(parent_path, "docs");
+
+        let path = AegisFS::child_path(&parent_path, "report.txt");
+        let key = fs.storage_key(&path);
+        fs.block_on(crate::blockstore::create_empty(fs.storage.as_ref(), &key)).unwrap();
+        fs.block_on(crate::metadata::save(
+            fs.storage.as_ref(),
+            &key,
+            &FileMetadata::new_file(0o644, 0, 0),
+        ))
+        .unwrap();
+
+        assert_eq!(fs.file_size(&path).unwrap(), 0);
+        assert!(fs.block_on(crate::metadata::load_or_default(fs.storage.as_ref(), &key, 0)).mode == 0o644);
+    }
+
+    #[test]
+    fn file_contents_round_trip_through_the_block_store() {
+        let fs = test_fs(DirectoryMode::Marker, "");
+        let key = fs.storage_key("big.bin");
+        let data = vec![42u8; (crate::blockstore::BLOCK_SIZE * 2 + 17) as usize];
+
+        fs.block_on(crate::blockstore::write_range(fs.storage.as_ref(), &fs.encryptor, &key, 0, &data, false, 0))
+            .unwrap();
+        let read_back = fs
+            .block_on(crate::blockstore::read_range(fs.storage.as_ref(), &fs.encryptor, &key, 0, data.len() as u64))
+            .unwrap();
+
+        assert_eq!(read_back, data);
+        assert_eq!(fs.block_on(crate::blockstore::total_len(fs.storage.as_ref(), &key)).unwrap(), data.len() as u64);
+    }
+
+    #[test]
+    fn copy_tree_then_delete_tree_moves_a_file() {
+        let fs = test_fs(DirectoryMode::Marker, "");
+        let key = fs.storage_key("a.txt");
+        fs.block_on(crate::blockstore::write_range(fs.storage.as_ref(), &fs.encryptor, &key, 0, b"hello", false, 0))
+            .unwrap();
+
+        fs.copy_tree("a.txt", "b.txt", false).unwrap();
+        fs.delete_tree("a.txt", false).unwrap();
+
+        assert!(!fs.is_file("a.txt"));
+        assert!(fs.is_file("b.txt"));
+        let moved = fs
+            .block_on(crate::blockstore::read_range(fs.storage.as_ref(), &fs.encryptor, &fs.storage_key("b.txt"), 0, 5))
+            .unwrap();
+        assert_eq!(moved, b"hello");
+    }
+
+    #[test]
+    fn copy_tree_then_delete_tree_moves_a_populated_directory() {
+        let fs = test_fs(DirectoryMode::Marker, "");
+        fs.block_on(fs.storage.put(&fs.storage_key("docs/.dir"), vec![])).unwrap();
+        let key = fs.storage_key("docs/a.txt");
+        fs.block_on(crate::blockstore::write_range(fs.storage.as_ref(), &fs.encryptor, &key, 0, b"nested", false, 0))
+            .unwrap();
+
+        fs.copy_tree("docs", "archive", true).unwrap();
+        fs.delete_tree("docs", true).unwrap();
+
+        assert!(!fs.is_directory("docs"));
+        assert!(fs.is_directory("archive"));
+        assert!(fs.is_file("archive/a.txt"));
+        let moved = fs
+            .block_on(crate::blockstore::read_range(fs.storage.as_ref(), &fs.encryptor, &fs.storage_key("archive/a.txt"), 0, 6))
+            .unwrap();
+        assert_eq!(moved, b"nested");
+    }
+
+    #[test]
+    fn copy_tree_carries_a_directorys_own_metadata_sidecar() {
+        let fs = test_fs(DirectoryMode::Marker, "");
+        fs.block_on(fs.storage.put(&fs.storage_key("docs/.dir"), vec![])).unwrap();
+        let meta = FileMetadata::new_file(0o700, 42, 42);
+        fs.block_on(crate::metadata::save(fs.storage.as_ref(), &fs.storage_key("docs"), &meta)).unwrap();
+
+        fs.copy_tree("docs", "archive", true).unwrap();
+
+        let copied = fs.block_on(crate::metadata::load(fs.storage.as_ref(), &fs.storage_key("archive"))).unwrap();
+        assert_eq!(copied.mode, 0o700);
+        assert_eq!(copied.uid, 42);
+    }
+
+    #[test]
+    fn copy_file_range_whole_file_copy_reencrypts_under_the_destination_key() {
+        // Runs the same sequence `copy_file_range()` takes for a
+        // whole-file copy (offset_in == offset_out == 0, `len` at
+        // least the source's length), since `fuser::Request`/
+        // `ReplyWrite` can't be constructed in a unit test (see
+        // `create_resolves_a_subdirectory_parent_...` above). With
+        // filename encryption on, `src`'s and `dst`'s storage keys
+        // differ, so this only round-trips if `copy_all` actually
+        // re-encrypts each block under the destination key rather than
+        // copying the source's ciphertext byte-for-byte.
+        let fs = test_fs_with_path_crypt(DirectoryMode::Marker);
+        let src_key = fs.storage_key("src.txt");
+        let data = b"hello aegis";
+        fs.block_on(crate::blockstore::write_range(fs.storage.as_ref(), &fs.encryptor, &src_key, 0, data, false, 0)).unwrap();
+
+        let dst_key = fs.storage_key("dst.txt");
+        let src_len = fs.block_on(crate::blockstore::total_len(fs.storage.as_ref(), &src_key)).unwrap();
+        assert_eq!(data.len() as u64, src_len);
+        fs.block_on(crate::blockstore::copy_all(fs.storage.as_ref(), &fs.encryptor, &src_key, &dst_key)).unwrap();
+
+        let copied = fs.block_on(crate::blockstore::read_range(fs.storage.as_ref(), &fs.encryptor, &dst_key, 0, src_len)).unwrap();
+        assert_eq!(copied, data);
+    }
+
+    #[test]
+    fn copy_file_range_partial_copy_from_the_start_only_copies_len_bytes() {
+        // Regression test: `offset_in == offset_out == 0` alone isn't
+        // enough to take the whole-file `copy_all` fast path — `len`
+        // must also cover the source's entire length, or a caller
+        // chunking a large copy (as coreutils' `cp` does) would get
+        // the whole source file spliced into the destination instead
+        // of just the first `len` bytes. This runs the same sequence
+        // `copy_file_range()` takes once it falls through to the
+        // range-based path, since `fuser::Request`/`ReplyWrite` can't
+        // be constructed in a unit test.
+        let fs = test_fs(DirectoryMode::Marker, "");
+        let src_key = fs.storage_key("src.txt");
+        let data = vec![7u8; (crate::blockstore::BLOCK_SIZE + 1024) as usize];
+        fs.block_on(crate::blockstore::write_range(fs.storage.as_ref(), &fs.encryptor, &src_key, 0, &data, false, 0)).unwrap();
+
+        let dst_key = fs.storage_key("dst.txt");
+        let len = 65536u64;
+        assert!(len < data.len() as u64);
+        let copied = fs.block_on(crate::blockstore::read_range(fs.storage.as_ref(), &fs.encryptor, &src_key, 0, len)).unwrap();
+        fs.block_on(crate::blockstore::write_range(fs.storage.as_ref(), &fs.encryptor, &dst_key, 0, &copied, false, 0)).unwrap();
+
+        assert_eq!(fs.block_on(crate::blockstore::total_len(fs.storage.as_ref(), &dst_key)).unwrap(), len);
+        assert_eq!(copied.len() as u64, len);
+    }
+
+    #[test]
+    fn a_mount_wide_lifecycle_create_write_read_list_rename_and_delete_stays_consistent_at_every_step() {
+        // A single continuous scenario strung together from the building
+        // blocks the narrower tests above exercise individually, since
+        // `fuser::Request`/the `Reply*` types can't be constructed in a
+        // unit test (see `create_resolves_a_subdirectory_parent_...`
+        // above) and this is as close as a test gets to driving the FUSE
+        // handlers end to end.
+        let fs = test_fs(DirectoryMode::Marker, "");
+
+        // mkdir
+        fs.block_on(fs.storage.put(&fs.storage_key("docs/.dir"), vec![])).unwrap();
+        assert!(fs.is_directory("docs"));
+        assert_eq!(list_root_entries(&fs), vec![("docs".to_string(), FileType::Directory)]);
+
+        // create + write
+        let key = fs.storage_key("docs/report.txt");
+        fs.block_on(crate::blockstore::create_empty(fs.storage.as_ref(), &key)).unwrap();
+        fs.block_on(crate::metadata::save(fs.storage.as_ref(), &key, &FileMetadata::new_file(0o644, 1000, 1000))).unwrap();
+        fs.block_on(crate::blockstore::write_range(fs.storage.as_ref(), &fs.encryptor, &key, 0, b"hello aegis", false, 0))
+            .unwrap();
+
+        // read back
+        let read_back = fs.block_on(crate::blockstore::read_range(fs.storage.as_ref(), &fs.encryptor, &key, 0, 11)).unwrap();
+        assert_eq!(read_back, b"hello aegis");
+
+        // readdir lists the new file under its parent
+        let docs_prefix = fs.storage_key("docs/");
+        let listing = fs.block_on(fs.storage.list_with_delimiter(&docs_prefix, "/")).unwrap();
+        let names: Vec<&str> = listing
+            .objects
+            .iter()
+            .filter_map(|obj| AegisFS::classify_listed_object(&obj.key[docs_prefix.len()..]))
+            .map(|(name, _)| name)
+            .collect();
+        assert_eq!(names, vec!["report.txt"]);
+
+        // getattr-equivalent: size and mode are both reported correctly
+        assert_eq!(fs.file_size("docs/report.txt").unwrap(), 11);
+        let meta = fs.block_on(crate::metadata::load_or_default(fs.storage.as_ref(), &key, 0));
+        assert_eq!(meta.mode, 0o644);
+        assert_eq!(meta.uid, 1000);
+
+        // rename
+        fs.copy_tree("docs/report.txt", "docs/final.txt", false).unwrap();
+        fs.delete_tree("docs/report.txt", false).unwrap();
+        assert!(!fs.is_file("docs/report.txt"));
+        assert!(fs.is_file("docs/final.txt"));
+        let renamed_key = fs.storage_key("docs/final.txt");
+        let read_after_rename = fs.block_on(crate::blockstore::read_range(fs.storage.as_ref(), &fs.encryptor, &renamed_key, 0, 11)).unwrap();
+        assert_eq!(read_after_rename, b"hello aegis");
+
+        // delete
+        fs.delete_tree("docs/final.txt", false).unwrap();
+        assert!(!fs.is_file("docs/final.txt"));
+        let docs_listing = fs.block_on(fs.storage.list_with_delimiter(&docs_prefix, "/")).unwrap();
+        assert!(docs_listing.objects.is_empty());
+    }
+
+    #[test]
+    fn a_directory_without_a_metadata_sidecar_falls_back_to_the_default_mode() {
+        let fs = test_fs(DirectoryMode::Marker, "");
+        fs.block_on(fs.storage.put(&fs.storage_key("docs/.dir"), vec![])).unwrap();
+
+        let meta = fs.block_on(crate::metadata::load_or_default(fs.storage.as_ref(), &fs.storage_key("docs"), 0o755));
+        assert_eq!(meta.mode, 0o755);
+    }
+
+    #[test]
+    fn file_size_is_readable_from_the_header_alone_without_fetching_blocks() {
+        let fs = test_fs(DirectoryMode::Marker, "");
+        let key = fs.storage_key("big.bin");
+        let data = vec![1u8; (crate::blockstore::BLOCK_SIZE * 3) as usize];
+        fs.block_on(crate::blockstore::write_range(fs.storage.as_ref(), &fs.encryptor, &key, 0, &data, false, 0))
+            .unwrap();
+
+        // Drop every block object, leaving only the header behind, then
+        // confirm the size is still reported correctly: a correct
+        // fast-path implementation never needs to touch block data.
+        fs.block_on(fs.storage.delete(&format!("{}.block/{:010}", key, 0))).unwrap();
+        fs.block_on(fs.storage.delete(&format!("{}.block/{:010}", key, 1))).unwrap();
+        fs.block_on(fs.storage.delete(&format!("{}.block/{:010}", key, 2))).unwrap();
+
+        assert_eq!(fs.file_size("big.bin").unwrap(), data.len() as u64);
+    }
+
+    fn open_with_write_back(fs: &AegisFS, path: &str) -> u64 {
+        let storage = Arc::clone(&fs.storage);
+        let encryptor = fs.encryptor.clone();
+        let runtime = fs.runtime.handle().clone();
+        let key_prefix = fs.key_prefix.clone();
+        let path_crypt = fs.path_crypt.clone();
+        let compression = fs.compression.clone();
+        let dedup = fs.dedup.clone();
+        let block_size = fs.block_size;
+        let journal = fs.journal.clone();
+        fs.handles.open(path, Vec::new(), move |evicted| {
+            let _ = AegisFS::flush_open_file(&storage, &encryptor, &runtime, &key_prefix, path_crypt.as_ref(), &compression, &dedup, evicted, block_size, journal.as_ref());
+        })
+    }
+
+    #[test]
+    fn write_back_buffer_is_not_committed_until_flushed() {
+        let fs = test_fs(DirectoryMode::Marker, "");
+        let key = fs.storage_key("f.txt");
+        fs.block_on(crate::blockstore::create_empty(fs.storage.as_ref(), &key)).unwrap();
+
+        let fh = open_with_write_back(&fs, "f.txt");
+        fs.handles.with_handle(fh, |open| {
+            open.buffer = b"hello".to_vec();
+            open.dirty = true;
+        });
+
+        // Nothing committed to the backend while the handle stays open.
+        assert_eq!(fs.block_on(crate::blockstore::total_len(fs.storage.as_ref(), &key)).unwrap(), 0);
+
+        let open_file = fs.handles.release(fh).unwrap();
+        let runtime = fs.runtime.handle().clone();
+        AegisFS::flush_open_file(&fs.storage, &fs.encryptor, &runtime, &fs.key_prefix, fs.path_crypt.as_ref(), &fs.compression, &fs.dedup, &open_file, fs.block_size, fs.journal.as_ref()).unwrap();
+
+        let data = fs.block_on(crate::blockstore::read_range(fs.storage.as_ref(), &fs.encryptor, &key, 0, 5)).unwrap();
+        assert_eq!(data, b"hello");
+    }
+
+    #[test]
+    fn repeated_flushes_of_the_same_file_succeed_and_keep_content_etag_current() {
+        let fs = test_fs(DirectoryMode::Marker, "");
+        let key = fs.storage_key("f.txt");
+        fs.block_on(crate::blockstore::create_empty(fs.storage.as_ref(), &key)).unwrap();
+        fs.block_on(crate::metadata::save(fs.storage.as_ref(), &key, &FileMetadata::new_file(0o644, 0, 0))).unwrap();
+        let runtime = fs.runtime.handle().clone();
+
+        for body in [&b"first"[..], &b"second, and longer"[..]] {
+            let fh = open_with_write_back(&fs, "f.txt");
+            fs.handles.with_handle(fh, |open| {
+                open.buffer = body.to_vec();
+                open.dirty = true;
+            });
+            let open_file = fs.handles.release(fh).unwrap();
+            AegisFS::flush_open_file(&fs.storage, &fs.encryptor, &runtime, &fs.key_prefix, fs.path_crypt.as_ref(), &fs.compression, &fs.dedup, &open_file, fs.block_size, fs.journal.as_ref()).unwrap();
+        }
+
+        let meta = fs.block_on(crate::metadata::load(fs.storage.as_ref(), &key)).unwrap();
+        assert!(meta.content_etag.is_some());
+        assert_eq!(fs.block_on(crate::blockstore::read_range(fs.storage.as_ref(), &fs.encryptor, &key, 0, 32)).unwrap(), b"second, and longer");
+    }
+
+    #[test]
+    fn flush_fails_with_a_conflict_when_another_writer_changed_the_content_externally() {
+        let fs = test_fs(DirectoryMode::Marker, "");
+        let key = fs.storage_key("f.txt");
+        fs.block_on(crate::blockstore::create_empty(fs.storage.as_ref(), &key)).unwrap();
+        fs.block_on(crate::metadata::save(fs.storage.as_ref(), &key, &FileMetadata::new_file(0o644, 0, 0))).unwrap();
+        let runtime = fs.runtime.handle().clone();
+
+        let fh = open_with_write_back(&fs, "f.txt");
+        fs.handles.with_handle(fh, |open| {
+            open.buffer = b"first".to_vec();
+            open.dirty = true;
+        });
+        let open_file = fs.handles.release(fh).unwrap();
+        AegisFS::flush_open_file(&fs.storage, &fs.encryptor, &runtime, &fs.key_prefix, fs.path_crypt.as_ref(), &fs.compression, &fs.dedup, &open_file, fs.block_size, fs.journal.as_ref()).unwrap();
+
+        // Another client touches the content object directly, without
+        // going through this file's content_etag bookkeeping.
+        fs.block_on(crate::blockstore::write_range(fs.storage.as_ref(), &fs.encryptor, &key, 0, b"someone else's write", false, 0)).unwrap();
+
+        let fh = open_with_write_back(&fs, "f.txt");
+        fs.handles.with_handle(fh, |open| {
+            open.buffer = b"second".to_vec();
+            open.dirty = true;
+        });
+        let open_file = fs.handles.release(fh).unwrap();
+        let err = AegisFS::flush_open_file(&fs.storage, &fs.encryptor, &runtime, &fs.key_prefix, fs.path_crypt.as_ref(), &fs.compression, &fs.dedup, &open_file, fs.block_size, fs.journal.as_ref()).unwrap_err();
+        assert!(matches!(err, crate::error::AegisError::Conflict(_)));
+    }
+
+    #[test]
+    fn shutdown_handle_flushes_every_dirty_buffer_still_open() {
+        let fs = test_fs(DirectoryMode::Marker, "");
+        let key = fs.storage_key("f.txt");
+        fs.block_on(crate::blockstore::create_empty(fs.storage.as_ref(), &key)).unwrap();
+
+        let fh = open_with_write_back(&fs, "f.txt");
+        fs.handles.with_handle(fh, |open| {
+            open.buffer = b"hello".to_vec();
+            open.dirty = true;
+        });
+
+        let shutdown = fs.shutdown_handle();
+        assert_eq!(shutdown.flush_all(), 1);
+
+        let data = fs.block_on(crate::blockstore::read_range(fs.storage.as_ref(), &fs.encryptor, &key, 0, 5)).unwrap();
+        assert_eq!(data, b"hello");
+        assert_eq!(fs.handles.len(), 0);
+    }
+
+    #[test]
+    fn exceeding_handle_capacity_flushes_the_evicted_buffer_to_the_backend() {
+        let fs = AegisFS::with_handle_capacity(
+            Arc::new(MemoryStorage::new()),
+            Encryptor::new(&[7u8; 32]),
+            DirectoryMode::Marker,
+            String::new(),
+            ConsistencyMode::default(),
+            1,
+        );
+        fs.block_on(crate::blockstore::create_empty(fs.storage.as_ref(), "a.txt")).unwrap();
+        fs.block_on(crate::blockstore::create_empty(fs.storage.as_ref(), "b.txt")).unwrap();
+
+        let fh_a = open_with_write_back(&fs, "a.txt");
+        fs.handles.with_handle(fh_a, |open| {
+            open.buffer = b"first".to_vec();
+            open.dirty = true;
+        });
+
+        // Opening a second handle evicts "a.txt" (capacity 1), which
+        // must flush its dirty buffer before being dropped.
+        let _fh_b = open_with_write_back(&fs, "b.txt");
+
+        let data = fs.block_on(crate::blockstore::read_range(fs.storage.as_ref(), &fs.encryptor, "a.txt", 0, 5)).unwrap();
+        assert_eq!(data, b"first");
+    }
+
+    #[test]
+    fn flush_one_uploads_a_dirty_handle_and_marks_it_clean_without_releasing_it() {
+        let fs = test_fs(DirectoryMode::Marker, "");
+        let key = fs.storage_key("f.txt");
+        fs.block_on(crate::blockstore::create_empty(fs.storage.as_ref(), &key)).unwrap();
+
+        let fh = open_with_write_back(&fs, "f.txt");
+        fs.handles.with_handle(fh, |open| {
+            open.buffer = b"hello".to_vec();
+            open.dirty = true;
+        });
+
+        let shutdown = fs.shutdown_handle();
+        shutdown.flush_one(fh);
+
+        let data = fs.block_on(crate::blockstore::read_range(fs.storage.as_ref(), &fs.encryptor, &key, 0, 5)).unwrap();
+        assert_eq!(data, b"hello");
+        assert_eq!(fs.handles.with_handle(fh, |open| open.dirty), Some(false));
+    }
+
+    #[test]
+    fn flush_one_is_a_no_op_for_an_already_clean_or_unknown_handle() {
+        let fs = test_fs(DirectoryMode::Marker, "");
+        let fh = open_with_write_back(&fs, "f.txt");
+
+        let shutdown = fs.shutdown_handle();
+        // Neither call should panic or touch the backend.
+        shutdown.flush_one(fh);
+        shutdown.flush_one(fh + 1000);
+    }
+
+    #[test]
+    fn handles_due_for_flush_picks_up_buffers_older_than_the_flush_interval() {
+        let now = Instant::now();
+        let old = now - Duration::from_secs(60);
+        let fresh = now - Duration::from_secs(1);
+        let dirty = vec![(1, 10, old), (2, 10, fresh)];
+
+        let due = handles_due_for_flush(&dirty, now, Duration::from_secs(30), u64::MAX);
+
+        assert_eq!(due, vec![1]);
+    }
+
+    #[test]
+    fn handles_due_for_flush_picks_nothing_while_every_buffer_is_fresh_and_under_the_dirty_bytes_limit() {
+        let now = Instant::now();
+        let dirty = vec![(1, 10, now), (2, 10, now)];
+
+        let due = handles_due_for_flush(&dirty, now, Duration::from_secs(30), u64::MAX);
+
+        assert!(due.is_empty());
+    }
+
+    #[test]
+    fn handles_due_for_flush_flushes_the_largest_buffers_first_once_over_the_byte_limit() {
+        let now = Instant::now();
+        // None of these are old enough to flush on age alone.
+        let dirty = vec![(1, 100, now), (2, 300, now), (3, 50, now)];
+
+        // Limit of 200: the 300-byte buffer alone brings the total
+        // (450) back under the limit, so only it should be picked.
+        let due = handles_due_for_flush(&dirty, now, Duration::from_secs(30), 200);
+
+        assert_eq!(due, vec![2]);
+    }
+
+    #[test]
+    fn handles_due_for_flush_does_not_list_a_handle_twice_when_both_conditions_match() {
+        let now = Instant::now();
+        let old = now - Duration::from_secs(60);
+        let dirty = vec![(1, 300, old)];
+
+        let due = handles_due_for_flush(&dirty, now, Duration::from_secs(30), 100);
+
+        assert_eq!(due, vec![1]);
+    }
+
+    #[test]
+    fn normalize_path_collapses_duplicate_slashes_and_dot_components() {
+        assert_eq!(AegisFS::normalize_path("/a//b/./c/").unwrap(), "a/b/c");
+        assert_eq!(AegisFS::normalize_path("").unwrap(), "");
+        assert_eq!(AegisFS::normalize_path(".").unwrap(), "");
+    }
+
+    #[test]
+    fn normalize_path_resolves_dot_dot_within_the_mount() {
+        assert_eq!(AegisFS::normalize_path("a/b/../c").unwrap(), "a/c");
+        assert_eq!(AegisFS::normalize_path("a/../b").unwrap(), "b");
+    }
+
+    #[test]
+    fn normalize_path_rejects_traversal_above_the_mount_root() {
+        assert!(AegisFS::normalize_path("..").is_err());
+        assert!(AegisFS::normalize_path("../etc/passwd").is_err());
+        assert!(AegisFS::normalize_path("a/../../b").is_err());
+    }
+
+    #[test]
+    fn symlink_metadata_is_reported_as_a_symlink_not_a_regular_file() {
+        let meta = crate::metadata::FileMetadata::new_symlink(0o777, 1000, 1000);
+        let attr = AegisFS::file_attr_from_meta(42, 11, &meta, 1, crate::blockstore::BLOCK_SIZE as u32);
+        assert_eq!(attr.kind, FileType::Symlink);
+        assert_eq!(attr.perm, 0o777);
+    }
+
+    #[test]
+    fn symlink_target_round_trips_through_storage_like_readlink_would() {
+        let fs = test_fs(DirectoryMode::Marker, "");
+        let key = fs.storage_key("link-to-readme");
+        fs.block_on(crate::blockstore::write_range(fs.storage.as_ref(), &fs.encryptor, &key, 0, b"../readme.txt", false, 0)).unwrap();
+        let meta = crate::metadata::FileMetadata::new_symlink(0o777, 0, 0);
+        fs.block_on(crate::metadata::save(fs.storage.as_ref(), &key, &meta)).unwrap();
+
+        let len = fs.block_on(crate::blockstore::total_len(fs.storage.as_ref(), &key)).unwrap();
+        let target = fs.block_on(crate::blockstore::read_range(fs.storage.as_ref(), &fs.encryptor, &key, 0, len)).unwrap();
+        assert_eq!(target, b"../readme.txt");
+
+        let loaded_meta = fs.block_on(crate::metadata::load(fs.storage.as_ref(), &key)).unwrap();
+        assert_eq!(loaded_meta.kind, crate::metadata::FileKind::Symlink);
+    }
+
+    #[test]
+    fn usage_totals_sum_sizes_and_count_files_by_blockhdr() {
+        let fs = test_fs(DirectoryMode::Marker, "");
+        fs.block_on(crate::blockstore::write_range(fs.storage.as_ref(), &fs.encryptor, "a.txt", 0, b"hello", false, 0)).unwrap();
+        fs.block_on(crate::blockstore::write_range(fs.storage.as_ref(), &fs.encryptor, "dir/b.txt", 0, b"world!", false, 0)).unwrap();
+
+        let (used_bytes, file_count) = fs.usage_totals();
+        assert_eq!(file_count, 2);
+        assert!(used_bytes > 0);
+    }
+
+    #[test]
+    fn usage_totals_are_zero_for_an_empty_mount() {
+        let fs = test_fs(DirectoryMode::Marker, "");
+        assert_eq!(fs.usage_totals(), (0, 0));
+    }
+
+    #[test]
+    fn placeholder_key_differs_by_mode() {
+        assert_eq!(
+            AegisFS::dir_placeholder_key_for_mode(DirectoryMode::Marker, "docs"),
+            "docs/.dir"
+        );
+        assert_eq!(
+            AegisFS::dir_placeholder_key_for_mode(DirectoryMode::Implicit, "docs"),
+            "docs/"
+        );
+    }
+
+    // `fsync`/`fsyncdir` can't be exercised through the `Filesystem`
+    // trait directly (no public `Request`/`ReplyEmpty` constructors),
+    // so these drive the shared `flush_handle` helper the same way
+    // `flush` and `fsync` both do.
+
+    #[test]
+    fn flush_handle_uploads_a_dirty_buffer_and_clears_the_dirty_flag() {
+        let fs = test_fs(DirectoryMode::Marker, "");
+        let fh = fs.handles.open("a.txt", b"hello".to_vec(), |_| {});
+        fs.handles.with_handle(fh, |open| open.dirty = true).unwrap();
+
+        fs.flush_handle(fh).unwrap().unwrap();
+        assert!(!fs.handles.with_handle(fh, |open| open.dirty).unwrap());
+
+        let uploaded = fs.block_on(crate::blockstore::read_range(fs.storage.as_ref(), &fs.encryptor, "a.txt", 0, 5)).unwrap();
+        assert_eq!(uploaded, b"hello");
+    }
+
+    #[test]
+    fn flush_handle_is_a_no_op_for_a_clean_buffer() {
+        let fs = test_fs(DirectoryMode::Marker, "");
+        let fh = fs.handles.open("a.txt", b"hello".to_vec(), |_| {});
+
+        fs.flush_handle(fh).unwrap().unwrap();
+        assert!(fs.block_on(crate::blockstore::head(fs.storage.as_ref(), "a.txt")).is_err());
+    }
+
+    #[test]
+    fn flush_handle_returns_none_for_an_unknown_handle() {
+        let fs = test_fs(DirectoryMode::Marker, "");
+        assert!(fs.flush_handle(999).is_none());
+    }
+
+    /// Runs the same sequence `link()` does the first time `src_path`
+    /// is linked (or, if it's already been linked before, the sequence
+    /// for every link after the first), since `fuser::Request`/
+    /// `ReplyEntry` can't be constructed in a unit test. Returns the
+    /// resolved content key both paths now share.
+    fn link_paths(fs: &AegisFS, src_path: &str, dst_path: &str) -> String {
+        let src_key = fs.storage_key(src_path);
+        let mut meta = fs.block_on(crate::metadata::load_or_default(fs.storage.as_ref(), &src_key, 0o644));
+        meta.content_linked = true;
+
+        let content_key = match meta.content_id.clone() {
+            Some(id) => AegisFS::storage_key_with_prefix(&fs.key_prefix, fs.path_crypt.as_ref(), &AegisFS::content_object_path(&id)),
+            None => {
+                let id = crate::links::new_content_id();
+                let content_key = AegisFS::storage_key_with_prefix(&fs.key_prefix, fs.path_crypt.as_ref(), &AegisFS::content_object_path(&id));
+                fs.block_on(crate::blockstore::copy_all(fs.storage.as_ref(), &fs.encryptor, &src_key, &content_key)).unwrap();
+                fs.block_on(crate::blockstore::delete_all(fs.storage.as_ref(), &src_key)).unwrap();
+                meta.content_id = Some(id);
+                fs.block_on(crate::metadata::save(fs.storage.as_ref(), &src_key, &meta)).unwrap();
+                content_key
+            }
+        };
+
+        fs.block_on(crate::links::acquire(fs.storage.as_ref(), &content_key)).unwrap();
+        fs.block_on(crate::metadata::save(fs.storage.as_ref(), &fs.storage_key(dst_path), &meta)).unwrap();
+        content_key
+    }
+
+    #[test]
+    fn linking_a_never_linked_file_migrates_its_content_so_both_paths_read_it_back() {
+        let fs = test_fs(DirectoryMode::Marker, "");
+        let key = fs.storage_key("a.txt");
+        fs.block_on(crate::blockstore::write_range(fs.storage.as_ref(), &fs.encryptor, &key, 0, b"hello", false, 0)).unwrap();
+        fs.block_on(crate::metadata::save(fs.storage.as_ref(), &key, &FileMetadata::new_file(0o644, 0, 0))).unwrap();
+
+        link_paths(&fs, "a.txt", "b.txt");
+
+        assert!(fs.is_file("a.txt"));
+        assert!(fs.is_file("b.txt"));
+        let a_content = fs.content_key("a.txt");
+        assert_eq!(a_content, fs.content_key("b.txt"));
+        let read_back = fs.block_on(crate::blockstore::read_range(fs.storage.as_ref(), &fs.encryptor, &a_content, 0, 5)).unwrap();
+        assert_eq!(read_back, b"hello");
+    }
+
+    #[test]
+    fn file_nlink_is_one_before_linking_and_two_after_the_first_link() {
+        let fs = test_fs(DirectoryMode::Marker, "");
+        let key = fs.storage_key("a.txt");
+        fs.block_on(crate::blockstore::write_range(fs.storage.as_ref(), &fs.encryptor, &key, 0, b"hi", false, 0)).unwrap();
+        fs.block_on(crate::metadata::save(fs.storage.as_ref(), &key, &FileMetadata::new_file(0o644, 0, 0))).unwrap();
+
+        let meta_before = fs.block_on(crate::metadata::load_or_default(fs.storage.as_ref(), &key, 0o644));
+        assert_eq!(fs.file_nlink(&meta_before, &key), 1);
+
+        link_paths(&fs, "a.txt", "b.txt");
+
+        let content_key = fs.content_key("a.txt");
+        let meta_after = fs.block_on(crate::metadata::load_or_default(fs.storage.as_ref(), &fs.storage_key("a.txt"), 0o644));
+        assert_eq!(fs.file_nlink(&meta_after, &content_key), 2);
+
+        link_paths(&fs, "a.txt", "c.txt");
+        let meta_after = fs.block_on(crate::metadata::load_or_default(fs.storage.as_ref(), &fs.storage_key("a.txt"), 0o644));
+        assert_eq!(fs.file_nlink(&meta_after, &content_key), 3);
+    }
+
+    #[test]
+    fn unlinking_one_of_two_linked_paths_keeps_the_shared_content_alive_until_the_last_one_goes() {
+        let fs = test_fs(DirectoryMode::Marker, "");
+        let key = fs.storage_key("a.txt");
+        fs.block_on(crate::blockstore::write_range(fs.storage.as_ref(), &fs.encryptor, &key, 0, b"hi", false, 0)).unwrap();
+        fs.block_on(crate::metadata::save(fs.storage.as_ref(), &key, &FileMetadata::new_file(0o644, 0, 0))).unwrap();
+        link_paths(&fs, "a.txt", "b.txt");
+        let content_key = fs.content_key("a.txt");
+
+        fs.delete_file_content("a.txt").unwrap();
+        fs.block_on(crate::metadata::delete(fs.storage.as_ref(), &fs.storage_key("a.txt")));
+
+        assert!(fs.block_on(crate::blockstore::head(fs.storage.as_ref(), &content_key)).is_ok());
+        assert!(fs.is_file("b.txt"));
+
+        fs.delete_file_content("b.txt").unwrap();
+        assert!(fs.block_on(crate::blockstore::head(fs.storage.as_ref(), &content_key)).is_err());
+    }
+
+    #[test]
+    fn is_file_recognizes_a_hard_linked_path_even_though_nothing_lives_at_its_own_key() {
+        let fs = test_fs(DirectoryMode::Marker, "");
+        let key = fs.storage_key("a.txt");
+        fs.block_on(crate::blockstore::write_range(fs.storage.as_ref(), &fs.encryptor, &key, 0, b"hi", false, 0)).unwrap();
+        fs.block_on(crate::metadata::save(fs.storage.as_ref(), &key, &FileMetadata::new_file(0o644, 0, 0))).unwrap();
+
+        link_paths(&fs, "a.txt", "b.txt");
+
+        assert!(fs.block_on(crate::blockstore::head(fs.storage.as_ref(), &key)).is_err());
+        assert!(fs.is_file("a.txt"));
+    }
+
+    #[test]
+    fn readdir_never_surfaces_the_internal_content_prefix() {
+        let fs = test_fs(DirectoryMode::Marker, "");
+        let key = fs.storage_key("a.txt");
+        fs.block_on(crate::blockstore::write_range(fs.storage.as_ref(), &fs.encryptor, &key, 0, b"hi", false, 0)).unwrap();
+        fs.block_on(crate::metadata::save(fs.storage.as_ref(), &key, &FileMetadata::new_file(0o644, 0, 0))).unwrap();
+
+        link_paths(&fs, "a.txt", "b.txt");
+
+        let names: Vec<_> = list_root_entries(&fs).into_iter().map(|(name, _)| name).collect();
+        assert!(!names.contains(&CONTENT_PREFIX.to_string()));
+        assert!(names.contains(&"a.txt".to_string()));
+        assert!(names.contains(&"b.txt".to_string()));
+    }
+
+    fn flush_with_dedup(fs: &AegisFS, path: &str, bytes: &[u8]) {
+        let fh = fs.handles.open(path, Vec::new(), |_| {});
+        fs.handles.with_handle(fh, |open| {
+            open.buffer = bytes.to_vec();
+            open.dirty = true;
+        });
+        let open_file = fs.handles.release(fh).unwrap();
+        let runtime = fs.runtime.handle().clone();
+        AegisFS::flush_open_file(&fs.storage, &fs.encryptor, &runtime, &fs.key_prefix, fs.path_crypt.as_ref(), &fs.compression, &fs.dedup, &open_file, fs.block_size, fs.journal.as_ref()).unwrap();
+    }
+
+    #[test]
+    fn flushing_two_files_with_identical_content_dedups_the_second_onto_the_first() {
+        let fs = test_fs_with_dedup(DirectoryMode::Marker);
+        fs.block_on(crate::metadata::save(fs.storage.as_ref(), &fs.storage_key("a.txt"), &FileMetadata::new_file(0o644, 0, 0))).unwrap();
+        fs.block_on(crate::metadata::save(fs.storage.as_ref(), &fs.storage_key("b.txt"), &FileMetadata::new_file(0o644, 0, 0))).unwrap();
+
+        flush_with_dedup(&fs, "a.txt", b"same content");
+        flush_with_dedup(&fs, "b.txt", b"same content");
+
+        let a_content = fs.content_key("a.txt");
+        assert_eq!(a_content, fs.content_key("b.txt"));
+        assert_eq!(fs.block_on(crate::links::read_nlink(fs.storage.as_ref(), &a_content)), 2);
+
+        let read_back = fs.block_on(crate::blockstore::read_range(fs.storage.as_ref(), &fs.encryptor, &a_content, 0, 12)).unwrap();
+        assert_eq!(read_back, b"same content");
+    }
+
+    #[test]
+    fn flushing_a_file_with_unique_content_leaves_its_nlink_at_one() {
+        let fs = test_fs_with_dedup(DirectoryMode::Marker);
+        fs.block_on(crate::metadata::save(fs.storage.as_ref(), &fs.storage_key("a.txt"), &FileMetadata::new_file(0o644, 0, 0))).unwrap();
+
+        flush_with_dedup(&fs, "a.txt", b"nothing else matches this");
+
+        let content_key = fs.content_key("a.txt");
+        assert_eq!(fs.block_on(crate::links::read_nlink(fs.storage.as_ref(), &content_key)), 1);
+    }
+
+    #[test]
+    fn a_dedup_match_reports_nlink_one_not_the_shared_objects_refcount() {
+        // `flushing_two_files_with_identical_content_dedups_the_second_onto_the_first`
+        // already proves the shared content object's own refcount is 2 —
+        // but `a.txt` and `b.txt` were never `link()`ed, so `stat(2)`
+        // must keep reporting them as two independent files.
+        let fs = test_fs_with_dedup(DirectoryMode::Marker);
+        fs.block_on(crate::metadata::save(fs.storage.as_ref(), &fs.storage_key("a.txt"), &FileMetadata::new_file(0o644, 0, 0))).unwrap();
+        fs.block_on(crate::metadata::save(fs.storage.as_ref(), &fs.storage_key("b.txt"), &FileMetadata::new_file(0o644, 0, 0))).unwrap();
+
+        flush_with_dedup(&fs, "a.txt", b"same content");
+        flush_with_dedup(&fs, "b.txt", b"same content");
+
+        let content_key = fs.content_key("a.txt");
+        let meta_a = fs.block_on(crate::metadata::load_or_default(fs.storage.as_ref(), &fs.storage_key("a.txt"), 0o644));
+        let meta_b = fs.block_on(crate::metadata::load_or_default(fs.storage.as_ref(), &fs.storage_key("b.txt"), 0o644));
+        assert_eq!(fs.file_nlink(&meta_a, &content_key), 1);
+        assert_eq!(fs.file_nlink(&meta_b, &content_key), 1);
+    }
+
+    #[test]
+    fn rewriting_a_deduped_file_forks_its_own_content_instead_of_corrupting_its_dedup_match() {
+        // Regression test: `a.txt` and `b.txt` only ever matched by
+        // coincidence at flush time (never `link()`ed), so editing
+        // `a.txt` again must not change what `b.txt` reads back.
+        let fs = test_fs_with_dedup(DirectoryMode::Marker);
+        fs.block_on(crate::metadata::save(fs.storage.as_ref(), &fs.storage_key("a.txt"), &FileMetadata::new_file(0o644, 0, 0))).unwrap();
+        fs.block_on(crate::metadata::save(fs.storage.as_ref(), &fs.storage_key("b.txt"), &FileMetadata::new_file(0o644, 0, 0))).unwrap();
+
+        flush_with_dedup(&fs, "a.txt", b"same content");
+        flush_with_dedup(&fs, "b.txt", b"same content");
+        let shared_content = fs.content_key("a.txt");
+        assert_eq!(fs.content_key("b.txt"), shared_content);
+
+        flush_with_dedup(&fs, "a.txt", b"a.txt has since changed");
+
+        assert_ne!(fs.content_key("a.txt"), shared_content);
+        let a_read_back = fs.block_on(crate::blockstore::read_range(fs.storage.as_ref(), &fs.encryptor, &fs.content_key("a.txt"), 0, 24)).unwrap();
+        assert_eq!(a_read_back, b"a.txt has since changed");
+
+        let b_read_back = fs.block_on(crate::blockstore::read_range(fs.storage.as_ref(), &fs.encryptor, &shared_content, 0, 12)).unwrap();
+        assert_eq!(b_read_back, b"same content");
+        assert_eq!(fs.block_on(crate::links::read_nlink(fs.storage.as_ref(), &shared_content)), 1);
+    }
+
+    #[test]
+    fn rewriting_an_already_hard_linked_file_stays_visible_through_every_linked_path() {
+        let fs = test_fs_with_dedup(DirectoryMode::Marker);
+        let key = fs.storage_key("a.txt");
+        fs.block_on(crate::blockstore::write_range(fs.storage.as_ref(), &fs.encryptor, &key, 0, b"original", false, 0)).unwrap();
+        fs.block_on(crate::metadata::save(fs.storage.as_ref(), &key, &FileMetadata::new_file(0o644, 0, 0))).unwrap();
+        link_paths(&fs, "a.txt", "b.txt");
+        let shared_content = fs.content_key("a.txt");
+
+        flush_with_dedup(&fs, "a.txt", b"overwritten through a.txt");
+
+        assert_eq!(fs.content_key("b.txt"), shared_content);
+        let read_back = fs.block_on(crate::blockstore::read_range(fs.storage.as_ref(), &fs.encryptor, &shared_content, 0, 26)).unwrap();
+        assert_eq!(read_back, b"overwritten through a.txt");
+    }
+}
@@ -0,0 +1,314 @@
+//! Offline integrity checking for a bucket, without mounting it.
+//!
+//! Interrupted operations (a crashed `rmdir` that removed children but
+//! not the marker, a `mkdir` that never got populated, a crash between
+//! writing a file's blocks and its metadata sidecar, a write that
+//! crashed between staging a block's new ciphertext and renaming it
+//! onto the real block key, a dedup that never got to bump its
+//! refcount) can leave clutter or inconsistency behind.
+//! `scan` finds these; `repair` applies the fix `scan` recommends. Both
+//! are read-mostly: `scan` never mutates the backend, and `repair` only
+//! acts on exactly what the `FsckReport` it's given lists.
+
+use std::collections::{HashMap, HashSet};
+
+use serde::Serialize;
+
+use crate::error::Result;
+use crate::fs::{DirectoryMode, CONTENT_PREFIX, DIR_MARKER};
+use crate::metadata::FileKind;
+use crate::storage::StorageBackend;
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize)]
+pub struct FsckReport {
+    /// `.dir` markers whose directory has no other content.
+    pub orphaned_markers: Vec<String>,
+    /// Objects nested under a path with no `.dir` marker for that path,
+    /// making the chain back to the root incomplete.
+    pub dangling_children: Vec<String>,
+    /// Block headers and blocks no `.meta` sidecar (directly, or via a
+    /// hard link's `content_id`) claims as its content anymore, plus
+    /// any `.tmp` staging block left behind by a write that crashed
+    /// before [`crate::blockstore`] could rename it onto its real key.
+    pub orphaned_blocks: Vec<String>,
+    /// `.meta` sidecars for a plain (never-linked) file with no block
+    /// header at the same key, so the file they describe has no
+    /// content left to read.
+    pub dangling_metadata_sidecars: Vec<String>,
+    /// Content-addressed keys under [`CONTENT_PREFIX`] whose stored
+    /// `.nlink` count doesn't match how many `.meta` sidecars actually
+    /// point at them.
+    pub refcount_mismatches: Vec<String>,
+    /// Objects a `head` request failed against.
+    pub unreadable_headers: Vec<String>,
+}
+
+impl FsckReport {
+    pub fn is_clean(&self) -> bool {
+        self.orphaned_markers.is_empty()
+            && self.dangling_children.is_empty()
+            && self.orphaned_blocks.is_empty()
+            && self.dangling_metadata_sidecars.is_empty()
+            && self.refcount_mismatches.is_empty()
+            && self.unreadable_headers.is_empty()
+    }
+}
+
+/// Scans every object under `prefix` and reports integrity issues.
+///
+/// The `.dir`-marker checks (`orphaned_markers`, `dangling_children`)
+/// are only meaningful in [`DirectoryMode::Marker`]; [`DirectoryMode::Implicit`]
+/// has no marker objects that can go stale, so those two are always
+/// empty there. The block/metadata/refcount checks below don't depend
+/// on `dir_mode` at all and run either way.
+pub async fn scan<B: StorageBackend>(backend: &B, prefix: &str, dir_mode: DirectoryMode) -> Result<FsckReport> {
+    let mut report = FsckReport::default();
+
+    let objects = backend.list(prefix).await?;
+    let keys: HashSet<&str> = objects.iter().map(|o| o.key.as_str()).collect();
+
+    for obj in &objects {
+        if backend.head(&obj.key).await.is_err() {
+            report.unreadable_headers.push(obj.key.clone());
+        }
+
+        if dir_mode != DirectoryMode::Marker {
+            continue;
+        }
+
+        if let Some(dir) = obj.key.strip_suffix(&format!("/{}", DIR_MARKER)) {
+            let dir_prefix = format!("{}/", dir);
+            let has_other_content = objects.iter().any(|o| o.key != obj.key && o.key.starts_with(&dir_prefix));
+            if !has_other_content {
+                report.orphaned_markers.push(obj.key.clone());
+            }
+        } else if let Some(slash) = obj.key.rfind('/') {
+            let parent_marker = format!("{}/{}", &obj.key[..slash], DIR_MARKER);
+            if !keys.contains(parent_marker.as_str()) {
+                report.dangling_children.push(obj.key.clone());
+            }
+        }
+    }
+
+    // Metadata sidecars are plain JSON (unlike block content, they're
+    // never passed through an `Encryptor`), so they can be loaded and
+    // inspected here without a key. For every file, that's enough to
+    // tell whether its content lives at its own key (`content_id` is
+    // `None`) or at a shared, content-addressed key a hard link points
+    // at (`content_id` is `Some`), which is exactly what's needed to
+    // tell a file's own orphaned block header apart from a hard link's.
+    let mut plain_files: HashSet<String> = HashSet::new();
+    let mut content_ref_counts: HashMap<String, u32> = HashMap::new();
+    for obj in &objects {
+        if let Some(base_key) = obj.key.strip_suffix(".meta") {
+            if let Ok(meta) = crate::metadata::load(backend, base_key).await {
+                match meta.content_id {
+                    Some(id) => *content_ref_counts.entry(id).or_insert(0) += 1,
+                    None if meta.kind == FileKind::File => {
+                        plain_files.insert(base_key.to_string());
+                    }
+                    None => {}
+                }
+            }
+        }
+    }
+
+    for obj in &objects {
+        if let Some(base_key) = obj.key.strip_suffix(".blockhdr") {
+            let owned = plain_files.contains(base_key)
+                || base_key
+                    .strip_prefix(&format!("{}/", CONTENT_PREFIX))
+                    .is_some_and(|id| content_ref_counts.contains_key(id));
+            if !owned {
+                report.orphaned_blocks.push(obj.key.clone());
+            }
+        } else if let Some((base_key, index)) = obj.key.split_once(".block/") {
+            // A `.tmp` staging block is never referenced by anything —
+            // not even the header it's about to replace a block of —
+            // so it's always orphaned, header or no header.
+            if index.ends_with(".tmp") || !keys.contains(format!("{}.blockhdr", base_key).as_str()) {
+                report.orphaned_blocks.push(obj.key.clone());
+            }
+        } else if let Some(base_key) = obj.key.strip_suffix(".meta") {
+            if plain_files.contains(base_key) && !keys.contains(format!("{}.blockhdr", base_key).as_str()) {
+                report.dangling_metadata_sidecars.push(obj.key.clone());
+            }
+        }
+    }
+
+    for (content_id, actual_refs) in &content_ref_counts {
+        let content_key = format!("{}/{}", CONTENT_PREFIX, content_id);
+        if crate::links::read_nlink(backend, &content_key).await != *actual_refs {
+            report.refcount_mismatches.push(content_key);
+        }
+    }
+
+    Ok(report)
+}
+
+/// Applies the fixes a `scan` recommends.
+///
+/// - Orphaned markers are deleted outright, since by definition
+///   nothing else in the directory would be lost.
+/// - Dangling children and dangling metadata sidecars are quarantined
+///   under `lost+found/` rather than deleted, since they may still
+///   hold real data (or, for a sidecar, at least the mode/owner of
+///   whatever used to be there) worth recovering.
+/// - Orphaned blocks are deleted outright: by construction nothing
+///   claims them as content anymore, so nothing is lost.
+/// - Objects with unreadable headers, and refcount mismatches, are
+///   left untouched. A mismatch can only be fixed by re-deriving the
+///   authoritative count, which needs another full scan of every
+///   `.meta` sidecar in the bucket — worth surfacing to an operator
+///   rather than doing silently on every `--fix` run.
+pub async fn repair<B: StorageBackend>(backend: &B, report: &FsckReport) -> Result<()> {
+    for key in &report.orphaned_markers {
+        backend.delete(key).await?;
+    }
+    for key in report.dangling_children.iter().chain(&report.dangling_metadata_sidecars) {
+        let quarantined = format!("lost+found/{}", key);
+        backend.copy(key, &quarantined).await?;
+        backend.delete(key).await?;
+    }
+    for key in &report.orphaned_blocks {
+        backend.delete(key).await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryStorage;
+
+    #[tokio::test]
+    async fn detects_orphaned_markers_and_dangling_children_and_fixes_them() {
+        let backend = MemoryStorage::new();
+        backend.put("docs/.dir", vec![]).await.unwrap();
+        backend.put("photos/.dir", vec![]).await.unwrap();
+        backend.put("photos/trip.jpg", b"data".to_vec()).await.unwrap();
+        backend.put("orphan-parent/child/file.txt", b"data".to_vec()).await.unwrap();
+
+        let report = scan(&backend, "", DirectoryMode::Marker).await.unwrap();
+        assert_eq!(report.orphaned_markers, vec!["docs/.dir".to_string()]);
+        assert_eq!(report.dangling_children, vec!["orphan-parent/child/file.txt".to_string()]);
+        assert!(report.unreadable_headers.is_empty());
+
+        repair(&backend, &report).await.unwrap();
+
+        assert!(backend.get("docs/.dir").await.is_err());
+        assert!(backend.get("orphan-parent/child/file.txt").await.is_err());
+        assert_eq!(backend.get("lost+found/orphan-parent/child/file.txt").await.unwrap(), b"data");
+        assert_eq!(backend.get("photos/trip.jpg").await.unwrap(), b"data");
+    }
+
+    #[tokio::test]
+    async fn implicit_mode_is_always_reported_clean() {
+        let backend = MemoryStorage::new();
+        backend.put("a/b.txt", b"x".to_vec()).await.unwrap();
+        let report = scan(&backend, "", DirectoryMode::Implicit).await.unwrap();
+        assert!(report.is_clean());
+    }
+
+    #[tokio::test]
+    async fn detects_an_orphaned_block_header_and_a_dangling_sidecar_and_fixes_them() {
+        let backend = MemoryStorage::new();
+
+        // A normal file: block header + metadata sidecar, in sync.
+        crate::blockstore::create_empty(&backend, "docs/report.txt").await.unwrap();
+        crate::metadata::save(&backend, "docs/report.txt", &crate::metadata::FileMetadata::new_file(0o644, 0, 0)).await.unwrap();
+
+        // A block header left behind by an interrupted delete: its
+        // `.meta` sidecar is gone, so nothing claims it anymore.
+        crate::blockstore::create_empty(&backend, "docs/half-deleted.txt").await.unwrap();
+
+        // A metadata sidecar left behind by an interrupted create: its
+        // block header never got written.
+        crate::metadata::save(&backend, "docs/half-created.txt", &crate::metadata::FileMetadata::new_file(0o644, 0, 0)).await.unwrap();
+
+        let report = scan(&backend, "", DirectoryMode::Implicit).await.unwrap();
+        assert_eq!(report.orphaned_blocks, vec!["docs/half-deleted.txt.blockhdr".to_string()]);
+        assert_eq!(report.dangling_metadata_sidecars, vec!["docs/half-created.txt.meta".to_string()]);
+
+        repair(&backend, &report).await.unwrap();
+
+        assert!(backend.get("docs/half-deleted.txt.blockhdr").await.is_err());
+        assert!(backend.get("docs/half-created.txt.meta").await.is_err());
+        assert!(backend.get("lost+found/docs/half-created.txt.meta").await.is_ok());
+        // The untouched, in-sync file survives.
+        assert!(backend.get("docs/report.txt.blockhdr").await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn a_leftover_block_staging_key_is_orphaned_even_with_an_intact_header() {
+        let backend = MemoryStorage::new();
+
+        // A normal, fully-written file...
+        crate::metadata::save(&backend, "docs/report.txt", &crate::metadata::FileMetadata::new_file(0o644, 0, 0)).await.unwrap();
+        let enc = crate::encryption::Encryptor::new(&[0u8; 32]);
+        crate::blockstore::write_range(&backend, &enc, "docs/report.txt", 0, b"hello", false, 0).await.unwrap();
+
+        // ...plus a `.tmp` staging block left behind by a write that
+        // crashed after staging but before renaming it onto the real
+        // block key. The header and real block above are untouched.
+        backend.put("docs/report.txt.block/0000000000.tmp", b"stale ciphertext".to_vec()).await.unwrap();
+
+        let report = scan(&backend, "", DirectoryMode::Implicit).await.unwrap();
+        assert_eq!(report.orphaned_blocks, vec!["docs/report.txt.block/0000000000.tmp".to_string()]);
+
+        repair(&backend, &report).await.unwrap();
+
+        assert!(backend.get("docs/report.txt.block/0000000000.tmp").await.is_err());
+        assert_eq!(
+            crate::blockstore::read_range(&backend, &enc, "docs/report.txt", 0, 5).await.unwrap(),
+            b"hello"
+        );
+    }
+
+    #[tokio::test]
+    async fn a_hard_links_shared_content_is_not_mistaken_for_orphaned() {
+        let backend = MemoryStorage::new();
+        let content_key = format!("{}/{}", CONTENT_PREFIX, "abc123");
+
+        crate::blockstore::create_empty(&backend, &content_key).await.unwrap();
+        // The content starts at the implicit count of 1 (file A's own
+        // create); hard-linking file B onto it bumps that to 2.
+        crate::links::acquire(&backend, &content_key).await.unwrap();
+
+        let mut meta_a = crate::metadata::FileMetadata::new_file(0o644, 0, 0);
+        meta_a.content_id = Some("abc123".to_string());
+        crate::metadata::save(&backend, "docs/a.txt", &meta_a).await.unwrap();
+        let mut meta_b = crate::metadata::FileMetadata::new_file(0o644, 0, 0);
+        meta_b.content_id = Some("abc123".to_string());
+        crate::metadata::save(&backend, "docs/b.txt", &meta_b).await.unwrap();
+
+        let report = scan(&backend, "", DirectoryMode::Implicit).await.unwrap();
+        assert!(report.orphaned_blocks.is_empty());
+        assert!(report.refcount_mismatches.is_empty());
+    }
+
+    #[tokio::test]
+    async fn detects_a_refcount_that_doesnt_match_the_actual_number_of_links() {
+        let backend = MemoryStorage::new();
+        let content_key = format!("{}/{}", CONTENT_PREFIX, "deadbeef");
+        crate::blockstore::create_empty(&backend, &content_key).await.unwrap();
+
+        // Three files point at the shared content, but only one `acquire`
+        // ever landed on top of the implicit count of 1 — as if a crash
+        // happened between linking the third path and bumping the
+        // refcount.
+        crate::links::acquire(&backend, &content_key).await.unwrap();
+        let mut meta_a = crate::metadata::FileMetadata::new_file(0o644, 0, 0);
+        meta_a.content_id = Some("deadbeef".to_string());
+        crate::metadata::save(&backend, "docs/a.txt", &meta_a).await.unwrap();
+        let mut meta_b = crate::metadata::FileMetadata::new_file(0o644, 0, 0);
+        meta_b.content_id = Some("deadbeef".to_string());
+        crate::metadata::save(&backend, "docs/b.txt", &meta_b).await.unwrap();
+        let mut meta_c = crate::metadata::FileMetadata::new_file(0o644, 0, 0);
+        meta_c.content_id = Some("deadbeef".to_string());
+        crate::metadata::save(&backend, "docs/c.txt", &meta_c).await.unwrap();
+
+        let report = scan(&backend, "", DirectoryMode::Implicit).await.unwrap();
+        assert_eq!(report.refcount_mismatches, vec![content_key]);
+    }
+}
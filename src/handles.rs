@@ -0,0 +1,364 @@
+use std::collections::HashMap;
+use std::num::NonZeroUsize;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Instant;
+
+use lru::LruCache;
+
+/// An open file's in-memory buffer. `dirty` tracks whether `buffer`
+/// has writes that haven't been flushed to the backend yet.
+///
+/// `logical_len` is normally just `buffer.len()`, but can run ahead of
+/// it: a write landing far enough past the buffered region bypasses
+/// the buffer entirely (so it doesn't have to zero-fill a potentially
+/// enormous gap in memory, see `fs::SPARSE_WRITE_GAP_THRESHOLD`) and
+/// goes straight to the backend, which already knows how to leave an
+/// untouched gap as an implicit hole. `logical_len` is what tracks
+/// that the file is now longer than what's actually resident.
+pub struct OpenFile {
+    pub path: String,
+    pub buffer: Vec<u8>,
+    pub dirty: bool,
+    pub logical_len: u64,
+    /// When `dirty` most recently transitioned from `false` to `true`.
+    /// `None` while clean, or when `dirty` was set directly rather than
+    /// through [`Self::mark_dirty`] (as a handful of tests do) — the
+    /// background write-back flusher in `fs.rs` treats a missing
+    /// timestamp as "just now" rather than panicking on it. See
+    /// [`Self::mark_dirty`] for why a steady stream of writes doesn't
+    /// keep pushing this forward.
+    pub dirty_since: Option<Instant>,
+}
+
+impl OpenFile {
+    fn new(path: &str, buffer: Vec<u8>) -> Self {
+        let logical_len = buffer.len() as u64;
+        OpenFile { path: path.to_string(), buffer, dirty: false, logical_len, dirty_since: None }
+    }
+
+    /// Marks this handle dirty. Only records `dirty_since` on the
+    /// clean-to-dirty transition, so a file under a steady stream of
+    /// writes still ages normally instead of looking perpetually fresh
+    /// to the write-back flusher's "older than the flush interval"
+    /// check.
+    pub fn mark_dirty(&mut self) {
+        if !self.dirty {
+            self.dirty_since = Some(Instant::now());
+        }
+        self.dirty = true;
+    }
+
+    /// Marks this handle clean after a successful flush.
+    pub fn mark_clean(&mut self) {
+        self.dirty = false;
+        self.dirty_since = None;
+    }
+
+    /// Clamps `[offset, offset + size)` to this handle's true length
+    /// and returns the byte range to slice out of `buffer` for it, or
+    /// `None` if any of that range falls past what's actually
+    /// resident — e.g. past a gap a far write bypassed the buffer for
+    /// — and must be read from the backend instead.
+    pub fn readable_range(&self, offset: u64, size: u64) -> Option<std::ops::Range<usize>> {
+        let start = offset.min(self.logical_len);
+        let end = (start + size).min(self.logical_len);
+        (end as usize <= self.buffer.len()).then(|| start as usize..end as usize)
+    }
+
+    /// Records that the file is now known to be at least `end` bytes
+    /// long, whether those bytes landed in `buffer` or were written
+    /// straight to the backend instead.
+    pub fn extend_logical_len(&mut self, end: u64) {
+        self.logical_len = self.logical_len.max(end);
+    }
+}
+
+/// Bounds the number of open-file buffers AegisFS keeps resident, so a
+/// process opening thousands of files can't exhaust memory.
+///
+/// When the cap is exceeded, the least-recently-used handle is
+/// evicted: if it has unflushed writes, the caller-supplied `flush` is
+/// invoked before it's dropped; clean handles are simply dropped.
+/// Evicted handles are not "closed" from the kernel's point of view —
+/// a later read or write with the same `fh` goes straight to the
+/// backend instead (the `fh` itself stays valid; only its buffer is
+/// reclaimed, and it is not re-registered here).
+pub struct HandleTable {
+    inner: Mutex<LruCache<u64, OpenFile>>,
+    next_fh: AtomicU64,
+}
+
+impl HandleTable {
+    pub fn new(cap: usize) -> Self {
+        let cap = NonZeroUsize::new(cap).unwrap_or(NonZeroUsize::new(1).unwrap());
+        HandleTable {
+            inner: Mutex::new(LruCache::new(cap)),
+            next_fh: AtomicU64::new(1),
+        }
+    }
+
+    /// Registers a freshly-opened file, evicting the LRU entry first if
+    /// the table is already at capacity. Returns the new handle id.
+    pub fn open(&self, path: &str, buffer: Vec<u8>, flush: impl FnOnce(&OpenFile)) -> u64 {
+        let fh = self.next_fh.fetch_add(1, Ordering::SeqCst);
+        let mut inner = self.inner.lock().unwrap();
+
+        if inner.len() == inner.cap().get() {
+            if let Some((_, evicted)) = inner.pop_lru() {
+                if evicted.dirty {
+                    flush(&evicted);
+                }
+            }
+        }
+
+        inner.put(fh, OpenFile::new(path, buffer));
+        fh
+    }
+
+    pub fn with_handle<R>(&self, fh: u64, f: impl FnOnce(&mut OpenFile) -> R) -> Option<R> {
+        self.inner.lock().unwrap().get_mut(&fh).map(f)
+    }
+
+    pub fn release(&self, fh: u64) -> Option<OpenFile> {
+        self.inner.lock().unwrap().pop(&fh)
+    }
+
+    pub fn len(&self) -> usize {
+        self.inner.lock().unwrap().len()
+    }
+
+    /// Removes and returns every resident handle, e.g. for a graceful
+    /// shutdown that needs to flush whatever is still dirty before the
+    /// process exits. Clean handles are returned too (the caller just
+    /// ignores them), since there's no cheaper way to drain an
+    /// `LruCache` than to pop everything out of it.
+    pub fn take_all(&self) -> Vec<OpenFile> {
+        let mut inner = self.inner.lock().unwrap();
+        let mut taken = Vec::with_capacity(inner.len());
+        while let Some((_, open)) = inner.pop_lru() {
+            taken.push(open);
+        }
+        taken
+    }
+
+    /// Returns the id, buffered byte length, and dirty-since timestamp
+    /// of every currently-dirty handle, without evicting or otherwise
+    /// disturbing LRU order the way [`Self::take_all`] does — used by
+    /// the background write-back flusher (see `fs::AegisFS`) to decide
+    /// what's individually old, or collectively large, enough to flush
+    /// eagerly while everything else stays resident.
+    pub fn dirty_snapshot(&self) -> Vec<(u64, usize, Instant)> {
+        self.inner
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, open)| open.dirty)
+            .map(|(&fh, open)| (fh, open.buffer.len(), open.dirty_since.unwrap_or_else(Instant::now)))
+            .collect()
+    }
+}
+
+/// A directory's listing, snapshotted once by `opendir` and served by
+/// `readdir` at whatever offset the kernel asks for, so a directory that
+/// changes mid-`ls` can't make the listing skip or duplicate entries the
+/// way re-listing the backend on every `readdir` call could. Dropped by
+/// `releasedir`; unlike [`HandleTable`], there's nothing to flush on
+/// release, so this never needs an eviction policy either — a snapshot
+/// is small (names and inode numbers, not file contents) and short-lived
+/// (the life of one `ls`).
+pub struct DirHandleTable {
+    inner: Mutex<HashMap<u64, Vec<(u64, fuser::FileType, String)>>>,
+    next_fh: AtomicU64,
+}
+
+impl DirHandleTable {
+    pub fn new() -> Self {
+        DirHandleTable { inner: Mutex::new(HashMap::new()), next_fh: AtomicU64::new(1) }
+    }
+
+    /// Registers a freshly-listed directory snapshot. Returns the new
+    /// handle id.
+    pub fn open(&self, entries: Vec<(u64, fuser::FileType, String)>) -> u64 {
+        let fh = self.next_fh.fetch_add(1, Ordering::SeqCst);
+        self.inner.lock().unwrap().insert(fh, entries);
+        fh
+    }
+
+    pub fn with_handle<R>(&self, fh: u64, f: impl FnOnce(&[(u64, fuser::FileType, String)]) -> R) -> Option<R> {
+        self.inner.lock().unwrap().get(&fh).map(|entries| f(entries))
+    }
+
+    pub fn release(&self, fh: u64) {
+        self.inner.lock().unwrap().remove(&fh);
+    }
+}
+
+impl Default for DirHandleTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn eviction_flushes_dirty_handles_and_drops_clean_ones() {
+        let table = HandleTable::new(2);
+        let flushed = Arc::new(Mutex::new(Vec::new()));
+
+        let flush = |evicted: &OpenFile| {
+            // stand-in for flushed.lock().unwrap().push(...) done inline below
+            let _ = evicted;
+        };
+
+        let fh1 = table.open("a.txt", b"a".to_vec(), flush);
+        let fh2 = table.open("b.txt", b"b".to_vec(), flush);
+        assert_eq!(table.len(), 2);
+
+        // Mark fh1 dirty so its eviction must be observed via `flush`.
+        table.with_handle(fh1, |h| h.dirty = true);
+        // `with_handle` counts as a use, so touch fh2 afterwards to
+        // leave fh1 least-recently-used again.
+        table.with_handle(fh2, |_| ());
+
+        let flushed_clone = Arc::clone(&flushed);
+        let fh3 = table.open("c.txt", b"c".to_vec(), |evicted| {
+            flushed_clone.lock().unwrap().push(evicted.path.clone());
+        });
+
+        // fh1 was least-recently-used (fh2 was touched after it, and
+        // fh3 is new), so it should have been evicted+flushed.
+        assert_eq!(*flushed.lock().unwrap(), vec!["a.txt".to_string()]);
+        assert_eq!(table.len(), 2);
+        assert!(table.with_handle(fh1, |h| h.dirty).is_none());
+
+        // fh2 and fh3 survive with their data intact.
+        assert_eq!(table.with_handle(fh2, |h| h.buffer.clone()), Some(b"b".to_vec()));
+        assert_eq!(table.with_handle(fh3, |h| h.buffer.clone()), Some(b"c".to_vec()));
+    }
+
+    #[test]
+    fn take_all_drains_every_handle_regardless_of_dirty_state() {
+        let table = HandleTable::new(4);
+        table.open("a.txt", b"a".to_vec(), |_| {});
+        let fh2 = table.open("b.txt", b"b".to_vec(), |_| {});
+        table.with_handle(fh2, |h| h.dirty = true);
+
+        let taken = table.take_all();
+
+        assert_eq!(taken.len(), 2);
+        assert_eq!(table.len(), 0);
+        assert!(taken.iter().any(|o| o.path == "a.txt" && !o.dirty));
+        assert!(taken.iter().any(|o| o.path == "b.txt" && o.dirty));
+    }
+
+    #[test]
+    fn readable_range_serves_straight_out_of_the_buffer_when_nothing_bypassed_it() {
+        let open = OpenFile::new("a.txt", b"hello world".to_vec());
+        assert_eq!(open.readable_range(0, 11), Some(0..11));
+        assert_eq!(open.readable_range(6, 100), Some(6..11));
+        // Past EOF.
+        assert_eq!(open.readable_range(20, 5), Some(11..11));
+    }
+
+    #[test]
+    fn readable_range_defers_to_the_backend_once_logical_len_runs_ahead_of_the_buffer() {
+        let mut open = OpenFile::new("a.txt", b"hi".to_vec());
+        open.extend_logical_len(1_000_000);
+
+        // Still resident: served straight from `buffer`.
+        assert_eq!(open.readable_range(0, 2), Some(0..2));
+        // Past what's resident, even though it's within the file's
+        // true length: the caller must fall back to the backend.
+        assert_eq!(open.readable_range(0, 100), None);
+        assert_eq!(open.readable_range(999_000, 10), None);
+    }
+
+    #[test]
+    fn extend_logical_len_never_moves_backwards() {
+        let mut open = OpenFile::new("a.txt", b"hi".to_vec());
+        open.extend_logical_len(50);
+        open.extend_logical_len(10);
+        assert_eq!(open.logical_len, 50);
+    }
+
+    #[test]
+    fn mark_dirty_records_dirty_since_only_on_the_clean_to_dirty_transition() {
+        let mut open = OpenFile::new("a.txt", b"hi".to_vec());
+        assert_eq!(open.dirty_since, None);
+
+        open.mark_dirty();
+        let first = open.dirty_since.unwrap();
+
+        // A second write while already dirty must not push the
+        // timestamp forward, or a steadily-written file could dodge
+        // the age-based flush forever.
+        open.mark_dirty();
+        assert_eq!(open.dirty_since, Some(first));
+    }
+
+    #[test]
+    fn mark_clean_clears_both_dirty_and_dirty_since() {
+        let mut open = OpenFile::new("a.txt", b"hi".to_vec());
+        open.mark_dirty();
+
+        open.mark_clean();
+
+        assert!(!open.dirty);
+        assert_eq!(open.dirty_since, None);
+    }
+
+    #[test]
+    fn dirty_snapshot_reports_only_dirty_handles_without_disturbing_lru_order() {
+        let table = HandleTable::new(4);
+        let fh1 = table.open("a.txt", b"aaa".to_vec(), |_| {});
+        let fh2 = table.open("b.txt", b"bb".to_vec(), |_| {});
+        table.with_handle(fh2, |h| h.mark_dirty());
+
+        let snapshot = table.dirty_snapshot();
+
+        assert_eq!(snapshot.len(), 1);
+        assert_eq!(snapshot[0].0, fh2);
+        assert_eq!(snapshot[0].1, 2);
+
+        // Still resident afterwards, in both the usual sense...
+        assert_eq!(table.len(), 2);
+        // ...and with the kept entry unharmed.
+        assert_eq!(table.with_handle(fh1, |h| h.buffer.clone()), Some(b"aaa".to_vec()));
+    }
+
+    #[test]
+    fn dir_handle_table_serves_back_exactly_the_snapshot_it_was_given() {
+        let table = DirHandleTable::new();
+        let entries = vec![(1, fuser::FileType::Directory, "a".to_string()), (2, fuser::FileType::RegularFile, "b".to_string())];
+
+        let fh = table.open(entries.clone());
+
+        assert_eq!(table.with_handle(fh, |e| e.to_vec()), Some(entries));
+    }
+
+    #[test]
+    fn dir_handle_table_assigns_distinct_handles_per_open_call() {
+        let table = DirHandleTable::new();
+        let fh1 = table.open(vec![(1, fuser::FileType::Directory, "a".to_string())]);
+        let fh2 = table.open(vec![(2, fuser::FileType::Directory, "b".to_string())]);
+
+        assert_ne!(fh1, fh2);
+        assert_eq!(table.with_handle(fh1, |e| e.len()), Some(1));
+        assert_eq!(table.with_handle(fh2, |e| e.len()), Some(1));
+    }
+
+    #[test]
+    fn dir_handle_table_release_drops_the_snapshot() {
+        let table = DirHandleTable::new();
+        let fh = table.open(vec![(1, fuser::FileType::Directory, "a".to_string())]);
+
+        table.release(fh);
+
+        assert!(table.with_handle(fh, |_| ()).is_none());
+    }
+}
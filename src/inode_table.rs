@@ -0,0 +1,202 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+const ROOT_INO: u64 = 1;
+
+struct Inner {
+    next_ino: u64,
+    inode_map: HashMap<u64, String>,
+    path_map: HashMap<String, u64>,
+}
+
+/// Bidirectional inode <-> path bookkeeping for `AegisFS`.
+///
+/// `inode_map` and `path_map` are two views onto the same data, so they
+/// must only ever change together. Previously they were separate
+/// `Mutex`es acquired one after another, which left a window where
+/// concurrent FUSE callbacks (rename, unlink, lookup) could observe a
+/// path present in one map but not the other. `InodeTable` holds both
+/// behind a single lock so every operation is atomic with respect to
+/// the other maps.
+pub struct InodeTable {
+    inner: Mutex<Inner>,
+}
+
+impl InodeTable {
+    pub fn new() -> Self {
+        let mut inode_map = HashMap::new();
+        inode_map.insert(ROOT_INO, String::new());
+        let mut path_map = HashMap::new();
+        path_map.insert(String::new(), ROOT_INO);
+
+        InodeTable {
+            inner: Mutex::new(Inner {
+                next_ino: ROOT_INO + 1,
+                inode_map,
+                path_map,
+            }),
+        }
+    }
+
+    /// Returns the inode for `path`, allocating a new one if this is the
+    /// first time it has been seen.
+    pub fn get_or_create_ino(&self, path: &str) -> u64 {
+        let mut inner = self.inner.lock().unwrap();
+        if let Some(ino) = inner.path_map.get(path) {
+            return *ino;
+        }
+        let ino = inner.next_ino;
+        inner.next_ino += 1;
+        inner.inode_map.insert(ino, path.to_string());
+        inner.path_map.insert(path.to_string(), ino);
+        ino
+    }
+
+    pub fn path_for_ino(&self, ino: u64) -> Option<String> {
+        self.inner.lock().unwrap().inode_map.get(&ino).cloned()
+    }
+
+    pub fn ino_for_path(&self, path: &str) -> Option<u64> {
+        self.inner.lock().unwrap().path_map.get(path).copied()
+    }
+
+    /// Removes `path` from both maps atomically, returning its inode if
+    /// it was known.
+    pub fn remove(&self, path: &str) -> Option<u64> {
+        let mut inner = self.inner.lock().unwrap();
+        let ino = inner.path_map.remove(path)?;
+        inner.inode_map.remove(&ino);
+        Some(ino)
+    }
+
+    /// Atomically moves `old_path` to `new_path`, keeping the same
+    /// inode number. Returns the inode that was moved, or `None` if
+    /// `old_path` was not known.
+    pub fn rename(&self, old_path: &str, new_path: &str) -> Option<u64> {
+        let mut inner = self.inner.lock().unwrap();
+        let ino = inner.path_map.remove(old_path)?;
+        // An entry may already exist at new_path (overwrite rename);
+        // drop it so the maps don't end up with two paths claiming it.
+        if let Some(replaced_ino) = inner.path_map.insert(new_path.to_string(), ino) {
+            inner.inode_map.remove(&replaced_ino);
+        }
+        inner.inode_map.insert(ino, new_path.to_string());
+        Some(ino)
+    }
+
+    /// Like [`rename`](Self::rename), but for a directory: moves
+    /// `old_prefix` itself, plus every known path nested under it
+    /// (`old_prefix/...`), to the equivalent path under `new_prefix`,
+    /// atomically and with each entry keeping its inode number.
+    pub fn rename_prefix(&self, old_prefix: &str, new_prefix: &str) {
+        let mut inner = self.inner.lock().unwrap();
+
+        if let Some(ino) = inner.path_map.remove(old_prefix) {
+            if let Some(replaced_ino) = inner.path_map.insert(new_prefix.to_string(), ino) {
+                inner.inode_map.remove(&replaced_ino);
+            }
+            inner.inode_map.insert(ino, new_prefix.to_string());
+        }
+
+        let old_child_prefix = format!("{}/", old_prefix);
+        let nested: Vec<String> = inner
+            .path_map
+            .keys()
+            .filter(|p| p.starts_with(&old_child_prefix))
+            .cloned()
+            .collect();
+        for old_path in nested {
+            let new_path = format!("{}{}", new_prefix, &old_path[old_prefix.len()..]);
+            let ino = inner.path_map.remove(&old_path).expect("just observed in path_map");
+            if let Some(replaced_ino) = inner.path_map.insert(new_path.clone(), ino) {
+                inner.inode_map.remove(&replaced_ino);
+            }
+            inner.inode_map.insert(ino, new_path);
+        }
+    }
+}
+
+impl Default for InodeTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn maps_stay_consistent_after_create_rename_unlink() {
+        let table = Arc::new(InodeTable::new());
+
+        let ino = table.get_or_create_ino("a.txt");
+        assert_eq!(table.path_for_ino(ino).as_deref(), Some("a.txt"));
+
+        table.rename("a.txt", "b.txt");
+        assert_eq!(table.path_for_ino(ino).as_deref(), Some("b.txt"));
+        assert_eq!(table.ino_for_path("a.txt"), None);
+        assert_eq!(table.ino_for_path("b.txt"), Some(ino));
+
+        let removed = table.remove("b.txt");
+        assert_eq!(removed, Some(ino));
+        assert_eq!(table.path_for_ino(ino), None);
+    }
+
+    #[test]
+    fn rename_prefix_moves_a_directory_and_everything_nested_under_it() {
+        let table = InodeTable::new();
+
+        let dir_ino = table.get_or_create_ino("docs");
+        let a_ino = table.get_or_create_ino("docs/a.txt");
+        let b_ino = table.get_or_create_ino("docs/sub/b.txt");
+        let unrelated_ino = table.get_or_create_ino("docset.txt");
+
+        table.rename_prefix("docs", "archive");
+
+        assert_eq!(table.path_for_ino(dir_ino).as_deref(), Some("archive"));
+        assert_eq!(table.path_for_ino(a_ino).as_deref(), Some("archive/a.txt"));
+        assert_eq!(table.path_for_ino(b_ino).as_deref(), Some("archive/sub/b.txt"));
+        assert_eq!(table.ino_for_path("docs"), None);
+        assert_eq!(table.ino_for_path("docs/a.txt"), None);
+        // A sibling whose name merely starts with the same characters
+        // must not be swept up by the prefix match.
+        assert_eq!(table.path_for_ino(unrelated_ino).as_deref(), Some("docset.txt"));
+    }
+
+    #[test]
+    fn concurrent_create_rename_unlink_never_desyncs_maps() {
+        let table = Arc::new(InodeTable::new());
+        let mut handles = Vec::new();
+
+        for t in 0..8 {
+            let table = Arc::clone(&table);
+            handles.push(thread::spawn(move || {
+                for i in 0..200 {
+                    let path = format!("t{}-{}.txt", t, i);
+                    let ino = table.get_or_create_ino(&path);
+                    let renamed = format!("t{}-{}.renamed", t, i);
+                    table.rename(&path, &renamed);
+                    assert_eq!(table.ino_for_path(&renamed), Some(ino));
+                    table.remove(&renamed);
+                }
+            }));
+        }
+        for h in handles {
+            h.join().unwrap();
+        }
+
+        // Every remaining inode_map entry must have a matching path_map
+        // entry pointing back at it, and vice versa: the two views can
+        // never disagree, regardless of how the threads interleaved.
+        let inner = table.inner.lock().unwrap();
+        for (ino, path) in inner.inode_map.iter() {
+            assert_eq!(inner.path_map.get(path), Some(ino));
+        }
+        for (path, ino) in inner.path_map.iter() {
+            assert_eq!(inner.inode_map.get(ino), Some(path));
+        }
+    }
+}
@@ -0,0 +1,209 @@
+//! Local write-ahead journal bounding the data-loss window between a
+//! buffered write and its upload to the backend.
+//!
+//! A write-back buffer ([`crate::handles::OpenFile`]) only reaches the
+//! backend once it's flushed — on `close()`, `fsync()`, eviction, or the
+//! background flusher's own schedule (see `fs::AegisFS::flush_open_file`).
+//! A crash between a `write()` returning to the kernel and that flush
+//! landing loses the buffer with no trace it ever existed, since nothing
+//! durable records it was ever pending. [`Journal`] closes that window:
+//! right before a flush uploads a buffer, [`Journal::begin`] durably
+//! records its plaintext on local disk; once the upload succeeds,
+//! [`Journal::commit`] deletes the record. [`Journal::replay`], run once
+//! at mount time (see `fs::AegisFS::with_journal`), finishes uploading
+//! whatever records survived an unclean shutdown, so the data-loss
+//! window is bounded to whatever was in flight at the moment of the
+//! crash rather than losing every buffered write since the last flush.
+
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use serde::{Deserialize, Serialize};
+
+use crate::encryption::Encryptor;
+use crate::error::Result;
+use crate::storage::StorageBackend;
+
+/// Metadata written alongside a pending write's raw plaintext, enough
+/// for [`Journal::replay`] to reissue it as the exact
+/// `blockstore::write_range_with_block_size` call that would otherwise
+/// have made it to the backend.
+#[derive(Debug, Serialize, Deserialize)]
+struct Header {
+    key: String,
+    offset: u64,
+    compress_enabled: bool,
+    compress_level: i32,
+}
+
+/// Identifies one pending record written by [`Journal::begin`], for
+/// [`Journal::commit`] to remove once the write it stands in for has
+/// actually landed.
+pub struct JournalRecord {
+    path: PathBuf,
+}
+
+/// An on-disk directory of pending writes, opened once at mount time.
+pub struct Journal {
+    dir: PathBuf,
+    next_id: AtomicU64,
+}
+
+impl Journal {
+    /// Opens (creating if necessary) `dir` as this mount's journal
+    /// directory.
+    pub fn open(dir: impl Into<PathBuf>) -> std::io::Result<Self> {
+        let dir = dir.into();
+        std::fs::create_dir_all(&dir)?;
+        Ok(Journal { dir, next_id: AtomicU64::new(0) })
+    }
+
+    fn entry_path(&self, id: u64) -> PathBuf {
+        self.dir.join(format!("{:020}.entry", id))
+    }
+
+    /// Durably records `data` as pending for `key` at `offset`. Written
+    /// to a `.tmp` file and renamed into place — the same
+    /// stage-then-rename pattern `blockstore::write_block` uses — so a
+    /// crash mid-write leaves at most an orphaned `.tmp` file rather
+    /// than a half-written record [`Self::replay`] could misread.
+    pub fn begin(&self, key: &str, offset: u64, data: &[u8], compress_enabled: bool, compress_level: i32) -> std::io::Result<JournalRecord> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let path = self.entry_path(id);
+        let tmp_path = path.with_extension("tmp");
+
+        let header = serde_json::to_string(&Header { key: key.to_string(), offset, compress_enabled, compress_level })
+            .expect("Header contains no types that fail to serialize");
+        let mut file = std::fs::File::create(&tmp_path)?;
+        file.write_all(header.as_bytes())?;
+        file.write_all(b"\n")?;
+        file.write_all(data)?;
+        file.sync_all()?;
+        std::fs::rename(&tmp_path, &path)?;
+
+        Ok(JournalRecord { path })
+    }
+
+    /// Removes `record`, once the write it stands in for has actually
+    /// reached the backend. Already having been removed (e.g. by a
+    /// concurrent [`Self::replay`]) is not an error.
+    pub fn commit(&self, record: JournalRecord) -> std::io::Result<()> {
+        match std::fs::remove_file(&record.path) {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Reissues every record still on disk as the
+    /// `blockstore::write_range_with_block_size` upload it was recorded
+    /// ahead of, deleting each one as it lands, and returns how many
+    /// were replayed. Records are replayed in the order they were
+    /// written (their filenames sort lexicographically by id), so a
+    /// later write to the same key always wins over an earlier one —
+    /// matching the order they'd have landed in had the crash not
+    /// interrupted them. Always passes [`crate::blockstore::BLOCK_SIZE`]
+    /// for a not-yet-existing destination; an existing file ignores it
+    /// in favor of whatever block size its own header already recorded.
+    pub async fn replay(&self, storage: &dyn StorageBackend, encryptor: &Encryptor) -> Result<usize> {
+        let mut entries: Vec<PathBuf> = std::fs::read_dir(&self.dir)?
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().and_then(|ext| ext.to_str()) == Some("entry"))
+            .collect();
+        entries.sort();
+
+        let mut replayed = 0;
+        for path in entries {
+            let raw = std::fs::read(&path)?;
+            let newline = match raw.iter().position(|&b| b == b'\n') {
+                Some(i) => i,
+                None => continue,
+            };
+            let header: Header = match serde_json::from_slice(&raw[..newline]) {
+                Ok(header) => header,
+                Err(_) => continue,
+            };
+            let data = &raw[newline + 1..];
+
+            crate::blockstore::write_range_with_block_size(
+                storage,
+                encryptor,
+                &header.key,
+                header.offset,
+                data,
+                header.compress_enabled,
+                header.compress_level,
+                crate::blockstore::BLOCK_SIZE,
+            )
+            .await?;
+            std::fs::remove_file(&path)?;
+            replayed += 1;
+        }
+        Ok(replayed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryStorage;
+
+    fn encryptor() -> Encryptor {
+        Encryptor::new(&[6u8; 32])
+    }
+
+    #[test]
+    fn begin_then_commit_leaves_no_entry_behind() {
+        let dir = tempfile::tempdir().unwrap();
+        let journal = Journal::open(dir.path()).unwrap();
+
+        let record = journal.begin("f", 0, b"hello", false, 0).unwrap();
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+
+        journal.commit(record).unwrap();
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 0);
+    }
+
+    #[tokio::test]
+    async fn replay_uploads_every_record_left_behind_and_removes_them() {
+        let dir = tempfile::tempdir().unwrap();
+        let journal = Journal::open(dir.path()).unwrap();
+        let storage = MemoryStorage::new();
+        let enc = encryptor();
+
+        journal.begin("a.txt", 0, b"hello", false, 0).unwrap();
+        journal.begin("b.txt", 0, b"world", false, 0).unwrap();
+
+        let replayed = journal.replay(&storage, &enc).await.unwrap();
+
+        assert_eq!(replayed, 2);
+        assert_eq!(crate::blockstore::read_range(&storage, &enc, "a.txt", 0, 5).await.unwrap(), b"hello");
+        assert_eq!(crate::blockstore::read_range(&storage, &enc, "b.txt", 0, 5).await.unwrap(), b"world");
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 0);
+    }
+
+    #[tokio::test]
+    async fn replay_with_nothing_pending_is_a_no_op() {
+        let dir = tempfile::tempdir().unwrap();
+        let journal = Journal::open(dir.path()).unwrap();
+        let storage = MemoryStorage::new();
+
+        assert_eq!(journal.replay(&storage, &encryptor()).await.unwrap(), 0);
+    }
+
+    #[test]
+    fn a_leftover_tmp_file_is_not_mistaken_for_a_real_entry() {
+        let dir = tempfile::tempdir().unwrap();
+        let journal = Journal::open(dir.path()).unwrap();
+        std::fs::write(dir.path().join("00000000000000000099.tmp"), b"garbage").unwrap();
+
+        let record = journal.begin("f", 0, b"hi", false, 0).unwrap();
+        journal.commit(record).unwrap();
+
+        // The orphaned `.tmp` file is left alone; only `.entry` files
+        // are ever touched.
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+}
@@ -0,0 +1,146 @@
+//! A small, unencrypted per-bucket object recording which key a bucket
+//! is encrypted under, without storing (or being able to reveal) the
+//! key itself.
+//!
+//! [`crate::builder::build`] writes one on a bucket's first mount and
+//! compares against it on every mount after, so a passphrase pointed at
+//! the wrong bucket is rejected with a clear error instead of surfacing
+//! three layers down as a confusing decryption failure on the first
+//! real read. Unlike an encrypted sentinel, this is plain JSON: `verify`
+//! and `rotate-key` can read a bucket's recorded algorithm and format
+//! version without needing a working key at all.
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+use crate::encryption::EncryptionAlgorithm;
+use crate::error::{AegisError, Result};
+use crate::storage::StorageBackend;
+
+/// Bumped if this record's shape ever changes incompatibly. Distinct
+/// from the wire format version in `encryption.rs`, which versions the
+/// encrypted object header, not this plaintext one.
+const KEYSTORE_FORMAT_VERSION: u32 = 1;
+
+/// Context string mixed into the fingerprint HMAC so it can never be
+/// reused as, or confused with, an HMAC computed for any other purpose
+/// over the same key.
+const FINGERPRINT_CONTEXT: &[u8] = b"aegisfs-keystore-fingerprint-v1";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Keystore {
+    /// HMAC-SHA256 of a fixed context string under the bucket's data
+    /// key, base64-encoded. One-way: this never lets anyone recover
+    /// the key, only confirm whether a given key produces the same tag.
+    pub key_fingerprint: String,
+    pub format_version: u32,
+    pub algorithm: EncryptionAlgorithm,
+}
+
+/// Computes [`Keystore::key_fingerprint`] for `key`.
+fn fingerprint(key: &[u8; 32]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC-SHA256 accepts a key of any length");
+    mac.update(FINGERPRINT_CONTEXT);
+    URL_SAFE_NO_PAD.encode(mac.finalize().into_bytes())
+}
+
+/// Ensures `sentinel_key` in `storage` records `key`/`algorithm`,
+/// refusing to proceed if it already records a different key.
+///
+/// On a fresh bucket with nothing there yet, writes a new record and
+/// returns `Ok`, establishing `key` as this bucket's key going
+/// forward. On a bucket that already has one, a fingerprint mismatch
+/// means `key` is the wrong one for this bucket; a corrupt or
+/// unparseable record (tampering, or an object that predates this
+/// feature) is treated as a hard failure too, rather than silently
+/// overwriting whatever's already there.
+pub async fn check_or_initialize(
+    storage: &dyn StorageBackend,
+    sentinel_key: &str,
+    key: &[u8; 32],
+    algorithm: EncryptionAlgorithm,
+) -> Result<()> {
+    match storage.get(sentinel_key).await {
+        Ok(raw) => {
+            let recorded: Keystore = serde_json::from_slice(&raw)
+                .map_err(|e| AegisError::Encryption(format!("unreadable keystore object {}: {}", sentinel_key, e)))?;
+            if recorded.key_fingerprint != fingerprint(key) {
+                return Err(AegisError::KeyMismatch(sentinel_key.to_string()));
+            }
+            Ok(())
+        }
+        Err(_) => {
+            let record = Keystore { key_fingerprint: fingerprint(key), format_version: KEYSTORE_FORMAT_VERSION, algorithm };
+            let raw = serde_json::to_vec(&record)
+                .map_err(|e| AegisError::Encryption(format!("encoding keystore object {}: {}", sentinel_key, e)))?;
+            storage.put(sentinel_key, raw).await
+        }
+    }
+}
+
+/// Reads `sentinel_key`'s recorded state, for tooling (`verify`,
+/// `rotate-key`) that wants to know a bucket's algorithm/format without
+/// needing a working key to get at it. `None` if nothing has been
+/// written there yet.
+pub async fn read(storage: &dyn StorageBackend, sentinel_key: &str) -> Result<Option<Keystore>> {
+    match storage.get(sentinel_key).await {
+        Ok(raw) => {
+            let record = serde_json::from_slice(&raw)
+                .map_err(|e| AegisError::Encryption(format!("unreadable keystore object {}: {}", sentinel_key, e)))?;
+            Ok(Some(record))
+        }
+        Err(_) => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryStorage;
+
+    #[tokio::test]
+    async fn a_fresh_bucket_writes_a_keystore_record_on_first_check() {
+        let storage = MemoryStorage::new();
+        let key = [1u8; 32];
+
+        check_or_initialize(&storage, ".keystore", &key, EncryptionAlgorithm::Aes256Gcm).await.unwrap();
+
+        let record = read(&storage, ".keystore").await.unwrap().unwrap();
+        assert_eq!(record.algorithm, EncryptionAlgorithm::Aes256Gcm);
+        assert_eq!(record.format_version, KEYSTORE_FORMAT_VERSION);
+    }
+
+    #[tokio::test]
+    async fn the_same_key_passes_on_every_later_check() {
+        let storage = MemoryStorage::new();
+        let key = [2u8; 32];
+
+        check_or_initialize(&storage, ".keystore", &key, EncryptionAlgorithm::Aes256Gcm).await.unwrap();
+        assert!(check_or_initialize(&storage, ".keystore", &key, EncryptionAlgorithm::Aes256Gcm).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn a_different_key_is_rejected_with_key_mismatch() {
+        let storage = MemoryStorage::new();
+        check_or_initialize(&storage, ".keystore", &[3u8; 32], EncryptionAlgorithm::Aes256Gcm).await.unwrap();
+
+        let err = check_or_initialize(&storage, ".keystore", &[4u8; 32], EncryptionAlgorithm::Aes256Gcm).await.unwrap_err();
+        assert!(matches!(err, AegisError::KeyMismatch(_)));
+    }
+
+    #[test]
+    fn the_fingerprint_never_contains_the_key_bytes() {
+        let key = [0x42u8; 32];
+        let fp = fingerprint(&key);
+        assert!(!fp.contains(&URL_SAFE_NO_PAD.encode(key)));
+    }
+
+    #[tokio::test]
+    async fn read_returns_none_for_a_bucket_with_no_keystore_yet() {
+        let storage = MemoryStorage::new();
+        assert_eq!(read(&storage, ".keystore").await.unwrap(), None);
+    }
+}
@@ -0,0 +1,73 @@
+//! Resolves the master key from AWS KMS instead of a passphrase, so
+//! the plaintext key is never persisted anywhere AegisFS controls.
+//!
+//! [`resolve_master_key`] is the entry point `builder::build` and
+//! every single-object CLI command call instead of
+//! [`crate::encryption::derive_key_from_password`] directly: with
+//! `[kms]` configured, `encryption.key_path` (or `AEGIS_KEY`/stdin) is
+//! read as a base64 KMS ciphertext blob and sent to `Decrypt`; without
+//! it, the exact same passphrase+Argon2 path runs as before. Producing
+//! the ciphertext blob in the first place is an out-of-band, one-time
+//! operation (e.g. `aws kms encrypt`), same as `encryption.salt`'s
+//! generation — AegisFS only ever decrypts it.
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+
+use crate::config::{EncryptionConfig, KmsConfig};
+use crate::encryption::derive_key_from_password;
+use crate::error::{AegisError, Result};
+
+/// Resolves the 32-byte master key for `encryption`, going through KMS
+/// when `kms` is configured and falling back to the plain
+/// passphrase-derivation path when it isn't.
+pub async fn resolve_master_key(encryption: &EncryptionConfig, kms: Option<&KmsConfig>) -> Result<[u8; 32]> {
+    match kms {
+        Some(kms) => decrypt_master_key(kms, &encryption.load_passphrase()?).await,
+        None => derive_key_from_password(&encryption.load_passphrase()?, encryption.salt.as_bytes()),
+    }
+}
+
+/// Asks AWS KMS to `Decrypt` `ciphertext_b64` (the base64 text an
+/// operator gets back from e.g. `aws kms encrypt --output text
+/// --query CiphertextBlob`) and returns the plaintext data key.
+/// Errors if the decrypted plaintext isn't exactly 32 bytes, since
+/// that's always a misconfiguration (wrong blob, wrong key) rather
+/// than anything retrying would fix.
+pub async fn decrypt_master_key(kms: &KmsConfig, ciphertext_b64: &str) -> Result<[u8; 32]> {
+    let ciphertext = STANDARD
+        .decode(ciphertext_b64.trim())
+        .map_err(|e| AegisError::Encryption(format!("kms ciphertext blob is not valid base64: {}", e)))?;
+
+    let shared_config = aws_config::from_env().region(aws_config::Region::new(kms.region.clone())).load().await;
+    let client = aws_sdk_kms::Client::new(&shared_config);
+
+    let response = client
+        .decrypt()
+        .key_id(&kms.key_arn)
+        .ciphertext_blob(aws_sdk_kms::primitives::Blob::new(ciphertext))
+        .send()
+        .await
+        .map_err(|e| AegisError::Encryption(format!("kms decrypt: {}", e)))?;
+
+    let plaintext = response
+        .plaintext
+        .ok_or_else(|| AegisError::Encryption("kms decrypt returned no plaintext".to_string()))?
+        .into_inner();
+
+    plaintext
+        .try_into()
+        .map_err(|v: Vec<u8>| AegisError::Encryption(format!("kms-decrypted master key is {} bytes, want 32", v.len())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn a_ciphertext_blob_that_isnt_valid_base64_is_rejected_before_any_kms_call() {
+        let kms = KmsConfig { key_arn: "arn:aws:kms:us-east-1:123456789012:key/unused".into(), region: "us-east-1".into() };
+        let err = decrypt_master_key(&kms, "not valid base64!!!").await.unwrap_err();
+        assert!(matches!(err, AegisError::Encryption(_)));
+    }
+}
@@ -0,0 +1,35 @@
+pub mod audit;
+pub mod bench;
+pub mod blockstore;
+pub mod builder;
+pub mod codec;
+pub mod compress;
+pub mod config;
+pub mod encryption;
+pub mod error;
+pub mod fs;
+pub mod fsck;
+pub mod handles;
+pub mod inode_table;
+pub mod journal;
+pub mod keystore;
+pub mod kms;
+pub mod links;
+pub mod ls;
+pub mod metadata;
+pub mod metrics;
+pub mod migrate;
+pub mod pathcrypt;
+pub mod rotate;
+pub mod session;
+pub mod snapshot;
+pub mod sparse;
+pub mod storage;
+pub mod throttle;
+pub mod verify;
+
+pub use config::Config;
+pub use encryption::{EncryptionAlgorithm, Encryptor};
+pub use error::{AegisError, Result};
+pub use fs::{AegisFS, DirectoryMode};
+pub use storage::{S3Storage, StorageBackend};
@@ -0,0 +1,154 @@
+//! Reference counting for hard-linked file content.
+//!
+//! Backends are plain key-value stores, so [`crate::fs::AegisFS::link`]
+//! can't point two paths at the same inode the way a real filesystem
+//! would. Instead, the first time a path is ever linked, its content is
+//! migrated to a stable, path-independent content id (see
+//! [`new_content_id`]), and every linking path's own `.meta` sidecar
+//! records that id instead of holding content directly (see
+//! `FileMetadata::content_id`). This module tracks, for a given content
+//! id's storage key, how many paths currently reference it, in a small
+//! sidecar object colocated with the content itself — the single source
+//! of truth `getattr` consults for `nlink`, rather than a count
+//! duplicated into every linked path's own sidecar.
+
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+use crate::error::Result;
+use crate::storage::StorageBackend;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+struct RefCount {
+    nlink: u32,
+}
+
+/// A fresh, random content id: 16 bytes of entropy, hex-encoded so it's
+/// safe to splice directly into an object key.
+pub fn new_content_id() -> String {
+    let mut bytes = [0u8; 16];
+    rand::rngs::OsRng.fill_bytes(&mut bytes);
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Context string mixed into [`content_hash`] so it can never be
+/// reused as, or confused with, an HMAC computed under the same key
+/// for any other purpose (mirrors `crate::keystore::FINGERPRINT_CONTEXT`).
+const CONTENT_HASH_CONTEXT: &[u8] = b"aegisfs-content-dedup-v1";
+
+/// A deterministic content id for `plaintext`: HMAC-SHA256 under the
+/// bucket's own data key, hex-encoded so it slots into
+/// [`crate::fs::AegisFS::content_object_path`] exactly like
+/// [`new_content_id`]'s random one does. Keyed, rather than a plain
+/// hash, so identical files can only be recognized as such by someone
+/// who holds the data key — a plain hash would let the backend (or
+/// anyone who can list the bucket) learn which files are identical
+/// across users just by comparing content ids.
+pub fn content_hash(key: &[u8; 32], plaintext: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC-SHA256 accepts a key of any length");
+    mac.update(CONTENT_HASH_CONTEXT);
+    mac.update(plaintext);
+    mac.finalize().into_bytes().iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn nlink_key(content_key: &str) -> String {
+    format!("{}.nlink", content_key)
+}
+
+/// The current reference count for `content_key`, or `1` if no refcount
+/// object exists yet — the implicit count for a content id that was
+/// just created, before anything has called [`acquire`] against it.
+pub async fn read_nlink(storage: &dyn StorageBackend, content_key: &str) -> u32 {
+    match storage.get(&nlink_key(content_key)).await {
+        Ok(raw) => serde_json::from_slice::<RefCount>(&raw).map(|r| r.nlink).unwrap_or(1),
+        Err(_) => 1,
+    }
+}
+
+async fn write_nlink(storage: &dyn StorageBackend, content_key: &str, nlink: u32) -> Result<()> {
+    let raw = serde_json::to_vec(&RefCount { nlink }).expect("RefCount always serializes");
+    storage.put(&nlink_key(content_key), raw).await
+}
+
+/// Registers one more path referencing `content_key`'s content,
+/// returning the new total. Called by `link` (a fresh hard link) and by
+/// `copy_tree` (a `rename` duplicating a reference onto the destination
+/// before the source's own reference is dropped).
+pub async fn acquire(storage: &dyn StorageBackend, content_key: &str) -> Result<u32> {
+    let nlink = read_nlink(storage, content_key).await + 1;
+    write_nlink(storage, content_key, nlink).await?;
+    Ok(nlink)
+}
+
+/// Drops one path's reference to `content_key`'s content, returning the
+/// remaining count. Deletes the refcount object itself once it reaches
+/// zero, so it doesn't linger forever once nothing points at the
+/// content anymore; the caller is responsible for deleting the content
+/// itself in that case.
+pub async fn release(storage: &dyn StorageBackend, content_key: &str) -> Result<u32> {
+    let nlink = read_nlink(storage, content_key).await.saturating_sub(1);
+    if nlink == 0 {
+        let _ = storage.delete(&nlink_key(content_key)).await;
+    } else {
+        write_nlink(storage, content_key, nlink).await?;
+    }
+    Ok(nlink)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryStorage;
+
+    #[tokio::test]
+    async fn a_content_id_with_no_refcount_object_yet_defaults_to_one_reference() {
+        let storage = MemoryStorage::new();
+        assert_eq!(read_nlink(&storage, "content/abc").await, 1);
+    }
+
+    #[tokio::test]
+    async fn acquire_bumps_the_count_starting_from_the_implicit_one() {
+        let storage = MemoryStorage::new();
+        assert_eq!(acquire(&storage, "content/abc").await.unwrap(), 2);
+        assert_eq!(acquire(&storage, "content/abc").await.unwrap(), 3);
+        assert_eq!(read_nlink(&storage, "content/abc").await, 3);
+    }
+
+    #[tokio::test]
+    async fn release_drops_the_count_and_deletes_the_refcount_object_at_zero() {
+        let storage = MemoryStorage::new();
+        acquire(&storage, "content/abc").await.unwrap();
+        acquire(&storage, "content/abc").await.unwrap();
+
+        assert_eq!(release(&storage, "content/abc").await.unwrap(), 2);
+        assert_eq!(release(&storage, "content/abc").await.unwrap(), 1);
+        assert_eq!(release(&storage, "content/abc").await.unwrap(), 0);
+        assert!(storage.get("content/abc.nlink").await.is_err());
+    }
+
+    #[test]
+    fn new_content_id_is_stable_length_hex() {
+        let id = new_content_id();
+        assert_eq!(id.len(), 32);
+        assert!(id.chars().all(|c| c.is_ascii_hexdigit()));
+    }
+
+    #[test]
+    fn content_hash_is_deterministic_for_the_same_key_and_plaintext() {
+        let key = [7u8; 32];
+        assert_eq!(content_hash(&key, b"hello"), content_hash(&key, b"hello"));
+    }
+
+    #[test]
+    fn content_hash_differs_for_different_plaintext() {
+        let key = [7u8; 32];
+        assert_ne!(content_hash(&key, b"hello"), content_hash(&key, b"goodbye"));
+    }
+
+    #[test]
+    fn content_hash_differs_across_keys_for_the_same_plaintext() {
+        assert_ne!(content_hash(&[1u8; 32], b"hello"), content_hash(&[2u8; 32], b"hello"));
+    }
+}
@@ -0,0 +1,239 @@
+//! Per-path sidecar recording the POSIX metadata the backend itself has
+//! no concept of: permission bits, ownership, and timestamps. Every
+//! file `create`s gets one, and `setattr` (`chmod`/`chown`/`utimes`)
+//! updates it in place; without this, every file would report the same
+//! hardcoded mode and the mounting user's uid/gid forever.
+
+use std::time::SystemTime;
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::{AegisError, Result};
+use crate::storage::StorageBackend;
+
+/// Distinguishes what kind of entry a [`FileMetadata`] sidecar describes.
+/// Directories are identified structurally (a placeholder object or a
+/// listable prefix, depending on [`crate::fs::DirectoryMode`]) rather
+/// than through this field, so in practice it only ever toggles between
+/// `File` and `Symlink`. `#[serde(default)]` on the field it lives in
+/// keeps older sidecars, written before symlinks existed, readable as
+/// plain files.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum FileKind {
+    #[default]
+    File,
+    Directory,
+    Symlink,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FileMetadata {
+    pub mode: u32,
+    pub uid: u32,
+    pub gid: u32,
+    #[serde(default)]
+    pub kind: FileKind,
+    atime_secs: i64,
+    mtime_secs: i64,
+    ctime_secs: i64,
+    /// `None` (the default, and every file predating hard links) means
+    /// this path's content lives directly at its own storage key. A
+    /// hard-linked file's content instead lives at a shared,
+    /// content-addressed key this id identifies, tracked by
+    /// [`crate::links`]; see `AegisFS::link` for how and when a file
+    /// first acquires one.
+    #[serde(default)]
+    pub content_id: Option<String>,
+    /// `true` only when `content_id` was acquired through an explicit
+    /// `link()` call (a real Unix hard link, where every linked path is
+    /// supposed to observe the other's writes). `false` (the default,
+    /// and every sidecar predating this field) covers a `content_id`
+    /// acquired through dedup matching identical bytes at flush time —
+    /// those paths are logically independent files that merely started
+    /// out sharing storage, so a write to one must never become visible
+    /// through the other. See `AegisFS::flush_open_file`'s copy-on-write
+    /// branch and `AegisFS::file_nlink`, both of which key off this flag
+    /// rather than `content_id` alone.
+    #[serde(default)]
+    pub content_linked: bool,
+    /// The content object's ETag as of this file's last successful
+    /// flush, for detecting another writer having modified it directly
+    /// in the backend since. `None` until the first flush writes one
+    /// back, and for files predating this check. See
+    /// `blockstore::check_not_modified`; only meaningful while
+    /// `content_id` is `None` (content lives at its own key) — a
+    /// hard-linked/deduped file's shared content object is out of
+    /// scope for this check.
+    #[serde(default)]
+    pub content_etag: Option<String>,
+}
+
+impl FileMetadata {
+    /// A freshly `create`d file's metadata: the given mode and owner,
+    /// with every timestamp set to now.
+    pub fn new_file(mode: u32, uid: u32, gid: u32) -> Self {
+        let now = now_secs();
+        FileMetadata { mode, uid, gid, kind: FileKind::File, atime_secs: now, mtime_secs: now, ctime_secs: now, content_id: None, content_linked: false, content_etag: None }
+    }
+
+    /// A freshly `symlink`ed entry's metadata, otherwise identical to
+    /// [`Self::new_file`] but flagged so `getattr`/`lookup` report
+    /// [`FileKind::Symlink`] instead of a regular file.
+    pub fn new_symlink(mode: u32, uid: u32, gid: u32) -> Self {
+        FileMetadata { kind: FileKind::Symlink, ..Self::new_file(mode, uid, gid) }
+    }
+
+    pub fn atime(&self) -> SystemTime {
+        secs_to_system_time(self.atime_secs)
+    }
+
+    pub fn mtime(&self) -> SystemTime {
+        secs_to_system_time(self.mtime_secs)
+    }
+
+    pub fn ctime(&self) -> SystemTime {
+        secs_to_system_time(self.ctime_secs)
+    }
+
+    pub fn set_atime(&mut self, t: SystemTime) {
+        self.atime_secs = system_time_to_secs(t);
+    }
+
+    pub fn set_mtime(&mut self, t: SystemTime) {
+        self.mtime_secs = system_time_to_secs(t);
+    }
+
+    /// Stamps `ctime` to now, as every metadata-changing operation
+    /// (`chmod`, `chown`, a completed write) must.
+    pub fn touch_ctime(&mut self) {
+        self.ctime_secs = now_secs();
+    }
+}
+
+pub fn now_secs() -> i64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0)
+}
+
+pub fn system_time_to_secs(t: SystemTime) -> i64 {
+    match t.duration_since(SystemTime::UNIX_EPOCH) {
+        Ok(d) => d.as_secs() as i64,
+        Err(e) => -(e.duration().as_secs() as i64),
+    }
+}
+
+fn secs_to_system_time(secs: i64) -> SystemTime {
+    if secs >= 0 {
+        SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(secs as u64)
+    } else {
+        SystemTime::UNIX_EPOCH - std::time::Duration::from_secs((-secs) as u64)
+    }
+}
+
+fn meta_key(base_key: &str) -> String {
+    format!("{}.meta", base_key)
+}
+
+pub async fn load(storage: &dyn StorageBackend, base_key: &str) -> Result<FileMetadata> {
+    let raw = storage.get(&meta_key(base_key)).await?;
+    serde_json::from_slice(&raw)
+        .map_err(|e| AegisError::Encryption(format!("corrupt metadata for {}: {}", base_key, e)))
+}
+
+pub async fn save(storage: &dyn StorageBackend, base_key: &str, meta: &FileMetadata) -> Result<()> {
+    let raw = serde_json::to_vec(meta)
+        .map_err(|e| AegisError::Encryption(format!("encoding metadata for {}: {}", base_key, e)))?;
+    storage.put(&meta_key(base_key), raw).await
+}
+
+pub async fn delete(storage: &dyn StorageBackend, base_key: &str) {
+    let _ = storage.delete(&meta_key(base_key)).await;
+}
+
+/// Loads `base_key`'s metadata, falling back to `default_mode` (owned
+/// by root, stamped to now) for objects that predate this sidecar or
+/// were written by an external tool, rather than failing the caller.
+pub async fn load_or_default(storage: &dyn StorageBackend, base_key: &str, default_mode: u32) -> FileMetadata {
+    load(storage, base_key).await.unwrap_or_else(|_| FileMetadata::new_file(default_mode, 0, 0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryStorage;
+
+    #[tokio::test]
+    async fn save_then_load_round_trips() {
+        let storage = MemoryStorage::new();
+        let meta = FileMetadata::new_file(0o600, 1000, 1000);
+        save(&storage, "secret.txt", &meta).await.unwrap();
+
+        let loaded = load(&storage, "secret.txt").await.unwrap();
+        assert_eq!(loaded.mode, 0o600);
+        assert_eq!(loaded.uid, 1000);
+        assert_eq!(loaded.gid, 1000);
+    }
+
+    #[tokio::test]
+    async fn load_or_default_falls_back_for_unknown_paths() {
+        let storage = MemoryStorage::new();
+        let meta = load_or_default(&storage, "never-written.txt", 0o644).await;
+        assert_eq!(meta.mode, 0o644);
+        assert_eq!(meta.uid, 0);
+    }
+
+    #[test]
+    fn timestamps_round_trip_through_seconds() {
+        let t = SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1_700_000_000);
+        let mut meta = FileMetadata::new_file(0o644, 0, 0);
+        meta.set_mtime(t);
+        assert_eq!(meta.mtime(), t);
+    }
+
+    #[test]
+    fn new_symlink_is_flagged_distinctly_from_a_regular_file() {
+        let file = FileMetadata::new_file(0o644, 1000, 1000);
+        let symlink = FileMetadata::new_symlink(0o777, 1000, 1000);
+        assert_eq!(file.kind, FileKind::File);
+        assert_eq!(symlink.kind, FileKind::Symlink);
+    }
+
+    #[test]
+    fn sidecars_written_before_file_kind_existed_deserialize_as_a_plain_file() {
+        let without_kind = serde_json::json!({
+            "mode": 0o644,
+            "uid": 0,
+            "gid": 0,
+            "atime_secs": 0,
+            "mtime_secs": 0,
+            "ctime_secs": 0,
+        });
+        let meta: FileMetadata = serde_json::from_value(without_kind).unwrap();
+        assert_eq!(meta.kind, FileKind::File);
+    }
+
+    #[test]
+    fn sidecars_written_before_content_etag_tracking_existed_deserialize_with_none() {
+        let without_content_etag = serde_json::json!({
+            "mode": 0o644,
+            "uid": 0,
+            "gid": 0,
+            "atime_secs": 0,
+            "mtime_secs": 0,
+            "ctime_secs": 0,
+        });
+        let meta: FileMetadata = serde_json::from_value(without_content_etag).unwrap();
+        assert_eq!(meta.content_etag, None);
+    }
+
+    #[tokio::test]
+    async fn delete_removes_the_sidecar() {
+        let storage = MemoryStorage::new();
+        save(&storage, "f", &FileMetadata::new_file(0o644, 0, 0)).await.unwrap();
+        delete(&storage, "f").await;
+        assert!(load(&storage, "f").await.is_err());
+    }
+}
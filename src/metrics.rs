@@ -0,0 +1,167 @@
+//! In-process counters for FUSE operations, backend requests, and
+//! cache hits, rendered as Prometheus text exposition format.
+//!
+//! [`Metrics`] is a plain struct of [`AtomicU64`] counters shared (via
+//! `Arc`) between [`crate::fs::AegisFS`], [`crate::storage::S3Storage`],
+//! and [`crate::storage::CachingStorage`], so every layer that wants to
+//! record something just holds a clone of the same instance rather than
+//! reporting up through a callback. [`serve`] exposes the result over a
+//! bare-bones HTTP endpoint, so a mount can be scraped without pulling
+//! in a full web framework for one read-only route.
+
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// One counter per thing worth watching in production. Kept as a fixed
+/// struct of named fields, rather than a `HashMap<String, AtomicU64>`,
+/// so every increment is a direct atomic op with no locking or
+/// allocation on the hot path.
+#[derive(Default)]
+pub struct Metrics {
+    pub op_lookup: AtomicU64,
+    pub op_getattr: AtomicU64,
+    pub op_setattr: AtomicU64,
+    pub op_read: AtomicU64,
+    pub op_write: AtomicU64,
+    pub op_create: AtomicU64,
+    pub op_unlink: AtomicU64,
+    pub op_link: AtomicU64,
+    pub op_mkdir: AtomicU64,
+    pub op_rmdir: AtomicU64,
+    pub op_rename: AtomicU64,
+    pub op_readdir: AtomicU64,
+    pub op_open: AtomicU64,
+    pub op_release: AtomicU64,
+    pub op_opendir: AtomicU64,
+    pub op_releasedir: AtomicU64,
+    pub op_access: AtomicU64,
+    /// Requests issued to the backing object store (e.g. S3 API calls),
+    /// regardless of which FUSE op triggered them.
+    pub backend_requests: AtomicU64,
+    /// Backend requests that returned an error.
+    pub backend_errors: AtomicU64,
+    pub cache_hits: AtomicU64,
+    pub cache_misses: AtomicU64,
+    pub bytes_read: AtomicU64,
+    pub bytes_written: AtomicU64,
+}
+
+impl Metrics {
+    /// `(metric name, current value)` for every counter, in the order
+    /// they should be rendered. Centralized here so [`Self::render`]
+    /// and any future exporter (e.g. a JSON dump) stay in sync.
+    fn snapshot(&self) -> [(&'static str, u64); 23] {
+        [
+            ("op_lookup", self.op_lookup.load(Ordering::Relaxed)),
+            ("op_getattr", self.op_getattr.load(Ordering::Relaxed)),
+            ("op_setattr", self.op_setattr.load(Ordering::Relaxed)),
+            ("op_read", self.op_read.load(Ordering::Relaxed)),
+            ("op_write", self.op_write.load(Ordering::Relaxed)),
+            ("op_create", self.op_create.load(Ordering::Relaxed)),
+            ("op_unlink", self.op_unlink.load(Ordering::Relaxed)),
+            ("op_link", self.op_link.load(Ordering::Relaxed)),
+            ("op_mkdir", self.op_mkdir.load(Ordering::Relaxed)),
+            ("op_rmdir", self.op_rmdir.load(Ordering::Relaxed)),
+            ("op_rename", self.op_rename.load(Ordering::Relaxed)),
+            ("op_readdir", self.op_readdir.load(Ordering::Relaxed)),
+            ("op_open", self.op_open.load(Ordering::Relaxed)),
+            ("op_release", self.op_release.load(Ordering::Relaxed)),
+            ("op_opendir", self.op_opendir.load(Ordering::Relaxed)),
+            ("op_releasedir", self.op_releasedir.load(Ordering::Relaxed)),
+            ("op_access", self.op_access.load(Ordering::Relaxed)),
+            ("backend_requests", self.backend_requests.load(Ordering::Relaxed)),
+            ("backend_errors", self.backend_errors.load(Ordering::Relaxed)),
+            ("cache_hits", self.cache_hits.load(Ordering::Relaxed)),
+            ("cache_misses", self.cache_misses.load(Ordering::Relaxed)),
+            ("bytes_read", self.bytes_read.load(Ordering::Relaxed)),
+            ("bytes_written", self.bytes_written.load(Ordering::Relaxed)),
+        ]
+    }
+
+    /// Renders every counter under the `aegisfs_` namespace in
+    /// Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        for (name, value) in self.snapshot() {
+            out.push_str("# TYPE aegisfs_");
+            out.push_str(name);
+            out.push_str(" counter\n");
+            out.push_str("aegisfs_");
+            out.push_str(name);
+            out.push(' ');
+            out.push_str(&value.to_string());
+            out.push('\n');
+        }
+        out
+    }
+}
+
+/// Serves `GET /metrics` in Prometheus text format on `bind_address`,
+/// from a dedicated background thread so scraping never competes with
+/// (or blocks on) the FUSE session's own runtime. Deliberately minimal
+/// — no routing, no keep-alive, no dependency beyond `std` — since
+/// this is the only HTTP surface AegisFS exposes.
+pub fn serve(metrics: Arc<Metrics>, bind_address: &str) -> std::io::Result<()> {
+    let listener = TcpListener::bind(bind_address)?;
+    std::thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            handle_connection(stream, &metrics);
+        }
+    });
+    Ok(())
+}
+
+/// Reads (and discards) the request and always answers with the
+/// current metrics snapshot, regardless of path or method — there's
+/// only one thing to serve.
+fn handle_connection(mut stream: TcpStream, metrics: &Metrics) {
+    let mut buf = [0u8; 1024];
+    let _ = stream.read(&mut buf);
+
+    let body = metrics.render();
+    let response = format!(
+        "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        body.len(),
+        body
+    );
+    let _ = stream.write_all(response.as_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_includes_every_counter_at_its_current_value() {
+        let metrics = Metrics::default();
+        metrics.op_read.fetch_add(3, Ordering::Relaxed);
+        metrics.backend_errors.fetch_add(1, Ordering::Relaxed);
+
+        let rendered = metrics.render();
+
+        assert!(rendered.contains("aegisfs_op_read 3"));
+        assert!(rendered.contains("aegisfs_backend_errors 1"));
+        assert!(rendered.contains("aegisfs_cache_hits 0"));
+    }
+
+    #[test]
+    fn serve_answers_any_request_with_the_current_snapshot() {
+        let metrics = Arc::new(Metrics::default());
+        metrics.op_write.fetch_add(7, Ordering::Relaxed);
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        drop(listener);
+        serve(Arc::clone(&metrics), &addr.to_string()).unwrap();
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.write_all(b"GET /metrics HTTP/1.1\r\n\r\n").unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+
+        assert!(response.contains("200 OK"));
+        assert!(response.contains("aegisfs_op_write 7"));
+    }
+}
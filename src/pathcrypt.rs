@@ -0,0 +1,131 @@
+//! Deterministic, reversible encryption of individual path components,
+//! so object keys in the backend don't leak plaintext file and
+//! directory names. Opt-in via
+//! [`crate::config::EncryptionConfig::encrypt_filenames`], since it
+//! changes the on-disk layout: a bucket written with it off has a
+//! different shape than one written with it on, and the two can't be
+//! mixed for the same mount.
+//!
+//! Each path component is encrypted independently under AES-256-GCM-SIV
+//! with an all-zero nonce. Reusing a nonce is unsafe for most AEADs,
+//! but GCM-SIV is specifically nonce-misuse resistant: encrypting the
+//! same plaintext twice under the same key always yields the same
+//! ciphertext. That determinism is exactly what's needed here —
+//! `lookup`/`getattr` must re-derive the same object key for a given
+//! path without consulting a side index, and the hierarchy is
+//! preserved by encrypting each `/`-separated component on its own
+//! rather than the path as a whole, so prefix listing still works.
+
+use aes_gcm_siv::aead::{Aead, KeyInit};
+use aes_gcm_siv::{Aes256GcmSiv, Nonce};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+
+use crate::error::{AegisError, Result};
+
+const ZERO_NONCE: [u8; 12] = [0u8; 12];
+
+/// Encrypts and decrypts individual path components. See the module
+/// docs for why a fixed nonce is safe with GCM-SIV.
+#[derive(Clone)]
+pub struct PathCrypt {
+    cipher: Aes256GcmSiv,
+}
+
+impl PathCrypt {
+    pub fn new(key: &[u8; 32]) -> Self {
+        PathCrypt {
+            cipher: Aes256GcmSiv::new_from_slice(key).expect("AES-256-GCM-SIV key must be 32 bytes"),
+        }
+    }
+
+    /// Encrypts a single path component (must not contain `/`) into a
+    /// deterministic, key-safe token.
+    pub fn encrypt_component(&self, name: &str) -> String {
+        let ciphertext = self
+            .cipher
+            .encrypt(Nonce::from_slice(&ZERO_NONCE), name.as_bytes())
+            .expect("GCM-SIV encryption under a fixed-size key never fails");
+        URL_SAFE_NO_PAD.encode(ciphertext)
+    }
+
+    /// Inverse of [`Self::encrypt_component`].
+    pub fn decrypt_component(&self, token: &str) -> Result<String> {
+        let ciphertext = URL_SAFE_NO_PAD
+            .decode(token)
+            .map_err(|e| AegisError::Encryption(format!("invalid encrypted path component {}: {}", token, e)))?;
+        let plaintext = self
+            .cipher
+            .decrypt(Nonce::from_slice(&ZERO_NONCE), ciphertext.as_slice())
+            .map_err(|e| AegisError::Encryption(format!("decrypting path component {}: {}", token, e)))?;
+        String::from_utf8(plaintext)
+            .map_err(|e| AegisError::Encryption(format!("decrypted path component {} is not utf-8: {}", token, e)))
+    }
+
+    /// Encrypts every `/`-separated component of `path` independently,
+    /// keeping the `/` separators so the hierarchical prefix structure
+    /// (and therefore directory listing) is unchanged.
+    pub fn encrypt_path(&self, path: &str) -> String {
+        path.split('/').map(|component| self.encrypt_component(component)).collect::<Vec<_>>().join("/")
+    }
+
+    /// Inverse of [`Self::encrypt_path`].
+    pub fn decrypt_path(&self, path: &str) -> Result<String> {
+        path.split('/')
+            .map(|component| self.decrypt_component(component))
+            .collect::<Result<Vec<_>>>()
+            .map(|parts| parts.join("/"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn crypt() -> PathCrypt {
+        PathCrypt::new(&[11u8; 32])
+    }
+
+    #[test]
+    fn encrypting_a_component_hides_the_plaintext() {
+        let pc = crypt();
+        let token = pc.encrypt_component("secret-report.pdf");
+        assert!(!token.contains("secret-report"));
+        assert_eq!(pc.decrypt_component(&token).unwrap(), "secret-report.pdf");
+    }
+
+    #[test]
+    fn encryption_is_deterministic_for_repeat_lookups() {
+        let pc = crypt();
+        assert_eq!(pc.encrypt_component("readme.txt"), pc.encrypt_component("readme.txt"));
+    }
+
+    #[test]
+    fn different_keys_produce_different_ciphertext() {
+        let a = PathCrypt::new(&[1u8; 32]);
+        let b = PathCrypt::new(&[2u8; 32]);
+        assert_ne!(a.encrypt_component("same-name"), b.encrypt_component("same-name"));
+    }
+
+    #[test]
+    fn path_round_trips_component_by_component_preserving_hierarchy() {
+        let pc = crypt();
+        let encrypted = pc.encrypt_path("docs/2024/report.pdf");
+        assert_eq!(encrypted.matches('/').count(), 2);
+        assert_eq!(pc.decrypt_path(&encrypted).unwrap(), "docs/2024/report.pdf");
+    }
+
+    #[test]
+    fn empty_path_round_trips_to_itself() {
+        let pc = crypt();
+        assert_eq!(pc.decrypt_path(&pc.encrypt_path("")).unwrap(), "");
+    }
+
+    #[test]
+    fn decrypting_a_token_from_the_wrong_key_fails() {
+        let a = PathCrypt::new(&[3u8; 32]);
+        let b = PathCrypt::new(&[4u8; 32]);
+        let token = a.encrypt_component("name");
+        assert!(b.decrypt_component(&token).is_err());
+    }
+}
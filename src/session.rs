@@ -0,0 +1,38 @@
+use std::sync::Arc;
+
+use crate::config::{Config, ResolvedProfile};
+use crate::encryption::Encryptor;
+use crate::error::Result;
+use crate::storage::{S3Storage, StorageBackend};
+
+/// Bundles the storage backend and encryptor that the CLI subcommands
+/// `get`, `put`, `verify`, `ls`, `migrate` and `bench` need, built the
+/// same way every time so these entry points can't subtly diverge in
+/// how they interpret a [`Config`]. `Mount` doesn't go through this —
+/// it needs the full `local`/`gcs`/caching backend selection and FUSE
+/// wiring `builder::build` does instead — but resolves the master key
+/// identically, via the same [`crate::kms::resolve_master_key`] call.
+pub struct Session {
+    pub storage: Arc<dyn StorageBackend>,
+    pub encryptor: Arc<Encryptor>,
+    /// `resolved.s3.prefix`, defaulted the same way every caller
+    /// already did by hand.
+    pub prefix: String,
+}
+
+impl Session {
+    /// Resolves the master key (via KMS if `config.kms` is set, else
+    /// passphrase derivation) and connects to `resolved.s3`. Always
+    /// talks to S3 directly, with no caching layer: these subcommands
+    /// are meant to exercise the backend as-is, not the mount's cache.
+    pub async fn from_config(config: &Config, resolved: &ResolvedProfile) -> Result<Self> {
+        let key = crate::kms::resolve_master_key(&resolved.encryption, config.kms.as_ref()).await?;
+        let encryptor = Encryptor::with_algorithm(&key, resolved.encryption.algorithm);
+        let storage = S3Storage::new(&resolved.s3).await?;
+        Ok(Session {
+            storage: Arc::new(storage),
+            encryptor: Arc::new(encryptor),
+            prefix: resolved.s3.prefix.clone().unwrap_or_default(),
+        })
+    }
+}
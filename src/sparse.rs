@@ -0,0 +1,107 @@
+//! Sparse-aware plaintext framing.
+//!
+//! [`crate::blockstore`] passes every block's plaintext through
+//! [`encode`] before compressing and encrypting it, and through
+//! [`decode`] after decrypting it. A zero-filled stretch inside a block
+//! (e.g. from `write` zero-extending past the old EOF, common for VM
+//! disks and databases) would otherwise store every zero byte; this
+//! module run-length-encodes zero runs of at least `SPARSE_BLOCK_SIZE`
+//! bytes, so such a stretch costs a few bytes instead of its logical
+//! size. A block that was never written has no object at all and reads
+//! back as zeros.
+
+/// Zero runs shorter than this are left inline as literal bytes; only
+/// runs at least this long are worth the tag/length overhead.
+pub const SPARSE_BLOCK_SIZE: usize = 4096;
+
+const TAG_LITERAL: u8 = 0;
+const TAG_ZERO_RUN: u8 = 1;
+
+/// Encodes `plaintext`, collapsing zero runs of at least
+/// [`SPARSE_BLOCK_SIZE`] bytes into a `(tag, length)` pair.
+pub fn encode(plaintext: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut literal = Vec::new();
+    let mut i = 0;
+    while i < plaintext.len() {
+        if plaintext[i] == 0 {
+            let run = plaintext[i..].iter().take_while(|&&b| b == 0).count();
+            if run >= SPARSE_BLOCK_SIZE {
+                flush_literal(&mut out, &mut literal);
+                out.push(TAG_ZERO_RUN);
+                out.extend_from_slice(&(run as u64).to_le_bytes());
+                i += run;
+                continue;
+            }
+        }
+        literal.push(plaintext[i]);
+        i += 1;
+    }
+    flush_literal(&mut out, &mut literal);
+    out
+}
+
+fn flush_literal(out: &mut Vec<u8>, literal: &mut Vec<u8>) {
+    if literal.is_empty() {
+        return;
+    }
+    out.push(TAG_LITERAL);
+    out.extend_from_slice(&(literal.len() as u64).to_le_bytes());
+    out.extend_from_slice(literal);
+    literal.clear();
+}
+
+/// Reverses [`encode`], restoring the original bytes including any
+/// collapsed zero runs.
+pub fn decode(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut i = 0;
+    while i < data.len() {
+        let tag = data[i];
+        i += 1;
+        let len = u64::from_le_bytes(data[i..i + 8].try_into().unwrap()) as usize;
+        i += 8;
+        match tag {
+            TAG_ZERO_RUN => out.resize(out.len() + len, 0),
+            TAG_LITERAL => {
+                out.extend_from_slice(&data[i..i + len]);
+                i += len;
+            }
+            _ => unreachable!("unknown sparse frame tag {}", tag),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_plaintext_with_no_zero_runs() {
+        let data = b"just some ordinary file content".to_vec();
+        assert_eq!(decode(&encode(&data)), data);
+    }
+
+    #[test]
+    fn large_zero_gap_encodes_to_far_fewer_bytes_than_the_logical_size() {
+        let mut data = b"head".to_vec();
+        data.resize(data.len() + SPARSE_BLOCK_SIZE * 4, 0);
+        data.extend_from_slice(b"tail");
+
+        let encoded = encode(&data);
+        assert!(encoded.len() < data.len() / 10);
+        assert_eq!(decode(&encoded), data);
+    }
+
+    #[test]
+    fn zero_runs_shorter_than_the_threshold_stay_literal() {
+        let data = vec![0u8; SPARSE_BLOCK_SIZE - 1];
+        assert_eq!(decode(&encode(&data)), data);
+    }
+
+    #[test]
+    fn empty_input_round_trips() {
+        assert_eq!(decode(&encode(&[])), Vec::<u8>::new());
+    }
+}
@@ -0,0 +1,139 @@
+use std::path::PathBuf;
+
+use async_trait::async_trait;
+
+use crate::error::{AegisError, Result};
+
+use super::{ObjectMeta, StorageBackend};
+
+/// Stores encrypted blobs as files under a root directory, using the
+/// same prefix-based listing and key semantics as `S3Storage`. Makes
+/// the crate usable offline, and for tests that want real filesystem
+/// I/O without a live S3 endpoint.
+pub struct LocalStorage {
+    root: PathBuf,
+}
+
+impl LocalStorage {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        LocalStorage { root: root.into() }
+    }
+
+    fn path_for(&self, key: &str) -> PathBuf {
+        self.root.join(key)
+    }
+}
+
+#[async_trait]
+impl StorageBackend for LocalStorage {
+    async fn get(&self, key: &str) -> Result<Vec<u8>> {
+        tokio::fs::read(self.path_for(key))
+            .await
+            .map_err(|_| AegisError::NotFound(key.to_string()))
+    }
+
+    async fn put(&self, key: &str, data: Vec<u8>) -> Result<()> {
+        let path = self.path_for(key);
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        tokio::fs::write(path, data).await?;
+        Ok(())
+    }
+
+    async fn delete(&self, key: &str) -> Result<()> {
+        match tokio::fs::remove_file(self.path_for(key)).await {
+            // S3's DeleteObject is idempotent against a missing key; match that.
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(AegisError::Storage(format!("delete {}: {}", key, e))),
+        }
+    }
+
+    async fn list(&self, prefix: &str) -> Result<Vec<ObjectMeta>> {
+        let mut out = Vec::new();
+        let mut pending = vec![self.root.clone()];
+
+        while let Some(dir) = pending.pop() {
+            let mut entries = match tokio::fs::read_dir(&dir).await {
+                Ok(entries) => entries,
+                Err(_) => continue,
+            };
+
+            while let Some(entry) = entries
+                .next_entry()
+                .await
+                .map_err(|e| AegisError::Storage(format!("listing {}: {}", prefix, e)))?
+            {
+                let file_type = entry
+                    .file_type()
+                    .await
+                    .map_err(|e| AegisError::Storage(format!("listing {}: {}", prefix, e)))?;
+                if file_type.is_dir() {
+                    pending.push(entry.path());
+                    continue;
+                }
+
+                let key = entry
+                    .path()
+                    .strip_prefix(&self.root)
+                    .unwrap()
+                    .to_string_lossy()
+                    .replace(std::path::MAIN_SEPARATOR, "/");
+                if key.starts_with(prefix) {
+                    let size = entry
+                        .metadata()
+                        .await
+                        .map_err(|e| AegisError::Storage(format!("listing {}: {}", prefix, e)))?
+                        .len();
+                    out.push(ObjectMeta { key, size, etag: None });
+                }
+            }
+        }
+
+        Ok(out)
+    }
+
+    async fn head(&self, key: &str) -> Result<ObjectMeta> {
+        let metadata = tokio::fs::metadata(self.path_for(key))
+            .await
+            .map_err(|_| AegisError::NotFound(key.to_string()))?;
+        Ok(ObjectMeta { key: key.to_string(), size: metadata.len(), etag: None })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn round_trips_and_lists_by_prefix() {
+        let dir = tempfile::tempdir().unwrap();
+        let backend = LocalStorage::new(dir.path());
+
+        backend.put("docs/readme.txt", b"hello".to_vec()).await.unwrap();
+        backend.put("docs/.dir", vec![]).await.unwrap();
+        backend.put("photos/trip.jpg", b"jpeg".to_vec()).await.unwrap();
+
+        assert_eq!(backend.get("docs/readme.txt").await.unwrap(), b"hello");
+
+        let mut docs: Vec<String> = backend.list("docs/").await.unwrap().into_iter().map(|o| o.key).collect();
+        docs.sort();
+        assert_eq!(docs, vec!["docs/.dir".to_string(), "docs/readme.txt".to_string()]);
+
+        backend.delete("docs/readme.txt").await.unwrap();
+        assert!(backend.get("docs/readme.txt").await.is_err());
+        // Deleting an already-missing key is not an error, matching S3.
+        assert!(backend.delete("docs/readme.txt").await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn head_reports_size_and_missing_keys_as_not_found() {
+        let dir = tempfile::tempdir().unwrap();
+        let backend = LocalStorage::new(dir.path());
+        backend.put("a.txt", b"abcde".to_vec()).await.unwrap();
+
+        assert_eq!(backend.head("a.txt").await.unwrap().size, 5);
+        assert!(backend.head("missing.txt").await.is_err());
+    }
+}
@@ -0,0 +1,147 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use async_trait::async_trait;
+use sha2::{Digest, Sha256};
+
+use crate::error::{AegisError, Result};
+
+use super::{ObjectMeta, StorageBackend};
+
+/// A real backend's ETag reflects the object's current bytes (S3's is an
+/// MD5 of the body, for non-multipart uploads); a `MemoryStorage` that
+/// always reported `None` would silently opt every etag-based check
+/// (`AegisFS`'s `ConsistencyMode::Strong` cache, `blockstore::check_not_modified`)
+/// out of its own test coverage. A plain content hash is enough to make
+/// those checks exercisable in tests — nothing here needs to be
+/// unguessable the way [`crate::links::content_hash`]'s keyed hash does.
+fn etag_of(data: &[u8]) -> String {
+    let digest = Sha256::digest(data);
+    digest.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// An in-memory `StorageBackend`. Useful for tests and offline tools
+/// (like `fsck`) that shouldn't need a real S3 endpoint to exercise
+/// backend-facing logic.
+#[derive(Default)]
+pub struct MemoryStorage {
+    objects: Mutex<HashMap<String, Vec<u8>>>,
+}
+
+impl MemoryStorage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl StorageBackend for MemoryStorage {
+    async fn get(&self, key: &str) -> Result<Vec<u8>> {
+        self.objects
+            .lock()
+            .unwrap()
+            .get(key)
+            .cloned()
+            .ok_or_else(|| AegisError::NotFound(key.to_string()))
+    }
+
+    async fn put(&self, key: &str, data: Vec<u8>) -> Result<()> {
+        self.objects.lock().unwrap().insert(key.to_string(), data);
+        Ok(())
+    }
+
+    async fn delete(&self, key: &str) -> Result<()> {
+        self.objects.lock().unwrap().remove(key);
+        Ok(())
+    }
+
+    async fn list(&self, prefix: &str) -> Result<Vec<ObjectMeta>> {
+        Ok(self
+            .objects
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(key, _)| key.starts_with(prefix))
+            .map(|(key, data)| ObjectMeta { key: key.clone(), size: data.len() as u64, etag: Some(etag_of(data)) })
+            .collect())
+    }
+
+    async fn head(&self, key: &str) -> Result<ObjectMeta> {
+        self.objects
+            .lock()
+            .unwrap()
+            .get(key)
+            .map(|data| ObjectMeta { key: key.to_string(), size: data.len() as u64, etag: Some(etag_of(data)) })
+            .ok_or_else(|| AegisError::NotFound(key.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn put_then_get_round_trips_the_exact_bytes() {
+        let storage = MemoryStorage::new();
+        storage.put("a.txt", b"hello".to_vec()).await.unwrap();
+
+        assert_eq!(storage.get("a.txt").await.unwrap(), b"hello");
+    }
+
+    #[tokio::test]
+    async fn get_and_head_report_not_found_for_a_missing_key() {
+        let storage = MemoryStorage::new();
+
+        assert!(matches!(storage.get("missing").await, Err(AegisError::NotFound(_))));
+        assert!(matches!(storage.head("missing").await, Err(AegisError::NotFound(_))));
+    }
+
+    #[tokio::test]
+    async fn delete_removes_the_key_and_is_a_no_op_if_its_already_gone() {
+        let storage = MemoryStorage::new();
+        storage.put("a.txt", b"hello".to_vec()).await.unwrap();
+
+        storage.delete("a.txt").await.unwrap();
+        assert!(storage.get("a.txt").await.is_err());
+
+        // A second delete of the same, now-absent key isn't an error —
+        // matching the idempotent-delete semantics a real backend (S3's
+        // DeleteObject, say) already has.
+        storage.delete("a.txt").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn list_returns_only_keys_under_the_given_prefix() {
+        let storage = MemoryStorage::new();
+        storage.put("docs/a.txt", b"a".to_vec()).await.unwrap();
+        storage.put("docs/b.txt", b"bb".to_vec()).await.unwrap();
+        storage.put("other/c.txt", b"ccc".to_vec()).await.unwrap();
+
+        let mut keys: Vec<String> = storage.list("docs/").await.unwrap().into_iter().map(|o| o.key).collect();
+        keys.sort();
+
+        assert_eq!(keys, vec!["docs/a.txt".to_string(), "docs/b.txt".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn head_reports_the_current_size_without_fetching_the_bytes() {
+        let storage = MemoryStorage::new();
+        storage.put("a.txt", b"hello world".to_vec()).await.unwrap();
+
+        let meta = storage.head("a.txt").await.unwrap();
+        assert_eq!(meta.size, 11);
+    }
+
+    #[tokio::test]
+    async fn head_etag_changes_only_when_the_content_does() {
+        let storage = MemoryStorage::new();
+        storage.put("a.txt", b"v1".to_vec()).await.unwrap();
+        let first = storage.head("a.txt").await.unwrap().etag.unwrap();
+
+        storage.put("a.txt", b"v1".to_vec()).await.unwrap();
+        assert_eq!(storage.head("a.txt").await.unwrap().etag.unwrap(), first);
+
+        storage.put("a.txt", b"v2".to_vec()).await.unwrap();
+        assert_ne!(storage.head("a.txt").await.unwrap().etag.unwrap(), first);
+    }
+}
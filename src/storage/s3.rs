@@ -0,0 +1,849 @@
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use aws_sdk_s3::Client;
+
+use crate::config::S3Config;
+use crate::error::{AegisError, Result};
+use crate::metrics::Metrics;
+use crate::throttle::TokenBucket;
+
+use super::{DelimitedListing, ObjectMeta, StorageBackend};
+
+/// S3 requires every multipart part but the last to be at least 5 MiB;
+/// below this we can't profitably `UploadPartCopy` the existing object.
+const MIN_MULTIPART_PART_SIZE: u64 = 5 * 1024 * 1024;
+
+/// Caps how many parts of a multipart `put` are in flight at once, so a
+/// single large file can't monopolize every connection in the SDK's
+/// pool.
+const MAX_CONCURRENT_PART_UPLOADS: usize = 4;
+
+/// S3's `DeleteObjects` accepts at most this many keys per request.
+const MAX_KEYS_PER_DELETE_BATCH: usize = 1000;
+
+/// `Days` passed on a `RestoreObject` request issued for an archived
+/// object — how long the restored copy stays in the standard tier
+/// before S3 re-archives it. A week is enough time for a read that
+/// triggered the restore to notice and retry.
+const RESTORE_DAYS: i32 = 7;
+
+/// S3-compatible object storage backend.
+pub struct S3Storage {
+    client: Client,
+    /// Client pointed at [`S3Config::read_endpoint`] instead of the
+    /// primary endpoint, used by `get`/`list`/`list_with_delimiter`/
+    /// `head` in preference to `client`, falling back to it on failure.
+    /// `None` when no read endpoint is configured, in which case those
+    /// operations just use `client` directly.
+    read_client: Option<Client>,
+    bucket: String,
+    upload_limiter: Arc<TokenBucket>,
+    download_limiter: Arc<TokenBucket>,
+    /// Object size, in bytes, at or above which `put` uses a multipart
+    /// upload. See [`S3Config::multipart_threshold_bytes`].
+    multipart_threshold: u64,
+    /// Size of each part in a multipart `put`. See
+    /// [`S3Config::multipart_part_size_bytes`].
+    multipart_part_size: u64,
+    /// Bounds how many requests are in flight against the backend at
+    /// once. See [`S3Config::max_concurrent_requests`].
+    request_limiter: Option<Arc<tokio::sync::Semaphore>>,
+    /// Set via [`Self::with_metrics`]; `None` means requests simply
+    /// aren't counted, so embedders that don't care about metrics pay
+    /// nothing for them.
+    metrics: Option<Arc<Metrics>>,
+    /// `ServerSideEncryption`/`SSEKMSKeyId` to set on every upload. See
+    /// [`S3Config::server_side_encryption`].
+    server_side_encryption: Option<aws_sdk_s3::types::ServerSideEncryption>,
+    sse_kms_key_id: Option<String>,
+    /// See [`S3Config::restore_archived_objects`].
+    restore_archived_objects: bool,
+}
+
+/// A `rustls::client::danger::ServerCertVerifier` that accepts any
+/// certificate. Only ever installed when the operator has explicitly
+/// opted in via `danger_accept_invalid_certs`.
+#[derive(Debug)]
+struct NoCertVerification;
+
+impl rustls::client::danger::ServerCertVerifier for NoCertVerification {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &rustls::pki_types::CertificateDer<'_>,
+        _intermediates: &[rustls::pki_types::CertificateDer<'_>],
+        _server_name: &rustls::pki_types::ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: rustls::pki_types::UnixTime,
+    ) -> std::result::Result<rustls::client::danger::ServerCertVerified, rustls::Error> {
+        Ok(rustls::client::danger::ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        _message: &[u8],
+        _cert: &rustls::pki_types::CertificateDer<'_>,
+        _dss: &rustls::DigitallySignedStruct,
+    ) -> std::result::Result<rustls::client::danger::HandshakeSignatureValid, rustls::Error> {
+        Ok(rustls::client::danger::HandshakeSignatureValid::assertion())
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        _message: &[u8],
+        _cert: &rustls::pki_types::CertificateDer<'_>,
+        _dss: &rustls::DigitallySignedStruct,
+    ) -> std::result::Result<rustls::client::danger::HandshakeSignatureValid, rustls::Error> {
+        Ok(rustls::client::danger::HandshakeSignatureValid::assertion())
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<rustls::SignatureScheme> {
+        rustls::crypto::ring::default_provider()
+            .signature_verification_algorithms
+            .supported_schemes()
+    }
+}
+
+/// Builds the rustls `ClientConfig` used for talking to the S3
+/// endpoint, honoring `ca_cert_path` and `danger_accept_invalid_certs`.
+/// Defaults to full verification against the system trust store.
+fn build_tls_config(config: &S3Config) -> Result<rustls::ClientConfig> {
+    if config.danger_accept_invalid_certs {
+        tracing::warn!(
+            "TLS certificate verification is DISABLED for the S3 endpoint; \
+             this must never be used against a production bucket"
+        );
+        return Ok(rustls::ClientConfig::builder()
+            .dangerous()
+            .with_custom_certificate_verifier(std::sync::Arc::new(NoCertVerification))
+            .with_no_client_auth());
+    }
+
+    let mut roots = rustls::RootCertStore::empty();
+    roots.extend(rustls_native_certs::load_native_certs().certs);
+
+    if let Some(ca_cert_path) = &config.ca_cert_path {
+        let pem = std::fs::read(ca_cert_path)
+            .map_err(|e| AegisError::Config(format!("reading ca_cert_path {}: {}", ca_cert_path, e)))?;
+        for cert in rustls_pemfile::certs(&mut pem.as_slice()) {
+            let cert = cert
+                .map_err(|e| AegisError::Config(format!("parsing ca_cert_path {}: {}", ca_cert_path, e)))?;
+            roots
+                .add(cert)
+                .map_err(|e| AegisError::Config(format!("adding CA from {}: {}", ca_cert_path, e)))?;
+        }
+    }
+
+    Ok(rustls::ClientConfig::builder()
+        .with_root_certificates(roots)
+        .with_no_client_auth())
+}
+
+/// Builds the S3 client used for an `S3Storage`, honoring every
+/// transport-level knob in `config` and, if `endpoint_override` is set,
+/// pointing the client at it instead of the default AWS endpoint for
+/// `shared_config`'s region. Shared between the primary client and the
+/// [`S3Config::read_endpoint`] client `S3Storage::new` builds, so the
+/// two only ever differ in which endpoint they talk to.
+async fn build_client(shared_config: &aws_config::SdkConfig, config: &S3Config, endpoint_override: Option<&str>) -> Result<Client> {
+    if config.ca_cert_path.is_some()
+        || config.danger_accept_invalid_certs
+        || config.max_idle_connections_per_host.is_some()
+        || config.role_arn.is_some()
+        || endpoint_override.is_some()
+    {
+        let mut aws_config_builder = aws_sdk_s3::config::Builder::from(shared_config);
+
+        if let Some(role_arn) = &config.role_arn {
+            // Wrapped in `aws_sdk_s3::config::Builder`'s default
+            // `IdentityCache::lazy()`, so the assumed session is
+            // refreshed automatically as it nears expiry rather than
+            // held for the life of the mount.
+            let assumed_role = aws_config::sts::AssumeRoleProvider::builder(role_arn.clone())
+                .configure(shared_config)
+                .session_name("aegisfs")
+                .build()
+                .await;
+            aws_config_builder = aws_config_builder.credentials_provider(assumed_role);
+        }
+
+        if config.ca_cert_path.is_some() || config.danger_accept_invalid_certs || config.max_idle_connections_per_host.is_some() {
+            let tls_config = build_tls_config(config)?;
+            let http_client = hyper_rustls::HttpsConnectorBuilder::new()
+                .with_tls_config(tls_config)
+                .https_or_http()
+                .enable_http1()
+                .enable_http2()
+                .build();
+            let mut client_builder = aws_smithy_runtime::client::http::hyper_014::HyperClientBuilder::new();
+            if let Some(max_idle) = config.max_idle_connections_per_host {
+                let mut hyper_builder = hyper_014::Client::builder();
+                hyper_builder.pool_max_idle_per_host(max_idle);
+                client_builder = client_builder.hyper_builder(hyper_builder);
+            }
+            aws_config_builder = aws_config_builder.http_client(client_builder.build(http_client));
+        }
+
+        if let Some(endpoint) = endpoint_override {
+            aws_config_builder = aws_config_builder.endpoint_url(endpoint);
+        }
+
+        Ok(Client::from_conf(aws_config_builder.build()))
+    } else {
+        Ok(Client::new(shared_config))
+    }
+}
+
+impl S3Storage {
+    pub async fn new(config: &S3Config) -> Result<Self> {
+        let mut loader = aws_config::from_env();
+        if let Some(profile) = &config.profile {
+            loader = loader.profile_name(profile);
+        }
+        // Applied to every request (connect and operation-attempt,
+        // i.e. a single HTTP request/response including reading the
+        // body) so a stalled connection or an unresponsive endpoint
+        // fails an op promptly instead of hanging the FUSE callback
+        // that's waiting on it forever.
+        loader = loader.timeout_config(
+            aws_sdk_s3::config::timeout::TimeoutConfig::builder()
+                .connect_timeout(std::time::Duration::from_secs(config.connect_timeout_secs))
+                .operation_attempt_timeout(std::time::Duration::from_secs(config.read_timeout_secs))
+                .build(),
+        );
+        let shared_config = loader.load().await;
+
+        let client = build_client(&shared_config, config, None).await?;
+        let read_client = match &config.read_endpoint {
+            Some(endpoint) => Some(build_client(&shared_config, config, Some(endpoint)).await?),
+            None => None,
+        };
+
+        Ok(S3Storage {
+            client,
+            read_client,
+            bucket: config.bucket.clone(),
+            upload_limiter: Arc::new(TokenBucket::new(config.max_upload_bytes_per_sec.unwrap_or(0))),
+            download_limiter: Arc::new(TokenBucket::new(config.max_download_bytes_per_sec.unwrap_or(0))),
+            multipart_threshold: config.multipart_threshold_bytes,
+            multipart_part_size: config.multipart_part_size_bytes,
+            request_limiter: config.max_concurrent_requests.map(|n| Arc::new(tokio::sync::Semaphore::new(n))),
+            metrics: None,
+            server_side_encryption: config
+                .server_side_encryption
+                .as_deref()
+                .map(aws_sdk_s3::types::ServerSideEncryption::from),
+            sse_kms_key_id: config.sse_kms_key_id.clone(),
+            restore_archived_objects: config.restore_archived_objects,
+        })
+    }
+
+    /// The client reads should prefer: the `read_client` field if a
+    /// read endpoint is configured, `client` otherwise.
+    fn preferred_read_client(&self) -> &Client {
+        self.read_client.as_ref().unwrap_or(&self.client)
+    }
+
+    /// Acquires a permit before issuing a request, if
+    /// [`S3Config::max_concurrent_requests`] bounds how many can be in
+    /// flight at once; a no-op otherwise. Held for the duration of the
+    /// call, including a multipart `put`'s whole set of part uploads
+    /// (which are bounded separately, by `MAX_CONCURRENT_PART_UPLOADS`),
+    /// so one slow large upload can't itself exceed the cap.
+    async fn acquire_request_permit(&self) -> Option<tokio::sync::OwnedSemaphorePermit> {
+        match &self.request_limiter {
+            Some(limiter) => Some(Arc::clone(limiter).acquire_owned().await.expect("semaphore is never closed")),
+            None => None,
+        }
+    }
+
+    /// Records every request this backend issues (and every one that
+    /// fails) into `metrics`, shared with whatever else (typically
+    /// [`crate::fs::AegisFS`]) reports into the same instance. See
+    /// [`crate::metrics::serve`].
+    pub fn with_metrics(mut self, metrics: Arc<Metrics>) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Records one backend request, and an error too if `result` is
+    /// one. Called once per `get`, `put` (a multipart upload counts as
+    /// one request), `delete`, `delete_batch`, `list`,
+    /// `list_with_delimiter` and `head` in the [`StorageBackend`] impl
+    /// below; `head_if_none_match`, `copy` and the multipart path of
+    /// `append` are not counted. A no-op when [`Self::with_metrics`]
+    /// was never called.
+    fn record<T>(&self, result: &Result<T>) {
+        if let Some(metrics) = &self.metrics {
+            metrics.backend_requests.fetch_add(1, Ordering::Relaxed);
+            if result.is_err() {
+                metrics.backend_errors.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Uploads `data` as a multipart object: splits it into
+    /// `multipart_part_size`-sized parts, uploads up to
+    /// `MAX_CONCURRENT_PART_UPLOADS` of them concurrently, and
+    /// completes the upload once every part has succeeded. Aborts the
+    /// upload on any part failure so S3 doesn't keep billing for
+    /// orphaned parts.
+    async fn put_multipart(&self, key: &str, data: Vec<u8>) -> Result<()> {
+        let create = self
+            .client
+            .create_multipart_upload()
+            .bucket(&self.bucket)
+            .key(key)
+            .set_server_side_encryption(self.server_side_encryption.clone())
+            .set_ssekms_key_id(self.sse_kms_key_id.clone())
+            .send()
+            .await
+            .map_err(|e| AegisError::Storage(format!("create_multipart_upload {}: {}", key, e)))?;
+        let upload_id = create
+            .upload_id()
+            .ok_or_else(|| AegisError::Storage("missing upload id".into()))?
+            .to_string();
+
+        let part_size = self.multipart_part_size.max(1) as usize;
+        let semaphore = Arc::new(tokio::sync::Semaphore::new(MAX_CONCURRENT_PART_UPLOADS));
+        let mut tasks = tokio::task::JoinSet::new();
+
+        for (index, chunk) in data.chunks(part_size).enumerate() {
+            let part_number = (index + 1) as i32;
+            let client = self.client.clone();
+            let bucket = self.bucket.clone();
+            let key = key.to_string();
+            let upload_id = upload_id.clone();
+            let limiter = Arc::clone(&self.upload_limiter);
+            let semaphore = Arc::clone(&semaphore);
+            let chunk = chunk.to_vec();
+
+            tasks.spawn(async move {
+                let _permit = semaphore.acquire_owned().await.expect("semaphore is never closed");
+                limiter.acquire(chunk.len() as u64).await;
+                client
+                    .upload_part()
+                    .bucket(&bucket)
+                    .key(&key)
+                    .upload_id(&upload_id)
+                    .part_number(part_number)
+                    .body(chunk.into())
+                    .send()
+                    .await
+                    .map(|out| (part_number, out.e_tag().map(String::from)))
+                    .map_err(|e| AegisError::Storage(format!("upload_part {} (part {}): {}", key, part_number, e)))
+            });
+        }
+
+        let mut parts = Vec::new();
+        let mut first_error = None;
+        while let Some(result) = tasks.join_next().await {
+            match result {
+                Ok(Ok(part)) => parts.push(part),
+                Ok(Err(e)) => {
+                    first_error.get_or_insert(e);
+                }
+                Err(e) => {
+                    first_error.get_or_insert(AegisError::Storage(format!("upload_part task for {}: {}", key, e)));
+                }
+            }
+        }
+
+        if let Some(err) = first_error {
+            let _ = self
+                .client
+                .abort_multipart_upload()
+                .bucket(&self.bucket)
+                .key(key)
+                .upload_id(&upload_id)
+                .send()
+                .await;
+            return Err(err);
+        }
+
+        parts.sort_by_key(|(part_number, _)| *part_number);
+        let completed_parts = parts
+            .into_iter()
+            .map(|(part_number, e_tag)| {
+                aws_sdk_s3::types::CompletedPart::builder()
+                    .part_number(part_number)
+                    .set_e_tag(e_tag)
+                    .build()
+            })
+            .collect();
+
+        self.client
+            .complete_multipart_upload()
+            .bucket(&self.bucket)
+            .key(key)
+            .upload_id(&upload_id)
+            .multipart_upload(
+                aws_sdk_s3::types::CompletedMultipartUpload::builder()
+                    .set_parts(Some(completed_parts))
+                    .build(),
+            )
+            .send()
+            .await
+            .map_err(|e| AegisError::Storage(format!("complete_multipart_upload {}: {}", key, e)))?;
+
+        Ok(())
+    }
+}
+
+impl S3Storage {
+    async fn get_impl(&self, key: &str) -> Result<Vec<u8>> {
+        if self.read_client.is_some() {
+            if let Ok(data) = self.get_via(self.preferred_read_client(), key).await {
+                return Ok(data);
+            }
+        }
+        self.get_via(&self.client, key).await
+    }
+
+    async fn get_via(&self, client: &Client, key: &str) -> Result<Vec<u8>> {
+        let output = match client.get_object().bucket(&self.bucket).key(key).send().await {
+            Ok(output) => output,
+            Err(e) => {
+                let message = e.to_string();
+                if message.to_lowercase().contains("invalidobjectstate") {
+                    return Err(self.handle_archived_object(client, key, &message).await);
+                }
+                return Err(AegisError::Storage(format!("get {}: {}", key, message)));
+            }
+        };
+
+        let data = output
+            .body
+            .collect()
+            .await
+            .map_err(|e| AegisError::Storage(format!("reading body for {}: {}", key, e)))?
+            .into_bytes()
+            .to_vec();
+        self.download_limiter.acquire(data.len() as u64).await;
+        Ok(data)
+    }
+
+    /// Called when `get_via`'s request to `key` failed with S3's
+    /// `InvalidObjectState` error: the object has been transitioned to
+    /// Glacier or Deep Archive and can't be read directly. The
+    /// returned error's message keeps the `invalidobjectstate`
+    /// substring `AegisError::to_errno`'s `classify_storage_errno`
+    /// matches on, so it maps to `EAGAIN` rather than a cryptic `EIO` —
+    /// retryable, once the object comes back. Without
+    /// [`S3Config::restore_archived_objects`] that's all this does;
+    /// with it set, also issues a `RestoreObject` request so the
+    /// restore is already under way by the time whoever sees the error
+    /// goes looking — a restore takes hours, far longer than this call
+    /// (or any single FUSE op) can wait out.
+    async fn handle_archived_object(&self, client: &Client, key: &str, message: &str) -> AegisError {
+        if !self.restore_archived_objects {
+            return AegisError::Storage(format!(
+                "get {}: object is archived and must be restored before it can be read: {}",
+                key, message
+            ));
+        }
+
+        let glacier_job_parameters = match aws_sdk_s3::types::GlacierJobParameters::builder()
+            .tier(aws_sdk_s3::types::Tier::Standard)
+            .build()
+        {
+            Ok(params) => params,
+            Err(e) => return AegisError::Storage(format!("get {}: building restore request: {}", key, e)),
+        };
+        let restore_request = aws_sdk_s3::types::RestoreRequest::builder()
+            .days(RESTORE_DAYS)
+            .glacier_job_parameters(glacier_job_parameters)
+            .build();
+
+        match client
+            .restore_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .restore_request(restore_request)
+            .send()
+            .await
+        {
+            Ok(_) => AegisError::Storage(format!(
+                "get {}: object is archived; restore requested and now in progress, retry once it completes",
+                key
+            )),
+            Err(e) => AegisError::Storage(format!("get {}: object is archived; requesting restore failed: {}", key, e)),
+        }
+    }
+
+    async fn put_impl(&self, key: &str, data: Vec<u8>) -> Result<()> {
+        if data.len() as u64 >= self.multipart_threshold {
+            return self.put_multipart(key, data).await;
+        }
+        self.upload_limiter.acquire(data.len() as u64).await;
+        self.client
+            .put_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .body(data.into())
+            .set_server_side_encryption(self.server_side_encryption.clone())
+            .set_ssekms_key_id(self.sse_kms_key_id.clone())
+            .send()
+            .await
+            .map_err(|e| AegisError::Storage(format!("put {}: {}", key, e)))?;
+        Ok(())
+    }
+
+    async fn delete_impl(&self, key: &str) -> Result<()> {
+        self.client
+            .delete_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .send()
+            .await
+            .map_err(|e| AegisError::Storage(format!("delete {}: {}", key, e)))?;
+        Ok(())
+    }
+
+    /// Deletes `keys` via `DeleteObjects`, chunking into requests of at
+    /// most `MAX_KEYS_PER_DELETE_BATCH` keys. A chunk whose request
+    /// fails outright counts every key in it as failed; a chunk that
+    /// succeeds but reports per-key errors in its response counts only
+    /// those keys as failed.
+    async fn delete_batch_impl(&self, keys: &[String]) -> Result<Vec<String>> {
+        let mut failed = Vec::new();
+        for chunk in keys.chunks(MAX_KEYS_PER_DELETE_BATCH) {
+            let objects = chunk
+                .iter()
+                .map(|key| aws_sdk_s3::types::ObjectIdentifier::builder().key(key.as_str()).build())
+                .collect::<std::result::Result<Vec<_>, _>>()
+                .map_err(|e| AegisError::Storage(format!("building delete_objects request: {}", e)))?;
+            let delete = aws_sdk_s3::types::Delete::builder()
+                .set_objects(Some(objects))
+                .build()
+                .map_err(|e| AegisError::Storage(format!("building delete_objects request: {}", e)))?;
+
+            match self.client.delete_objects().bucket(&self.bucket).delete(delete).send().await {
+                Ok(output) => failed.extend(output.errors().iter().filter_map(|e| e.key().map(String::from))),
+                Err(_) => failed.extend(chunk.iter().cloned()),
+            }
+        }
+        Ok(failed)
+    }
+
+    async fn list_impl(&self, prefix: &str) -> Result<Vec<ObjectMeta>> {
+        if self.read_client.is_some() {
+            if let Ok(objects) = self.list_via(self.preferred_read_client(), prefix).await {
+                return Ok(objects);
+            }
+        }
+        self.list_via(&self.client, prefix).await
+    }
+
+    async fn list_via(&self, client: &Client, prefix: &str) -> Result<Vec<ObjectMeta>> {
+        let output = client
+            .list_objects_v2()
+            .bucket(&self.bucket)
+            .prefix(prefix)
+            .send()
+            .await
+            .map_err(|e| AegisError::Storage(format!("list {}: {}", prefix, e)))?;
+
+        Ok(output
+            .contents()
+            .iter()
+            .map(|obj| ObjectMeta {
+                key: obj.key().unwrap_or_default().to_string(),
+                size: obj.size().unwrap_or(0) as u64,
+                etag: obj.e_tag().map(|s| s.trim_matches('"').to_string()),
+            })
+            .collect())
+    }
+
+    /// Lists only the direct children of `prefix` via `ListObjectsV2`'s
+    /// `delimiter` parameter: S3 groups everything past the next
+    /// `delimiter` into `CommonPrefixes` itself, so this costs one
+    /// request regardless of how many descendants sit deeper in the
+    /// tree.
+    async fn list_with_delimiter_impl(&self, prefix: &str, delimiter: &str) -> Result<DelimitedListing> {
+        if self.read_client.is_some() {
+            if let Ok(listing) = self.list_with_delimiter_via(self.preferred_read_client(), prefix, delimiter).await {
+                return Ok(listing);
+            }
+        }
+        self.list_with_delimiter_via(&self.client, prefix, delimiter).await
+    }
+
+    async fn list_with_delimiter_via(&self, client: &Client, prefix: &str, delimiter: &str) -> Result<DelimitedListing> {
+        let output = client
+            .list_objects_v2()
+            .bucket(&self.bucket)
+            .prefix(prefix)
+            .delimiter(delimiter)
+            .send()
+            .await
+            .map_err(|e| AegisError::Storage(format!("list_with_delimiter {}: {}", prefix, e)))?;
+
+        Ok(DelimitedListing {
+            objects: output
+                .contents()
+                .iter()
+                .map(|obj| ObjectMeta {
+                    key: obj.key().unwrap_or_default().to_string(),
+                    size: obj.size().unwrap_or(0) as u64,
+                    etag: obj.e_tag().map(|s| s.trim_matches('"').to_string()),
+                })
+                .collect(),
+            common_prefixes: output.common_prefixes().iter().filter_map(|p| p.prefix().map(String::from)).collect(),
+        })
+    }
+
+    async fn head_impl(&self, key: &str) -> Result<ObjectMeta> {
+        if self.read_client.is_some() {
+            if let Ok(meta) = self.head_via(self.preferred_read_client(), key).await {
+                return Ok(meta);
+            }
+        }
+        self.head_via(&self.client, key).await
+    }
+
+    async fn head_via(&self, client: &Client, key: &str) -> Result<ObjectMeta> {
+        let output = client
+            .head_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .send()
+            .await
+            .map_err(|_| AegisError::NotFound(key.to_string()))?;
+
+        Ok(ObjectMeta {
+            key: key.to_string(),
+            size: output.content_length().unwrap_or(0) as u64,
+            etag: output.e_tag().map(|s| s.trim_matches('"').to_string()),
+        })
+    }
+}
+
+#[async_trait]
+impl StorageBackend for S3Storage {
+    async fn get(&self, key: &str) -> Result<Vec<u8>> {
+        let _permit = self.acquire_request_permit().await;
+        let result = self.get_impl(key).await;
+        self.record(&result);
+        result
+    }
+
+    async fn put(&self, key: &str, data: Vec<u8>) -> Result<()> {
+        let _permit = self.acquire_request_permit().await;
+        let result = self.put_impl(key, data).await;
+        self.record(&result);
+        result
+    }
+
+    async fn delete(&self, key: &str) -> Result<()> {
+        let _permit = self.acquire_request_permit().await;
+        let result = self.delete_impl(key).await;
+        self.record(&result);
+        result
+    }
+
+    async fn delete_batch(&self, keys: &[String]) -> Result<Vec<String>> {
+        let _permit = self.acquire_request_permit().await;
+        let result = self.delete_batch_impl(keys).await;
+        self.record(&result);
+        result
+    }
+
+    async fn list(&self, prefix: &str) -> Result<Vec<ObjectMeta>> {
+        let _permit = self.acquire_request_permit().await;
+        let result = self.list_impl(prefix).await;
+        self.record(&result);
+        result
+    }
+
+    async fn list_with_delimiter(&self, prefix: &str, delimiter: &str) -> Result<DelimitedListing> {
+        let _permit = self.acquire_request_permit().await;
+        let result = self.list_with_delimiter_impl(prefix, delimiter).await;
+        self.record(&result);
+        result
+    }
+
+    async fn head(&self, key: &str) -> Result<ObjectMeta> {
+        let _permit = self.acquire_request_permit().await;
+        let result = self.head_impl(key).await;
+        self.record(&result);
+        result
+    }
+
+    /// Uses S3's real `If-None-Match` support: a 304 response means the
+    /// object is unchanged and we never pay for the body.
+    async fn head_if_none_match(&self, key: &str, etag: &str) -> Result<Option<ObjectMeta>> {
+        let _permit = self.acquire_request_permit().await;
+        let result = self
+            .client
+            .head_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .if_none_match(format!("\"{}\"", etag))
+            .send()
+            .await;
+
+        match result {
+            Ok(output) => Ok(Some(ObjectMeta {
+                key: key.to_string(),
+                size: output.content_length().unwrap_or(0) as u64,
+                etag: output.e_tag().map(|s| s.trim_matches('"').to_string()),
+            })),
+            // The SDK surfaces a 304 (not-modified) as a service error;
+            // treat it as "unchanged" rather than a real failure.
+            Err(e) if e.to_string().contains("304") => Ok(None),
+            Err(e) => Err(AegisError::Storage(format!("head_if_none_match {}: {}", key, e))),
+        }
+    }
+
+    /// Server-side `CopyObject`: the object is duplicated entirely
+    /// within S3, without the ciphertext passing through us.
+    async fn copy(&self, src: &str, dst: &str) -> Result<()> {
+        let copy_source = format!("{}/{}", self.bucket, src);
+        self.client
+            .copy_object()
+            .bucket(&self.bucket)
+            .key(dst)
+            .copy_source(&copy_source)
+            .send()
+            .await
+            .map_err(|e| AegisError::Storage(format!("copy {} -> {}: {}", src, dst, e)))?;
+        Ok(())
+    }
+
+    /// For objects already at least `MIN_MULTIPART_PART_SIZE`, appends
+    /// `tail` without retransmitting the existing bytes: a multipart
+    /// upload whose first part is an `UploadPartCopy` of the existing
+    /// object and whose second part is the new tail, completed into
+    /// the same key. Smaller or missing objects fall back to the
+    /// default full read-modify-write, since S3 won't accept a
+    /// non-final part under 5 MiB.
+    async fn append(&self, key: &str, tail: &[u8]) -> Result<()> {
+        let existing_size = match self.head(key).await {
+            Ok(meta) => meta.size,
+            Err(_) => 0,
+        };
+        if existing_size < MIN_MULTIPART_PART_SIZE {
+            return StorageBackend::append(self, key, tail).await;
+        }
+
+        let create = self
+            .client
+            .create_multipart_upload()
+            .bucket(&self.bucket)
+            .key(key)
+            .send()
+            .await
+            .map_err(|e| AegisError::Storage(format!("create_multipart_upload {}: {}", key, e)))?;
+        let upload_id = create
+            .upload_id()
+            .ok_or_else(|| AegisError::Storage("missing upload id".into()))?
+            .to_string();
+
+        let copy_source = format!("{}/{}", self.bucket, key);
+        let copied_part = self
+            .client
+            .upload_part_copy()
+            .bucket(&self.bucket)
+            .key(key)
+            .upload_id(&upload_id)
+            .part_number(1)
+            .copy_source(&copy_source)
+            .send()
+            .await
+            .map_err(|e| AegisError::Storage(format!("upload_part_copy {}: {}", key, e)))?;
+
+        self.upload_limiter.acquire(tail.len() as u64).await;
+        let tail_part = self
+            .client
+            .upload_part()
+            .bucket(&self.bucket)
+            .key(key)
+            .upload_id(&upload_id)
+            .part_number(2)
+            .body(tail.to_vec().into())
+            .send()
+            .await
+            .map_err(|e| AegisError::Storage(format!("upload_part {}: {}", key, e)))?;
+
+        let completed_parts = vec![
+            aws_sdk_s3::types::CompletedPart::builder()
+                .part_number(1)
+                .set_e_tag(copied_part.copy_part_result().and_then(|r| r.e_tag()).map(String::from))
+                .build(),
+            aws_sdk_s3::types::CompletedPart::builder()
+                .part_number(2)
+                .set_e_tag(tail_part.e_tag().map(String::from))
+                .build(),
+        ];
+
+        self.client
+            .complete_multipart_upload()
+            .bucket(&self.bucket)
+            .key(key)
+            .upload_id(&upload_id)
+            .multipart_upload(
+                aws_sdk_s3::types::CompletedMultipartUpload::builder()
+                    .set_parts(Some(completed_parts))
+                    .build(),
+            )
+            .send()
+            .await
+            .map_err(|e| AegisError::Storage(format!("complete_multipart_upload {}: {}", key, e)))?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tls_tests {
+    use super::*;
+
+    fn base_config() -> S3Config {
+        S3Config {
+            bucket: "test".into(),
+            region: "us-east-1".into(),
+            endpoint: None,
+            access_key: None,
+            secret_key: None,
+            read_endpoint: None,
+            restore_archived_objects: false,
+            profile: None,
+            role_arn: None,
+            ca_cert_path: None,
+            prefix: None,
+            danger_accept_invalid_certs: false,
+            max_upload_bytes_per_sec: None,
+            max_download_bytes_per_sec: None,
+            multipart_threshold_bytes: 16 * 1024 * 1024,
+            multipart_part_size_bytes: 8 * 1024 * 1024,
+            max_concurrent_requests: None,
+            max_idle_connections_per_host: None,
+                server_side_encryption: None,
+                sse_kms_key_id: None,
+                connect_timeout_secs: 10,
+                read_timeout_secs: 30,
+        }
+    }
+
+    #[test]
+    fn default_config_uses_system_trust_store() {
+        let config = base_config();
+        assert!(build_tls_config(&config).is_ok());
+    }
+
+    #[test]
+    fn danger_flag_installs_no_op_verifier() {
+        let mut config = base_config();
+        config.danger_accept_invalid_certs = true;
+        assert!(build_tls_config(&config).is_ok());
+    }
+
+    #[test]
+    fn missing_ca_cert_path_is_a_config_error() {
+        let mut config = base_config();
+        config.ca_cert_path = Some("/nonexistent/ca.pem".into());
+        assert!(build_tls_config(&config).is_err());
+    }
+}